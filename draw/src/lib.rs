@@ -1,5 +1,7 @@
 use nannou::prelude::*;
-use std::time::Instant;
+use std::{collections::HashSet, time::Instant};
+
+pub mod offscreen;
 
 pub const WINDOW_WIDTH: u32 = 800;
 pub const WINDOW_HEIGHT: u32 = 800;
@@ -11,58 +13,302 @@ fn calculate_single_grid_position(point: Point2, spacing: f32, center: Point2) -
     Point2::new(x, y)
 }
 
-pub fn draw_line(draw: &Draw, start: Point2, end: Point2, color: Rgba, weight: f32) {
+/// Zoom factor of one step of the mouse wheel
+const ZOOM_STEP: f32 = 1.1;
+
+/// Share of the window fit-to-content leaves around the shapes
+const FIT_MARGIN: f32 = 0.05;
+
+/// Zoom and pan of the viewer, the spacing and centre of the grid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub zoom: f32,
+    pub center: Point2,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            zoom: 1.0,
+            center: Point2::new(0.0, 0.0),
+        }
+    }
+}
+
+impl Camera {
+    /// Window position of a point of the plan
+    pub fn to_screen(&self, point: Point2) -> Point2 {
+        calculate_single_grid_position(point, self.zoom, self.center)
+    }
+
+    /// Point of the plan under a window position
+    pub fn to_plan(&self, screen: Point2) -> Point2 {
+        let window = Point2::new(WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32);
+        (screen - self.center + window / 2.0) / self.zoom
+    }
+
+    /// Zooms by `factor` keeping the point under `screen` in place
+    pub fn zoom_at(&mut self, factor: f32, screen: Point2) {
+        let anchor = self.to_plan(screen);
+        self.zoom *= factor;
+        self.center += screen - self.to_screen(anchor);
+    }
+
+    /// Shows all of the shapes in a window of `size`
+    pub fn fit(shapes: &[Shape], size: Vec2) -> Self {
+        let Some((min, max)) = bounds(shapes) else {
+            return Camera::default();
+        };
+
+        let extent = (max - min).max(Vec2::splat(1.0));
+        let zoom = (size * (1.0 - 2.0 * FIT_MARGIN) / extent).min_element();
+        let window = Point2::new(WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32);
+
+        Camera {
+            zoom,
+            center: window / 2.0 - (min + max) / 2.0 * zoom,
+        }
+    }
+}
+
+/// Corners of the box around the shapes, `None` if there are none
+fn bounds(shapes: &[Shape]) -> Option<(Point2, Point2)> {
+    shapes
+        .iter()
+        .flat_map(|shape| match shape {
+            Shape::Line { start, end, .. } => vec![*start, *end],
+            Shape::Rectangle {
+                position,
+                width,
+                height,
+                ..
+            } => {
+                let half = Vec2::new(*width, *height) / 2.0;
+                vec![*position - half, *position + half]
+            }
+            Shape::Circle {
+                position, radius, ..
+            } => vec![*position - *radius, *position + *radius],
+            Shape::Text { position, .. } | Shape::Point { position, .. } => vec![*position],
+        })
+        .fold(None, |bounds, p| match bounds {
+            None => Some((p, p)),
+            Some((min, max)) => Some((p.min(min), p.max(max))),
+        })
+}
+
+pub fn draw_line(
+    draw: &Draw,
+    camera: &Camera,
+    start: Point2,
+    end: Point2,
+    color: Rgba,
+    weight: f32,
+) {
     draw.line()
-        .start(calculate_single_grid_position(
-            start,
-            1.0,
-            Point2::new(0.0, 0.0),
-        ))
-        .end(calculate_single_grid_position(
-            end,
-            1.0,
-            Point2::new(0.0, 0.0),
-        ))
+        .start(camera.to_screen(start))
+        .end(camera.to_screen(end))
         .color(color)
-        .weight(weight);
+        .weight(weight * camera.zoom);
 }
 
-pub fn draw_rectangle(draw: &Draw, center: Point2, width: f32, height: f32, color: Rgba) {
-    let grid_position = calculate_single_grid_position(center, 1.0, Point2::new(0.0, 0.0));
+pub fn draw_rectangle(
+    draw: &Draw,
+    camera: &Camera,
+    center: Point2,
+    width: f32,
+    height: f32,
+    color: Rgba,
+) {
+    let grid_position = camera.to_screen(center);
     draw.rect()
         .x_y(grid_position.x, grid_position.y)
-        .w_h(width, height)
+        .w_h(width * camera.zoom, height * camera.zoom)
         .color(color);
 }
 
-pub fn draw_circle(draw: &Draw, center: Point2, radius: f32, color: Rgba) {
-    let grid_position = calculate_single_grid_position(center, 1.0, Point2::new(0.0, 0.0));
+pub fn draw_circle(draw: &Draw, camera: &Camera, center: Point2, radius: f32, color: Rgba) {
+    let grid_position = camera.to_screen(center);
     draw.ellipse()
         .x_y(grid_position.x, grid_position.y)
-        .radius(radius)
+        .radius(radius * camera.zoom)
         .color(color);
 }
 
-pub fn draw_text(draw: &Draw, text: &str, position: Point2, font_size: u32, color: Rgba) {
-    let grid_position = calculate_single_grid_position(position, 1.0, Point2::new(0.0, 0.0));
+pub fn draw_text(
+    draw: &Draw,
+    camera: &Camera,
+    text: &str,
+    position: Point2,
+    font_size: u32,
+    color: Rgba,
+) {
+    let grid_position = camera.to_screen(position);
     draw.text(text)
         .x_y(grid_position.x, grid_position.y)
         .font_size(font_size)
         .color(color);
 }
 
-pub fn draw_point(draw: &Draw, position: Point2, color: Rgba) {
-    let grid_position = calculate_single_grid_position(position, 1.0, Point2::new(0.0, 0.0));
+pub fn draw_point(draw: &Draw, camera: &Camera, position: Point2, color: Rgba) {
+    let grid_position = camera.to_screen(position);
     draw.ellipse()
         .x_y(grid_position.x, grid_position.y)
-        .radius(1.0)
+        .radius(camera.zoom.max(1.0))
         .color(color);
 }
 
 pub struct Model {
     last_update: Instant,
-    shapes: Vec<Shape>,
+    stages: Vec<Stage>,
+    /// Index into `stages` of the one shown
+    stage: usize,
+    /// Whether the stages are stepped through every `render_interval` seconds
+    playing: bool,
     render_interval: f64,
+    camera: Camera,
+    /// Last mouse position while dragging with the left button
+    drag: Option<Point2>,
+    /// Mouse position the left button went down at
+    press: Option<Point2>,
+    mouse: Point2,
+    /// Source of the shapes under the mouse
+    hovered: Option<usize>,
+    /// Source of the shapes clicked last
+    selected: Option<usize>,
+    backgrounds: Vec<(String, wgpu::Texture)>,
+    /// Index into `backgrounds` of the one shown
+    background: Option<usize>,
+    hidden: HashSet<Layer>,
+    legend: bool,
+    document: Option<Box<dyn Document>>,
+    /// The document as it is now, shown instead of the stages while editing
+    edited: Option<Stage>,
+    /// Source of the shapes dragged while editing
+    moving: Option<usize>,
+    prompt: Option<Prompt>,
+    /// Outcome of the last edit, shown at the bottom
+    status: String,
+}
+
+/// Change to the edited [`Document`], shapes are named by their [`Meta::source`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    AddNode(Point2),
+    Delete(usize),
+    Move(usize, Point2),
+    Connect(usize, usize),
+    Disconnect(usize, usize),
+    /// Says what a symbol is, an empty kind makes it unrecognised again
+    SetKind(usize, String),
+    /// An empty label removes it
+    SetLabel(usize, String),
+}
+
+/// What the viewer corrects in edit mode
+pub trait Document {
+    /// Shapes and their descriptions as the document is now
+    fn stage(&self) -> Stage;
+    fn label(&self, source: usize) -> Option<String>;
+    /// Applies the edit, or tells why it can't be applied
+    fn apply(&mut self, edit: Edit) -> Result<(), String>;
+    /// Saves the document, telling where
+    fn save(&self) -> Result<String, String>;
+}
+
+/// Text being typed for an edit of a shape
+struct Prompt {
+    title: &'static str,
+    source: usize,
+    text: String,
+    edit: fn(usize, String) -> Edit,
+}
+
+/// Image shown under the shapes, pixel `(x, y)` lies at `(x, height - y)` of
+/// the plan like the pixels the shapes were detected from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Background {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// Row by row from the top, transparent where nothing should cover the shapes
+    pub rgba: Vec<u8>,
+}
+
+/// Opacity backgrounds are shown with
+const BACKGROUND_OPACITY: f32 = 0.5;
+
+/// What a drawn shape belongs to
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Meta {
+    /// Index of the detected shape it is drawn for, shared by every shape
+    /// drawn for the same one
+    pub source: Option<usize>,
+    pub layer: Layer,
+}
+
+/// Group of shapes the viewer shows or hides together
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Layer {
+    #[default]
+    Lines,
+    Nodes,
+    Pinpoints,
+    Symbols,
+    Labels,
+    Unresolved,
+}
+
+impl Layer {
+    pub const ALL: [Layer; 6] = [
+        Layer::Lines,
+        Layer::Nodes,
+        Layer::Pinpoints,
+        Layer::Symbols,
+        Layer::Labels,
+        Layer::Unresolved,
+    ];
+
+    /// Toggles the layer, the number of the layer in [`Layer::ALL`]
+    pub fn key(&self) -> Key {
+        match self {
+            Layer::Lines => Key::Key1,
+            Layer::Nodes => Key::Key2,
+            Layer::Pinpoints => Key::Key3,
+            Layer::Symbols => Key::Key4,
+            Layer::Labels => Key::Key5,
+            Layer::Unresolved => Key::Key6,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Layer::Lines => "tracks, in the colour of their ink",
+            Layer::Nodes => "junctions and ends of tracks",
+            Layer::Pinpoints => "nodes where two track ends meet",
+            Layer::Symbols => "circles and boxes",
+            Layer::Labels => "text",
+            Layer::Unresolved => "ink no shape was found for",
+        }
+    }
+}
+
+/// Shapes as a stage of the detection left them
+#[derive(Clone, Default)]
+pub struct Stage {
+    pub name: String,
+    pub shapes: Vec<Shape>,
+    /// Descriptions of the shapes, indexed by [`Meta::source`], empty if the
+    /// stage has none
+    pub infos: Vec<ShapeInfo>,
+}
+
+/// Description of a detected shape, shown by the inspector
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShapeInfo {
+    pub kind: String,
+    /// Name and value, in the order shown
+    pub properties: Vec<(String, String)>,
 }
 
 #[derive(Clone)]
@@ -72,89 +318,691 @@ pub enum Shape {
         end: Point2,
         color: Rgba,
         weight: f32,
+        meta: Meta,
     },
     Rectangle {
         position: Point2,
         width: f32,
         height: f32,
         color: Rgba,
+        meta: Meta,
     },
     Circle {
         position: Point2,
         radius: f32,
         color: Rgba,
+        meta: Meta,
     },
     Text {
         content: String,
         position: Point2,
         font_size: u32,
         color: Rgba,
+        meta: Meta,
     },
     Point {
         position: Point2,
         color: Rgba,
+        meta: Meta,
     },
 }
 
-pub fn model(app: &App, shapes: Vec<Shape>, render_interval: f64) -> Model {
-    Model {
-        last_update: Instant::now(),
-        shapes,
-        render_interval,
-    }
+/// Shapes closer to the mouse than this many window pixels are hovered
+const HOVER_DISTANCE: f32 = 6.0;
+
+/// Width of the inspector panel in window pixels
+const PANEL_WIDTH: f32 = 320.0;
+
+const PANEL_LINE_HEIGHT: f32 = 16.0;
+
+/// Width of the legend in window pixels
+const LEGEND_WIDTH: f32 = 420.0;
+
+fn distance_to_segment(p: Point2, a: Point2, b: Point2) -> f32 {
+    let ab = b - a;
+    let t = match ab.length_squared() {
+        l if l > 0.0 => ((p - a).dot(ab) / l).clamp(0.0, 1.0),
+        _ => 0.0,
+    };
+    p.distance(a + ab * t)
 }
 
-pub fn view(app: &App, model: &Model, frame: Frame) {
-    let draw = app.draw();
+impl Shape {
+    pub fn color(&self) -> Rgba {
+        match self {
+            Shape::Line { color, .. }
+            | Shape::Rectangle { color, .. }
+            | Shape::Circle { color, .. }
+            | Shape::Text { color, .. }
+            | Shape::Point { color, .. } => *color,
+        }
+    }
 
-    draw.background().color(WHITE);
+    pub fn meta(&self) -> &Meta {
+        match self {
+            Shape::Line { meta, .. }
+            | Shape::Rectangle { meta, .. }
+            | Shape::Circle { meta, .. }
+            | Shape::Text { meta, .. }
+            | Shape::Point { meta, .. } => meta,
+        }
+    }
 
-    for shape in &model.shapes {
-        match shape {
-            Shape::Line {
-                start,
-                end,
-                color,
-                weight,
-            } => {
-                draw_line(&draw, *start, *end, *color, *weight);
-            }
+    /// The same shape in another colour
+    pub fn with_color(&self, new: Rgba) -> Shape {
+        let mut shape = self.clone();
+        match &mut shape {
+            Shape::Line { color, .. }
+            | Shape::Rectangle { color, .. }
+            | Shape::Circle { color, .. }
+            | Shape::Text { color, .. }
+            | Shape::Point { color, .. } => *color = new,
+        }
+        shape
+    }
+
+    /// Distance of a point of the plan to the shape, 0 inside filled shapes
+    pub fn distance(&self, p: Point2) -> f32 {
+        match self {
+            Shape::Line { start, end, .. } => distance_to_segment(p, *start, *end),
             Shape::Rectangle {
                 position,
                 width,
                 height,
-                color,
+                ..
             } => {
-                draw_rectangle(&draw, *position, *width, *height, *color);
+                let outside = (p - *position).abs() - Vec2::new(*width, *height) / 2.0;
+                outside.max(Vec2::ZERO).length()
             }
             Shape::Circle {
-                position,
-                radius,
-                color,
-            } => {
-                draw_circle(&draw, *position, *radius, *color);
+                position, radius, ..
+            } => (p.distance(*position) - radius).max(0.0),
+            Shape::Text { position, .. } | Shape::Point { position, .. } => p.distance(*position),
+        }
+    }
+}
+
+pub fn draw_shape(draw: &Draw, camera: &Camera, shape: &Shape) {
+    match shape {
+        Shape::Line {
+            start,
+            end,
+            color,
+            weight,
+            ..
+        } => {
+            draw_line(draw, camera, *start, *end, *color, *weight);
+        }
+        Shape::Rectangle {
+            position,
+            width,
+            height,
+            color,
+            ..
+        } => {
+            draw_rectangle(draw, camera, *position, *width, *height, *color);
+        }
+        Shape::Circle {
+            position,
+            radius,
+            color,
+            ..
+        } => {
+            draw_circle(draw, camera, *position, *radius, *color);
+        }
+        Shape::Text {
+            content,
+            position,
+            font_size,
+            color,
+            ..
+        } => {
+            draw_text(draw, camera, content, *position, *font_size, *color);
+        }
+        Shape::Point {
+            position, color, ..
+        } => {
+            draw_point(draw, camera, *position, *color);
+        }
+    }
+}
+
+/// Viewer stepping through the stages from the first one, every
+/// `render_interval` seconds, and stopping at the last. The document, if
+/// any, can be corrected in edit mode.
+pub fn model(
+    app: &App,
+    stages: Vec<Stage>,
+    backgrounds: Vec<Background>,
+    render_interval: f64,
+    document: Option<Box<dyn Document>>,
+) -> Model {
+    let window = Vec2::new(WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32);
+
+    let backgrounds = backgrounds
+        .into_iter()
+        .filter_map(|background| {
+            let mut rgba = background.rgba;
+            for pixel in rgba.chunks_mut(4) {
+                pixel[3] = (pixel[3] as f32 * BACKGROUND_OPACITY) as u8;
             }
-            Shape::Text {
-                content,
-                position,
-                font_size,
-                color,
-            } => {
-                draw_text(&draw, content, *position, *font_size, *color);
+
+            let image =
+                nannou::image::RgbaImage::from_raw(background.width, background.height, rgba)?;
+            let texture =
+                wgpu::Texture::from_image(app, &nannou::image::DynamicImage::ImageRgba8(image));
+            Some((background.name, texture))
+        })
+        .collect();
+
+    Model {
+        last_update: Instant::now(),
+        camera: Camera::fit(
+            stages.last().map(|s| &s.shapes[..]).unwrap_or_default(),
+            window,
+        ),
+        stages,
+        stage: 0,
+        playing: true,
+        render_interval,
+        drag: None,
+        press: None,
+        mouse: Point2::ZERO,
+        hovered: None,
+        selected: None,
+        backgrounds,
+        background: None,
+        hidden: HashSet::new(),
+        legend: true,
+        document,
+        edited: None,
+        moving: None,
+        prompt: None,
+        status: String::new(),
+    }
+}
+
+impl Model {
+    fn shapes(&self) -> &[Shape] {
+        self.edited
+            .as_ref()
+            .or(self.stages.get(self.stage))
+            .map(|s| &s.shapes[..])
+            .unwrap_or_default()
+    }
+
+    fn infos(&self) -> &[ShapeInfo] {
+        self.edited
+            .as_ref()
+            .or(self.stages.get(self.stage))
+            .map(|s| &s.infos[..])
+            .unwrap_or_default()
+    }
+
+    /// Shows another stage, the sources of the last one mean nothing there
+    fn show_stage(&mut self, stage: usize) {
+        self.stage = stage.min(self.stages.len().saturating_sub(1));
+        self.hovered = None;
+        self.selected = None;
+    }
+
+    /// Enters or leaves edit mode, showing the document as it is now
+    fn toggle_editing(&mut self) {
+        self.edited = match (&self.edited, &self.document) {
+            (None, Some(document)) => Some(document.stage()),
+            (None, None) => {
+                self.status = "there is nothing to edit".to_string();
+                None
+            }
+            (Some(_), _) => None,
+        };
+        self.playing = false;
+        self.moving = None;
+        self.hovered = None;
+        self.selected = None;
+    }
+
+    /// Applies the edit to the document and shows the outcome
+    fn apply(&mut self, edit: Edit) -> bool {
+        let Some(document) = &mut self.document else {
+            return false;
+        };
+
+        match document.apply(edit) {
+            Ok(()) => {
+                self.edited = Some(document.stage());
+                self.status.clear();
+                true
             }
-            Shape::Point { position, color } => {
-                draw_point(&draw, *position, *color);
+            Err(error) => {
+                self.status = error;
+                false
             }
         }
     }
 
+    /// Starts typing the text of an edit of the selected shape
+    fn open_prompt(
+        &mut self,
+        app: &App,
+        title: &'static str,
+        text: Option<String>,
+        edit: fn(usize, String) -> Edit,
+    ) {
+        let Some(source) = self.selected else {
+            self.status = "select a shape first".to_string();
+            return;
+        };
+
+        // Escape cancels the prompt instead of closing the viewer
+        app.set_exit_on_escape(false);
+        self.prompt = Some(Prompt {
+            title,
+            source,
+            text: text.unwrap_or_default(),
+            edit,
+        });
+    }
+
+    fn close_prompt(&mut self, app: &App) {
+        app.set_exit_on_escape(true);
+        self.prompt = None;
+    }
+
+    /// The prompt, or the outcome of the last edit, at the bottom of the window
+    fn draw_status(&self, app: &App, draw: &Draw) {
+        let text = match (&self.prompt, self.edited.is_some()) {
+            (Some(prompt), _) => format!("{}: {}_", prompt.title, prompt.text),
+            (None, true) if self.status.is_empty() => "edit mode".to_string(),
+            (None, true) => format!("edit mode: {}", self.status),
+            (None, false) => self.status.clone(),
+        };
+
+        let window = app.window_rect();
+        draw.text(&text)
+            .x_y(window.x(), window.bottom() + 2.0 * PANEL_LINE_HEIGHT)
+            .w_h(
+                window.w() - 2.0 * LEGEND_WIDTH.min(window.w() / 4.0),
+                PANEL_LINE_HEIGHT,
+            )
+            .font_size(12)
+            .color(BLACK);
+    }
+
+    /// Name of the stage and how far along it is, in the top right corner
+    fn draw_stage(&self, app: &App, draw: &Draw) {
+        let Some(stage) = self.edited.as_ref().or(self.stages.get(self.stage)) else {
+            return;
+        };
+
+        let progress = match self.edited {
+            Some(_) => "editing".to_string(),
+            None => format!("stage {}/{}", self.stage + 1, self.stages.len()),
+        };
+
+        let window = app.window_rect();
+        draw.text(&format!("{}: {}", progress, stage.name))
+            .x_y(
+                window.right() - PANEL_WIDTH / 2.0,
+                window.top() - PANEL_LINE_HEIGHT,
+            )
+            .w_h(PANEL_WIDTH - PANEL_LINE_HEIGHT, PANEL_LINE_HEIGHT)
+            .right_justify()
+            .font_size(12)
+            .color(BLACK);
+    }
+
+    fn draw_background(&self, app: &App, draw: &Draw) {
+        let Some((name, texture)) = self.background.and_then(|i| self.backgrounds.get(i)) else {
+            return;
+        };
+
+        let [width, height] = texture.size();
+        let size = Vec2::new(width as f32, height as f32);
+        // Pixels are centred on their coordinates
+        let center = size / 2.0 + Vec2::new(-0.5, 0.5);
+
+        draw.texture(texture)
+            .xy(self.camera.to_screen(center))
+            .wh(size * self.camera.zoom);
+
+        let window = app.window_rect();
+        draw.text(&format!("background: {}", name))
+            .x_y(window.left() + 100.0, window.bottom() + 20.0)
+            .w_h(180.0, 20.0)
+            .left_justify()
+            .font_size(12)
+            .color(BLACK);
+    }
+
+    fn visible_shapes(&self) -> impl Iterator<Item = &Shape> {
+        self.shapes()
+            .iter()
+            .filter(|s| !self.hidden.contains(&s.meta().layer))
+    }
+
+    /// Every layer with shapes, its key, colour and whether it is shown, in
+    /// the bottom right corner
+    fn draw_legend(&self, app: &App, draw: &Draw) {
+        if !self.legend {
+            return;
+        }
+
+        // Numbered like the keys toggling them
+        let layers: Vec<_> = Layer::ALL
+            .iter()
+            .enumerate()
+            .filter_map(|(i, layer)| {
+                let mut shapes = self.shapes().iter().filter(|s| s.meta().layer == *layer);
+                let color = shapes.next()?.color();
+                Some((i + 1, layer, color, shapes.count() + 1))
+            })
+            .collect();
+
+        let window = app.window_rect();
+        let size = Vec2::new(
+            LEGEND_WIDTH,
+            layers.len() as f32 * PANEL_LINE_HEIGHT + PANEL_LINE_HEIGHT,
+        );
+        let corner = Point2::new(window.right() - size.x, window.bottom());
+
+        draw.rect()
+            .xy(corner + size / 2.0)
+            .wh(size)
+            .color(rgba(1.0, 1.0, 1.0, 0.9))
+            .stroke(BLACK)
+            .stroke_weight(1.0);
+
+        for (row, (number, layer, color, count)) in layers.iter().enumerate() {
+            let y = corner.y + size.y - PANEL_LINE_HEIGHT * (row as f32 + 1.0);
+            let shown = !self.hidden.contains(layer);
+
+            let swatch = match shown {
+                true => *color,
+                false => rgba(color.red, color.green, color.blue, 0.2),
+            };
+            draw.rect()
+                .x_y(corner.x + PANEL_LINE_HEIGHT, y)
+                .w_h(PANEL_LINE_HEIGHT / 2.0, PANEL_LINE_HEIGHT / 2.0)
+                .color(swatch);
+
+            let text = format!(
+                "{} {:?} ({}): {}{}",
+                number,
+                layer,
+                count,
+                layer.description(),
+                if shown { "" } else { ", hidden" }
+            );
+            let width = size.x - 2.0 * PANEL_LINE_HEIGHT;
+            draw.text(&text)
+                .x_y(corner.x + 1.5 * PANEL_LINE_HEIGHT + width / 2.0, y)
+                .w_h(width, PANEL_LINE_HEIGHT)
+                .left_justify()
+                .font_size(12)
+                .color(BLACK);
+        }
+    }
+
+    /// Source of the shape closest to the window position, if any is close enough
+    fn pick(&self, screen: Point2) -> Option<usize> {
+        let p = self.camera.to_plan(screen);
+        let reach = HOVER_DISTANCE / self.camera.zoom;
+
+        self.visible_shapes()
+            .filter_map(|s| Some((s.meta().source?, s.distance(p))))
+            .filter(|(_, distance)| *distance <= reach)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(source, _)| source)
+    }
+
+    /// Draws the shapes of the source again on top in `color`
+    fn highlight(&self, draw: &Draw, source: usize, color: Rgba) {
+        for shape in self.visible_shapes() {
+            if shape.meta().source == Some(source) {
+                draw_shape(draw, &self.camera, &shape.with_color(color));
+            }
+        }
+    }
+
+    /// Properties of the selected shape, or the hovered one, in the top left corner
+    fn draw_inspector(&self, app: &App, draw: &Draw) {
+        let Some(info) = self
+            .selected
+            .or(self.hovered)
+            .and_then(|source| self.infos().get(source))
+        else {
+            return;
+        };
+
+        let text = std::iter::once(info.kind.clone())
+            .chain(
+                info.properties
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, value)),
+            )
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let window = app.window_rect();
+        let size = Vec2::new(
+            PANEL_WIDTH,
+            (info.properties.len() + 1) as f32 * PANEL_LINE_HEIGHT + PANEL_LINE_HEIGHT,
+        );
+        let center = Point2::new(window.left(), window.top()) + Vec2::new(size.x, -size.y) / 2.0;
+
+        draw.rect()
+            .xy(center)
+            .wh(size)
+            .color(rgba(1.0, 1.0, 1.0, 0.9))
+            .stroke(BLACK)
+            .stroke_weight(1.0);
+        draw.text(&text)
+            .xy(center)
+            .wh(size - Vec2::splat(PANEL_LINE_HEIGHT / 2.0))
+            .font_size(12)
+            .left_justify()
+            .align_text_top()
+            .color(BLACK);
+    }
+}
+
+pub fn view(app: &App, model: &Model, frame: Frame) {
+    let draw = app.draw();
+
+    draw.background().color(WHITE);
+    model.draw_background(app, &draw);
+
+    for shape in model.visible_shapes() {
+        draw_shape(&draw, &model.camera, shape);
+    }
+
+    if let Some(source) = model.hovered {
+        model.highlight(&draw, source, rgba(1.0, 0.6, 0.0, 1.0));
+    }
+    if let Some(source) = model.selected {
+        model.highlight(&draw, source, rgba(1.0, 0.0, 1.0, 1.0));
+    }
+    model.draw_inspector(app, &draw);
+    model.draw_legend(app, &draw);
+    model.draw_stage(app, &draw);
+    model.draw_status(app, &draw);
+
     draw.to_frame(app, &frame).unwrap();
 }
 
+/// Steps to the next stage every `render_interval` seconds while playing
 pub fn update(_app: &App, model: &mut Model, _update: Update) {
     let now = Instant::now();
-    if now.duration_since(model.last_update).as_secs_f64() >= model.render_interval {
-        model.shapes.extend(model.shapes.clone());
-        model.last_update = now;
+    if !model.playing || now.duration_since(model.last_update).as_secs_f64() < model.render_interval
+    {
+        return;
+    }
+
+    model.last_update = now;
+    match model.stage + 1 < model.stages.len() {
+        true => model.show_stage(model.stage + 1),
+        false => model.playing = false,
+    }
+}
+
+/// Mouse wheel zooms at the cursor, dragging with the left button pans,
+/// `F` fits the shapes into the window and `R` resets to the unzoomed view.
+/// Hovering a shape shows its properties, clicking keeps them until a click
+/// next to every shape. `B` cycles through the backgrounds and none, the
+/// number keys of the [`Layer`]s hide and show them and `L` the legend.
+/// The arrow keys step through the stages, `Space` plays them from the first
+/// one or pauses.
+///
+/// `E` enters and leaves edit mode, where dragging a node moves it, `N` adds
+/// a node at the cursor, `Delete` deletes the selected shape, `C` and `D`
+/// connect and disconnect the selected node and the hovered one, `K` types
+/// the kind of the selected symbol, `T` the label of the selected shape and
+/// `S` saves the document.
+pub fn event(app: &App, model: &mut Model, event: Event) {
+    let Event::WindowEvent {
+        simple: Some(event),
+        ..
+    } = event
+    else {
+        return;
+    };
+
+    if let Some(prompt) = &mut model.prompt {
+        match event {
+            WindowEvent::ReceivedCharacter(c) if !c.is_control() => prompt.text.push(c),
+            WindowEvent::KeyPressed(Key::Back) => {
+                prompt.text.pop();
+            }
+            WindowEvent::KeyPressed(Key::Return) => {
+                let edit = (prompt.edit)(prompt.source, prompt.text.clone());
+                model.close_prompt(app);
+                model.apply(edit);
+            }
+            WindowEvent::KeyPressed(Key::Escape) => model.close_prompt(app),
+            _ => {}
+        }
+        return;
+    }
+
+    let editing = model.edited.is_some();
+
+    match event {
+        WindowEvent::MouseWheel(delta, _) => {
+            let steps = match delta {
+                MouseScrollDelta::LineDelta(_, y) => y,
+                MouseScrollDelta::PixelDelta(p) => p.y as f32 / 20.0,
+            };
+            model.camera.zoom_at(ZOOM_STEP.powf(steps), model.mouse);
+        }
+        WindowEvent::MousePressed(MouseButton::Left) => {
+            model.press = Some(model.mouse);
+            match model.pick(model.mouse).filter(|_| editing) {
+                Some(source) => model.moving = Some(source),
+                None => model.drag = Some(model.mouse),
+            }
+        }
+        WindowEvent::MouseReleased(MouseButton::Left) => {
+            // A click rather than the end of a drag
+            if model.press == Some(model.mouse) {
+                model.selected = model.pick(model.mouse);
+            }
+            model.drag = None;
+            model.press = None;
+            model.moving = None;
+        }
+        WindowEvent::MouseMoved(position) => {
+            match (model.drag, model.moving) {
+                (Some(last), _) => {
+                    model.camera.center += position - last;
+                    model.drag = Some(position);
+                }
+                (None, Some(source)) => {
+                    if !model.apply(Edit::Move(source, model.camera.to_plan(position))) {
+                        model.moving = None;
+                    }
+                }
+                (None, None) => model.hovered = model.pick(position),
+            }
+            model.mouse = position;
+        }
+        WindowEvent::KeyPressed(Key::F) => {
+            model.camera = Camera::fit(model.shapes(), app.window_rect().wh());
+        }
+        WindowEvent::KeyPressed(Key::R) => model.camera = Camera::default(),
+        WindowEvent::KeyPressed(Key::L) => model.legend = !model.legend,
+        WindowEvent::KeyPressed(Key::E) => model.toggle_editing(),
+        WindowEvent::KeyPressed(Key::N) if editing => {
+            model.apply(Edit::AddNode(model.camera.to_plan(model.mouse)));
+        }
+        WindowEvent::KeyPressed(Key::Delete | Key::Back) if editing => {
+            if let Some(source) = model.selected {
+                // Sources after the deleted shape change
+                if model.apply(Edit::Delete(source)) {
+                    model.selected = None;
+                    model.hovered = None;
+                }
+            }
+        }
+        WindowEvent::KeyPressed(key @ (Key::C | Key::D)) if editing => {
+            match (model.selected, model.hovered) {
+                (Some(a), Some(b)) if key == Key::C => {
+                    model.apply(Edit::Connect(a, b));
+                }
+                (Some(a), Some(b)) => {
+                    model.apply(Edit::Disconnect(a, b));
+                }
+                _ => model.status = "select a node and hover another one".to_string(),
+            }
+        }
+        // On release, or the character of the key would be typed into the prompt
+        WindowEvent::KeyReleased(Key::K) if editing => {
+            model.open_prompt(app, "kind", None, Edit::SetKind);
+        }
+        WindowEvent::KeyReleased(Key::T) if editing => {
+            let label = model
+                .selected
+                .zip(model.document.as_ref())
+                .and_then(|(source, document)| document.label(source));
+            model.open_prompt(app, "label", label, Edit::SetLabel);
+        }
+        WindowEvent::KeyPressed(Key::S) if editing => {
+            if let Some(document) = &model.document {
+                model.status = document.save().unwrap_or_else(|error| error);
+            }
+        }
+        WindowEvent::KeyPressed(Key::Left) if !editing => {
+            model.playing = false;
+            model.show_stage(model.stage.saturating_sub(1));
+        }
+        WindowEvent::KeyPressed(Key::Right) if !editing => {
+            model.playing = false;
+            model.show_stage(model.stage + 1);
+        }
+        WindowEvent::KeyPressed(Key::Space) if !editing => {
+            model.playing = !model.playing;
+            if model.playing && model.stage + 1 >= model.stages.len() {
+                model.show_stage(0);
+            }
+            model.last_update = Instant::now();
+        }
+        WindowEvent::KeyPressed(Key::B) => {
+            model.background = match model.background {
+                None if !model.backgrounds.is_empty() => Some(0),
+                Some(i) if i + 1 < model.backgrounds.len() => Some(i + 1),
+                _ => None,
+            };
+        }
+        WindowEvent::KeyPressed(key) => {
+            if let Some(layer) = Layer::ALL.into_iter().find(|l| l.key() == key) {
+                if !model.hidden.remove(&layer) {
+                    model.hidden.insert(layer);
+                }
+                // Hidden shapes can't stay selected
+                model.hovered = None;
+                model.selected = None;
+            }
+        }
+        _ => {}
     }
 }
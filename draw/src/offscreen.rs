@@ -0,0 +1,253 @@
+use std::{fmt::Write, path::Path, sync::Arc};
+
+use anyhow::Context;
+use nannou::prelude::*;
+use resvg::{tiny_skia, usvg};
+
+use crate::{Camera, Shape};
+
+/// Family the text is set in, bundled so that machines without fonts render
+/// the same
+const FONT_FAMILY: &str = "Noto Sans";
+
+/// Paint attributes, e.g. `fill="rgb(0,0,255)" fill-opacity="1"`
+fn paint(attribute: &str, color: &Rgba) -> String {
+    format!(
+        "{0}=\"rgb({1},{2},{3})\" {0}-opacity=\"{4}\"",
+        attribute,
+        (color.red * 255.0).round(),
+        (color.green * 255.0).round(),
+        (color.blue * 255.0).round(),
+        color.alpha
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The shapes as an SVG of `width` × `height` pixels, drawn like the viewer
+/// draws them after fitting them into its window
+fn to_svg(shapes: &[Shape], width: u32, height: u32) -> String {
+    let size = Vec2::new(width as f32, height as f32);
+    let camera = Camera::fit(shapes, size);
+    // Window positions have their origin in the centre and y up
+    let pixel = |p: Point2| {
+        let screen = camera.to_screen(p);
+        Vec2::new(size.x / 2.0 + screen.x, size.y / 2.0 - screen.y)
+    };
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\
+         <rect width=\"{0}\" height=\"{1}\" fill=\"white\"/>",
+        width, height
+    );
+
+    for shape in shapes {
+        // Writing to a string can't fail
+        let _ = match shape {
+            Shape::Line {
+                start,
+                end,
+                color,
+                weight,
+                ..
+            } => {
+                let (a, b) = (pixel(*start), pixel(*end));
+                write!(
+                    svg,
+                    "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" {} stroke-width=\"{}\"/>",
+                    a.x,
+                    a.y,
+                    b.x,
+                    b.y,
+                    paint("stroke", color),
+                    weight * camera.zoom
+                )
+            }
+            Shape::Rectangle {
+                position,
+                width,
+                height,
+                color,
+                ..
+            } => {
+                let (w, h) = (width * camera.zoom, height * camera.zoom);
+                let center = pixel(*position);
+                write!(
+                    svg,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" {}/>",
+                    center.x - w / 2.0,
+                    center.y - h / 2.0,
+                    w,
+                    h,
+                    paint("fill", color)
+                )
+            }
+            Shape::Circle {
+                position,
+                radius,
+                color,
+                ..
+            } => {
+                let center = pixel(*position);
+                write!(
+                    svg,
+                    "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" {}/>",
+                    center.x,
+                    center.y,
+                    radius * camera.zoom,
+                    paint("fill", color)
+                )
+            }
+            Shape::Point {
+                position, color, ..
+            } => {
+                let center = pixel(*position);
+                write!(
+                    svg,
+                    "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" {}/>",
+                    center.x,
+                    center.y,
+                    camera.zoom.max(1.0),
+                    paint("fill", color)
+                )
+            }
+            Shape::Text {
+                content,
+                position,
+                font_size,
+                color,
+                ..
+            } => {
+                let center = pixel(*position);
+                write!(
+                    svg,
+                    "<text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{}\" \
+                     text-anchor=\"middle\" dominant-baseline=\"central\" {}>{}</text>",
+                    center.x,
+                    center.y,
+                    FONT_FAMILY,
+                    font_size,
+                    paint("fill", color),
+                    escape(content)
+                )
+            }
+        };
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Renders the shapes without a window into an image of `width` × `height`
+/// pixels, fitted and drawn like the viewer shows them on a white background
+pub fn render(shapes: &[Shape], width: u32, height: u32) -> anyhow::Result<tiny_skia::Pixmap> {
+    let mut fonts = usvg::fontdb::Database::new();
+    fonts.load_font_data(notosans::REGULAR_TTF.to_vec());
+
+    let options = usvg::Options {
+        fontdb: Arc::new(fonts),
+        ..Default::default()
+    };
+    let tree = usvg::Tree::from_str(&to_svg(shapes, width, height), &options)
+        .context("failed to build the rendering")?;
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .with_context(|| format!("can't render an image of {} x {} pixels", width, height))?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+
+    Ok(pixmap)
+}
+
+/// [`render`] saved as PNG
+pub fn save_png(
+    shapes: &[Shape],
+    width: u32,
+    height: u32,
+    path: impl AsRef<Path>,
+) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    render(shapes, width, height)?
+        .save_png(path)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Meta;
+
+    fn pixel(pixmap: &tiny_skia::Pixmap, x: u32, y: u32) -> [u8; 3] {
+        let p = pixmap.pixel(x, y).unwrap();
+        [p.red(), p.green(), p.blue()]
+    }
+
+    #[test]
+    fn lines_and_circles_are_drawn_where_they_are_fitted() {
+        let shapes = [
+            Shape::Line {
+                start: pt2(0.0, 0.0),
+                end: pt2(100.0, 0.0),
+                color: rgba(0.0, 0.0, 0.0, 1.0),
+                weight: 2.0,
+                meta: Meta::default(),
+            },
+            Shape::Circle {
+                position: pt2(50.0, 50.0),
+                radius: 5.0,
+                color: rgba(1.0, 0.0, 0.0, 1.0),
+                meta: Meta::default(),
+            },
+        ];
+
+        // The 100 × 55 shapes fill 90 % of the width, zoomed 1.8 times around
+        // their centre (50, 27.5) and with y pointing down
+        let pixmap = render(&shapes, 200, 200).unwrap();
+
+        let white = [255, 255, 255];
+        // The line on row 149.5, 3.6 pixels thick from column 10 to 190
+        assert_eq!(pixel(&pixmap, 100, 149), [0, 0, 0]);
+        assert_eq!(pixel(&pixmap, 15, 150), [0, 0, 0]);
+        assert_eq!(pixel(&pixmap, 100, 145), white);
+        assert_eq!(pixel(&pixmap, 5, 149), white);
+        // The circle around (100, 59.5) with a radius of 9
+        assert_eq!(pixel(&pixmap, 100, 59), [255, 0, 0]);
+        assert_eq!(pixel(&pixmap, 106, 59), [255, 0, 0]);
+        assert_eq!(pixel(&pixmap, 100, 48), white);
+        assert_eq!(pixel(&pixmap, 2, 2), white);
+    }
+
+    #[test]
+    fn text_with_markup_characters_is_escaped() {
+        let shapes = [Shape::Text {
+            content: "Weiche <W1> & \"W2\"".to_string(),
+            position: pt2(0.0, 0.0),
+            font_size: 12,
+            color: rgba(0.0, 0.0, 0.0, 1.0),
+            meta: Meta::default(),
+        }];
+
+        let svg = to_svg(&shapes, 100, 40);
+        assert!(
+            svg.contains(">Weiche &lt;W1&gt; &amp; \"W2\"</text>"),
+            "{}",
+            svg
+        );
+        usvg::Tree::from_str(&svg, &usvg::Options::default()).unwrap();
+
+        // Some of the text is drawn around the centre
+        let pixmap = render(&shapes, 100, 40).unwrap();
+        assert!(pixmap.pixels().iter().any(|p| p.red() < 128));
+    }
+
+    #[test]
+    fn nothing_renders_a_blank_page() {
+        let pixmap = render(&[], 10, 10).unwrap();
+
+        assert!(pixmap.pixels().iter().all(|p| p.red() == 255));
+        assert!(render(&[], 0, 10).is_err());
+    }
+}
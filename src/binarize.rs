@@ -0,0 +1,265 @@
+use image::{GrayImage, Luma, RgbImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::BinarizationConfig;
+
+/// Ink / background decision for every pixel of a page, shared by all
+/// detection stages. Stages clear the pixels they explained, so whatever is
+/// left at the end is unresolved.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BinaryMask {
+    width: u32,
+    height: u32,
+    ink: Vec<bool>,
+}
+
+impl BinaryMask {
+    pub fn new(width: u32, height: u32) -> Self {
+        BinaryMask {
+            width,
+            height,
+            ink: vec![false; (width * height) as usize],
+        }
+    }
+
+    pub fn from_fn(width: u32, height: u32, f: impl Fn(u32, u32) -> bool + Sync) -> Self {
+        let ink = (0..width * height)
+            .into_par_iter()
+            .map(|i| f(i % width, i / width))
+            .collect();
+
+        BinaryMask { width, height, ink }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn is_ink(&self, x: u32, y: u32) -> bool {
+        self.ink[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, ink: bool) {
+        self.ink[(y * self.width + x) as usize] = ink;
+    }
+
+    pub fn ink_count(&self) -> usize {
+        self.ink.par_iter().filter(|i| **i).count()
+    }
+
+    /// Black ink on white, for saving or displaying the mask
+    pub fn to_image(&self) -> GrayImage {
+        GrayImage::from_fn(self.width, self.height, |x, y| match self.is_ink(x, y) {
+            true => Luma([0]),
+            false => Luma([255]),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BinarizationMethod {
+    /// Ink when every channel is below `threshold`
+    Fixed,
+    /// Global threshold on the luminance picked by Otsu's method
+    Otsu,
+    /// Local threshold on the luminance from the mean and deviation around each pixel
+    Sauvola,
+}
+
+pub fn binarize(img: &RgbImage, config: &BinarizationConfig) -> BinaryMask {
+    match config.method {
+        BinarizationMethod::Fixed => BinaryMask::from_fn(img.width(), img.height(), |x, y| {
+            let c = img.get_pixel(x, y);
+            c[0] < config.threshold && c[1] < config.threshold && c[2] < config.threshold
+        }),
+        BinarizationMethod::Otsu => {
+            let gray = image::imageops::grayscale(img);
+            let threshold = otsu_threshold(&gray);
+            BinaryMask::from_fn(img.width(), img.height(), |x, y| {
+                gray.get_pixel(x, y)[0] <= threshold
+            })
+        }
+        BinarizationMethod::Sauvola => {
+            sauvola(&image::imageops::grayscale(img), config.window, config.k)
+        }
+    }
+}
+
+/// Threshold maximising the variance between the two classes of the histogram
+pub fn otsu_threshold(gray: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for p in gray.pixels() {
+        histogram[p[0] as usize] += 1;
+    }
+
+    let total: u64 = histogram.iter().sum();
+    let sum: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(i, c)| i as f64 * *c as f64)
+        .sum();
+
+    let mut best = (0, 0.);
+    let mut background_weight = 0u64;
+    let mut background_sum = 0.;
+
+    for (t, count) in histogram.iter().enumerate() {
+        background_weight += count;
+        if background_weight == 0 {
+            continue;
+        }
+        let foreground_weight = total - background_weight;
+        if foreground_weight == 0 {
+            break;
+        }
+
+        background_sum += t as f64 * *count as f64;
+        let background_mean = background_sum / background_weight as f64;
+        let foreground_mean = (sum - background_sum) / foreground_weight as f64;
+
+        let variance = background_weight as f64
+            * foreground_weight as f64
+            * (background_mean - foreground_mean).powi(2);
+
+        if variance > best.1 {
+            best = (t, variance);
+        }
+    }
+
+    best.0 as u8
+}
+
+/// Sauvola's local threshold `mean * (1 + k * (deviation / 128 - 1))`, so
+/// uneven or grey backgrounds of scans don't turn into ink
+pub fn sauvola(gray: &GrayImage, window: u32, k: f32) -> BinaryMask {
+    let (width, height) = gray.dimensions();
+    let stride = width as usize + 1;
+
+    // Summed area tables with an extra zero row and column
+    let mut sum = vec![0f64; stride * (height as usize + 1)];
+    let mut sum_sq = vec![0f64; stride * (height as usize + 1)];
+
+    for y in 0..height as usize {
+        let mut row = 0.;
+        let mut row_sq = 0.;
+        for x in 0..width as usize {
+            let v = gray.get_pixel(x as _, y as _)[0] as f64;
+            row += v;
+            row_sq += v * v;
+            sum[(y + 1) * stride + x + 1] = sum[y * stride + x + 1] + row;
+            sum_sq[(y + 1) * stride + x + 1] = sum_sq[y * stride + x + 1] + row_sq;
+        }
+    }
+
+    let half = window / 2;
+
+    BinaryMask::from_fn(width, height, |x, y| {
+        let x0 = x.saturating_sub(half) as usize;
+        let y0 = y.saturating_sub(half) as usize;
+        let x1 = (x + half + 1).min(width) as usize;
+        let y1 = (y + half + 1).min(height) as usize;
+
+        let area = ((x1 - x0) * (y1 - y0)) as f64;
+        let rect = |t: &[f64]| {
+            t[y1 * stride + x1] - t[y0 * stride + x1] - t[y1 * stride + x0] + t[y0 * stride + x0]
+        };
+
+        let mean = rect(&sum) / area;
+        let deviation = (rect(&sum_sq) / area - mean * mean).max(0.).sqrt();
+        let threshold = mean * (1. + k as f64 * (deviation / 128. - 1.));
+
+        (gray.get_pixel(x, y)[0] as f64) <= threshold
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use super::*;
+
+    fn config(method: BinarizationMethod) -> BinarizationConfig {
+        BinarizationConfig {
+            method,
+            threshold: 200,
+            window: 25,
+            k: 0.1,
+        }
+    }
+
+    fn gray_to_rgb(gray: &GrayImage) -> RgbImage {
+        RgbImage::from_fn(gray.width(), gray.height(), |x, y| {
+            let v = gray.get_pixel(x, y)[0];
+            Rgb([v, v, v])
+        })
+    }
+
+    /// Light falling off from the left to the right, with a faint line across
+    /// rows 48 to 52 drawn 50 levels darker than the paper around it
+    fn unevenly_lit_line() -> RgbImage {
+        gray_to_rgb(&GrayImage::from_fn(200, 100, |x, y| {
+            let paper = 255 - x / 2;
+            match (48..53).contains(&y) {
+                true => Luma([(paper - 50) as u8]),
+                false => Luma([paper as u8]),
+            }
+        }))
+    }
+
+    #[test]
+    fn sauvola_keeps_a_line_on_uneven_illumination() {
+        let mask = binarize(&unevenly_lit_line(), &config(BinarizationMethod::Sauvola));
+
+        for x in 0..200 {
+            assert!((48..53).all(|y| mask.is_ink(x, y)), "line lost at {}", x);
+        }
+        assert_eq!(mask.ink_count(), 5 * 200);
+    }
+
+    #[test]
+    fn fixed_threshold_loses_the_line_where_the_light_is_bright() {
+        let mask = binarize(&unevenly_lit_line(), &config(BinarizationMethod::Fixed));
+
+        assert!(!mask.is_ink(5, 50));
+        // And takes the dim paper for ink
+        assert!(mask.is_ink(190, 10));
+    }
+
+    #[test]
+    fn otsu_picks_the_gap_of_a_bimodal_histogram() {
+        let gray = GrayImage::from_fn(100, 100, |x, y| match x < 30 {
+            true => Luma([30 + ((x + y) % 20) as u8]),
+            false => Luma([200 + ((x * y) % 20) as u8]),
+        });
+
+        let threshold = otsu_threshold(&gray);
+        assert!((49..200).contains(&threshold), "{}", threshold);
+
+        let mask = binarize(&gray_to_rgb(&gray), &config(BinarizationMethod::Otsu));
+        assert_eq!(mask.ink_count(), 30 * 100);
+        assert!(mask.is_ink(29, 99) && !mask.is_ink(30, 0));
+    }
+
+    #[test]
+    fn uniform_pages_have_no_ink() {
+        let white = RgbImage::from_pixel(64, 48, Rgb([255, 255, 255]));
+        let grey = RgbImage::from_pixel(64, 48, Rgb([180, 180, 180]));
+
+        for method in [
+            BinarizationMethod::Fixed,
+            BinarizationMethod::Otsu,
+            BinarizationMethod::Sauvola,
+        ] {
+            assert_eq!(binarize(&white, &config(method)).ink_count(), 0);
+        }
+        for method in [BinarizationMethod::Otsu, BinarizationMethod::Sauvola] {
+            assert_eq!(binarize(&grey, &config(method)).ink_count(), 0);
+        }
+    }
+}
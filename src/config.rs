@@ -0,0 +1,763 @@
+use std::{collections::BTreeMap, path::Path, path::PathBuf};
+
+use anyhow::{bail, ensure, Context};
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+use toml::Table;
+
+use crate::{binarize::BinarizationMethod, shape_finder::ThicknessClass};
+
+/// Settings of every pipeline stage.
+///
+/// Loaded from a TOML file where the top level holds the defaults and every
+/// `[profiles.<name>]` table overrides some of them for a family of plans:
+///
+/// ```toml
+/// render_width = 800
+///
+/// [detection]
+/// min_line_length = 200
+///
+/// [profiles.high-dpi]
+/// render_width = 2400
+/// detection = { min_line_length = 600 }
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    /// Page processed by [`crate::Pipeline::process_pdf`]
+    pub page: u32,
+    /// Width in pixels the PDF pages are rendered at
+    pub render_width: i32,
+    /// Resolution of images processed directly, PDF pages know their size
+    pub image_dpi: f32,
+    pub password: Option<String>,
+    pub binarization: BinarizationConfig,
+    pub deskew: DeskewConfig,
+    pub sheet: SheetConfig,
+    pub layers: LayersConfig,
+    pub detection: DetectionConfig,
+    pub thickness: ThicknessConfig,
+    pub corrections: CorrectionConfig,
+    pub export: ExportConfig,
+}
+
+/// How [`crate::binarize`] tells ink from background
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BinarizationConfig {
+    pub method: BinarizationMethod,
+    /// Used by [`BinarizationMethod::Fixed`]
+    pub threshold: u8,
+    /// Side of the square neighbourhood used by [`BinarizationMethod::Sauvola`], in pixels
+    pub window: u32,
+    /// Sensitivity of [`BinarizationMethod::Sauvola`], higher values produce less ink
+    pub k: f32,
+}
+
+/// How [`crate::deskew`] straightens rotated scans, angles are in degrees
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeskewConfig {
+    pub enabled: bool,
+    /// Largest rotation looked for in either direction
+    pub max_angle: f32,
+    /// Resolution of the search
+    pub step: f32,
+    /// Smaller rotations are left as they are
+    pub min_angle: f32,
+}
+
+/// How [`crate::sheet`] finds the frame and title block of the sheet
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SheetConfig {
+    pub enabled: bool,
+    /// Frame lines span at least this fraction of the page
+    pub frame_min_length: f32,
+    /// Lines this close to the frame belong to it, in pixels
+    pub frame_margin: u32,
+    /// Title blocks and legends are at least this fraction of the frame wide
+    pub box_min_width: f32,
+    /// Title blocks and legends are at most this fraction of the frame high
+    pub box_max_height: f32,
+    /// Labels in front of the fields of the title block, matched ignoring case
+    pub station_labels: Vec<String>,
+    pub plan_number_labels: Vec<String>,
+    pub revision_labels: Vec<String>,
+    pub date_labels: Vec<String>,
+    pub sheet_labels: Vec<String>,
+    pub scale_labels: Vec<String>,
+}
+
+/// How ink colours are grouped by [`crate::layers`] and named in the output
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayersConfig {
+    /// Most colours the ink is clustered into
+    pub clusters: usize,
+    /// Colours closer than this form a single cluster, differences in
+    /// lightness counting less than differences in hue, see [`crate::layers`]
+    pub min_distance: f32,
+    /// Layer of every colour not matched by `mapping`
+    pub default_layer: String,
+    pub mapping: Vec<LayerMapping>,
+}
+
+/// Puts shapes drawn close to `color` into the layer `name`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayerMapping {
+    pub name: String,
+    pub color: [u8; 3],
+    /// Largest RGB distance to `color` that still belongs to the layer
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f32,
+}
+
+fn default_tolerance() -> f32 {
+    80.
+}
+
+/// Thresholds used by [`crate::shape_finder`], lengths are in rendered pixels
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DetectionConfig {
+    /// Shortest horizontal run that counts as a track line
+    pub min_line_length: usize,
+    /// Connected components with fewer pixels are ignored
+    pub min_shape_pixels: usize,
+    /// Connected components with more pixels are ignored
+    pub max_shape_pixels: usize,
+    /// Largest gap between the dashes of a dashed or dotted line
+    pub dash_max_gap: usize,
+    /// Fewest dashes a dashed or dotted line is made of
+    pub min_dashes: usize,
+    /// Dashes up to this long make a dotted line
+    pub dot_max_length: usize,
+    /// Consecutive horizontal lines less different than this are merged
+    pub merge_similarity: f32,
+    /// Parallel lines at most this far apart are one double line
+    pub double_max_gap: f32,
+    /// Line end points closer than this to a line split it
+    pub split_distance: f32,
+    /// Pieces of a split line shorter than this are dropped
+    pub min_segment_length: f32,
+    /// Lines have to be thicker than this to be kept
+    pub min_thickness: f32,
+    /// End points closer than this are joined into one junction
+    pub junction_distance: f32,
+    /// Shortest stroke turned into a curve
+    pub curve_min_length: f32,
+    /// Widest stroke turned into a curve
+    pub curve_max_width: f32,
+    /// Largest distance of a curve from the arc or polyline fitted to it
+    pub curve_tolerance: f32,
+    /// Fraction of a circle or rectangle covered with ink to count as filled
+    pub min_fill_ratio: f32,
+    /// How far the pixels of circles and rectangles may stray from their outline
+    pub primitive_tolerance: f32,
+    /// Longer rectangles are left to the line detection
+    pub rectangle_max_aspect: f32,
+}
+
+/// Stroke widths telling the kinds of track apart, in sheet millimetres
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThicknessConfig {
+    /// Tracks at least this wide are main tracks
+    pub main_track: f32,
+    /// Tracks at least this wide are sidings, thinner ones auxiliary
+    pub siding: f32,
+}
+
+/// How corrections made by hand are found again in a fresh detection, see
+/// [`crate::correction`]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorrectionConfig {
+    /// Nodes and symbols this far from where a correction was made, in
+    /// sheet millimetres, are taken for the ones it was made to
+    pub anchor_distance: f32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportConfig {
+    pub svg_path: PathBuf,
+    /// Image with the pixels no shape could be found for
+    pub unresolved_path: PathBuf,
+    /// Georeferenced graph, written when control points are given
+    pub geojson_path: PathBuf,
+    /// Detected graph as JSON, see [`crate::plan_graph::PlanGraph`]
+    pub graph_path: PathBuf,
+    /// Where the viewer saves the graph corrected by hand
+    pub corrected_graph_path: PathBuf,
+    /// Where the viewer saves the corrections made by hand, to replay them
+    /// on later detections
+    pub corrections_path: PathBuf,
+    /// Difference between the rasterised SVG and the page, see
+    /// [`crate::render_svg`]
+    pub heatmap_path: PathBuf,
+    /// Ink of the SVG and the page this many pixels apart still matches
+    pub diff_tolerance: f32,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            page: 0,
+            render_width: 800,
+            image_dpi: 96.,
+            password: None,
+            binarization: BinarizationConfig::default(),
+            deskew: DeskewConfig::default(),
+            sheet: SheetConfig::default(),
+            layers: LayersConfig::default(),
+            detection: DetectionConfig::default(),
+            thickness: ThicknessConfig::default(),
+            corrections: CorrectionConfig::default(),
+            export: ExportConfig::default(),
+        }
+    }
+}
+
+impl Default for BinarizationConfig {
+    fn default() -> Self {
+        BinarizationConfig {
+            method: BinarizationMethod::Sauvola,
+            threshold: 200,
+            window: 25,
+            k: 0.2,
+        }
+    }
+}
+
+impl Default for DeskewConfig {
+    fn default() -> Self {
+        DeskewConfig {
+            enabled: true,
+            max_angle: 5.,
+            step: 0.1,
+            min_angle: 0.05,
+        }
+    }
+}
+
+fn labels(labels: &[&str]) -> Vec<String> {
+    labels.iter().map(|l| l.to_string()).collect()
+}
+
+impl Default for SheetConfig {
+    fn default() -> Self {
+        SheetConfig {
+            enabled: true,
+            frame_min_length: 0.5,
+            frame_margin: 10,
+            box_min_width: 0.1,
+            box_max_height: 0.5,
+            station_labels: labels(&["Bahnhof", "Station", "Gare", "Stazione"]),
+            plan_number_labels: labels(&["Plan-Nr", "Plannummer", "Plan Nr", "Plan No"]),
+            revision_labels: labels(&["Revision", "Rev", "Index"]),
+            date_labels: labels(&["Datum", "Date", "Data"]),
+            sheet_labels: labels(&["Blatt", "Feuille", "Foglio", "Sheet"]),
+            scale_labels: labels(&["Massstab", "Maßstab", "Echelle", "Scala", "Scale"]),
+        }
+    }
+}
+
+impl Default for LayersConfig {
+    fn default() -> Self {
+        LayersConfig {
+            clusters: 4,
+            min_distance: 80.,
+            default_layer: "existing".to_string(),
+            mapping: vec![],
+        }
+    }
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        DetectionConfig {
+            min_line_length: 200,
+            min_shape_pixels: 50,
+            max_shape_pixels: 200,
+            dash_max_gap: 15,
+            min_dashes: 4,
+            dot_max_length: 3,
+            merge_similarity: 20.,
+            double_max_gap: 6.,
+            split_distance: 5.,
+            min_segment_length: 3.,
+            min_thickness: 1.,
+            junction_distance: 20.,
+            curve_min_length: 30.,
+            curve_max_width: 8.,
+            curve_tolerance: 1.5,
+            min_fill_ratio: 0.85,
+            primitive_tolerance: 1.5,
+            rectangle_max_aspect: 4.,
+        }
+    }
+}
+
+impl Default for ThicknessConfig {
+    fn default() -> Self {
+        ThicknessConfig {
+            main_track: 0.5,
+            siding: 0.35,
+        }
+    }
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        ExportConfig {
+            svg_path: "plan.svg".into(),
+            unresolved_path: "non-resolved-parts.png".into(),
+            geojson_path: "plan.geojson".into(),
+            graph_path: "plan.json".into(),
+            corrected_graph_path: "plan-corrected.json".into(),
+            corrections_path: "plan-corrections.json".into(),
+            heatmap_path: "svg-diff.png".into(),
+            diff_tolerance: 2.,
+        }
+    }
+}
+
+impl Default for CorrectionConfig {
+    fn default() -> Self {
+        CorrectionConfig {
+            anchor_distance: 2.,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    profiles: BTreeMap<String, Table>,
+    #[serde(flatten)]
+    base: Table,
+}
+
+/// Recursively overwrites the values of `base` with the ones in `overrides`
+fn merge_tables(base: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(value)) => {
+                merge_tables(base, value)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+impl PipelineConfig {
+    /// Parses a configuration, applying the given profile on top of the defaults
+    pub fn from_toml(content: &str, profile: Option<&str>) -> anyhow::Result<Self> {
+        let ConfigFile {
+            mut profiles,
+            mut base,
+        } = toml::from_str(content)?;
+
+        if let Some(profile) = profile {
+            let Some(overrides) = profiles.remove(profile) else {
+                bail!(
+                    "unknown profile `{}`, available profiles: {}",
+                    profile,
+                    profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+                );
+            };
+            merge_tables(&mut base, overrides);
+        }
+
+        let config: PipelineConfig = base.try_into().with_context(|| match profile {
+            Some(profile) => format!("invalid settings in profile `{}`", profile),
+            None => "invalid settings".to_string(),
+        })?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>, profile: Option<&str>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+
+        Self::from_toml(&content, profile)
+            .with_context(|| format!("failed to load config {}", path.display()))
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.render_width > 0,
+            "render_width must be positive, got {}",
+            self.render_width
+        );
+        ensure!(
+            self.image_dpi.is_finite() && self.image_dpi > 0.,
+            "image_dpi must be positive, got {}",
+            self.image_dpi
+        );
+        self.binarization.validate()?;
+        self.deskew.validate()?;
+        self.sheet.validate()?;
+        self.layers.validate()?;
+        self.detection.validate()?;
+        self.thickness.validate()?;
+        self.corrections.validate()?;
+        self.export.validate()
+    }
+}
+
+impl BinarizationConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.threshold > 0,
+            "binarization.threshold must be above 0, otherwise no pixel is ink"
+        );
+        ensure!(
+            self.window >= 3,
+            "binarization.window must be at least 3, got {}",
+            self.window
+        );
+        ensure!(
+            self.k.is_finite() && self.k > 0. && self.k < 1.,
+            "binarization.k must be between 0 and 1, got {}",
+            self.k
+        );
+        Ok(())
+    }
+}
+
+impl DeskewConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.max_angle.is_finite() && self.max_angle > 0. && self.max_angle <= 45.,
+            "deskew.max_angle must be between 0 and 45 degrees, got {}",
+            self.max_angle
+        );
+        ensure!(
+            self.step.is_finite() && self.step > 0. && self.step <= self.max_angle,
+            "deskew.step must be positive and at most deskew.max_angle, got {}",
+            self.step
+        );
+        ensure!(
+            self.min_angle.is_finite() && self.min_angle >= 0.,
+            "deskew.min_angle must be a positive number, got {}",
+            self.min_angle
+        );
+        ensure!(
+            self.min_angle <= self.max_angle,
+            "deskew.min_angle must be at most deskew.max_angle, got {} and {}",
+            self.min_angle,
+            self.max_angle
+        );
+        Ok(())
+    }
+}
+
+impl SheetConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, value) in [
+            ("frame_min_length", self.frame_min_length),
+            ("box_min_width", self.box_min_width),
+            ("box_max_height", self.box_max_height),
+        ] {
+            ensure!(
+                value > 0. && value <= 1.,
+                "sheet.{} must be a fraction between 0 and 1, got {}",
+                name,
+                value
+            );
+        }
+        Ok(())
+    }
+}
+
+impl LayersConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.clusters > 0 && self.clusters < u8::MAX as usize,
+            "layers.clusters must be between 1 and 254, got {}",
+            self.clusters
+        );
+        ensure!(
+            self.min_distance.is_finite() && self.min_distance >= 0.,
+            "layers.min_distance must be a positive number, got {}",
+            self.min_distance
+        );
+        ensure!(
+            !self.default_layer.is_empty(),
+            "layers.default_layer must not be empty"
+        );
+
+        for (i, mapping) in self.mapping.iter().enumerate() {
+            ensure!(
+                !mapping.name.is_empty(),
+                "layers.mapping[{}].name must not be empty",
+                i
+            );
+            ensure!(
+                mapping.tolerance.is_finite() && mapping.tolerance >= 0.,
+                "layers.mapping[{}].tolerance must be a positive number, got {}",
+                i,
+                mapping.tolerance
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Name of the layer closest to the colour, within the tolerance of its mapping
+    pub fn layer_of(&self, color: &Rgb<u8>) -> &str {
+        let distance = |m: &LayerMapping| {
+            ((color[0] as f32 - m.color[0] as f32).powi(2)
+                + (color[1] as f32 - m.color[1] as f32).powi(2)
+                + (color[2] as f32 - m.color[2] as f32).powi(2))
+            .sqrt()
+        };
+
+        self.mapping
+            .iter()
+            .filter(|m| distance(m) <= m.tolerance)
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .map(|m| m.name.as_str())
+            .unwrap_or(&self.default_layer)
+    }
+}
+
+impl DetectionConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.min_line_length > 0,
+            "detection.min_line_length must be above 0"
+        );
+        ensure!(
+            self.min_dashes >= 2,
+            "detection.min_dashes must be at least 2, got {}",
+            self.min_dashes
+        );
+        ensure!(
+            self.min_fill_ratio > 0. && self.min_fill_ratio <= 1.,
+            "detection.min_fill_ratio must be between 0 and 1, got {}",
+            self.min_fill_ratio
+        );
+        ensure!(
+            self.rectangle_max_aspect >= 1.,
+            "detection.rectangle_max_aspect must be at least 1, got {}",
+            self.rectangle_max_aspect
+        );
+        ensure!(
+            self.min_shape_pixels < self.max_shape_pixels,
+            "detection.min_shape_pixels ({}) must be smaller than detection.max_shape_pixels ({})",
+            self.min_shape_pixels,
+            self.max_shape_pixels
+        );
+
+        for (name, value) in [
+            ("merge_similarity", self.merge_similarity),
+            ("double_max_gap", self.double_max_gap),
+            ("split_distance", self.split_distance),
+            ("min_segment_length", self.min_segment_length),
+            ("min_thickness", self.min_thickness),
+            ("junction_distance", self.junction_distance),
+            ("curve_min_length", self.curve_min_length),
+            ("curve_max_width", self.curve_max_width),
+            ("curve_tolerance", self.curve_tolerance),
+            ("primitive_tolerance", self.primitive_tolerance),
+        ] {
+            ensure!(
+                value.is_finite() && value >= 0.,
+                "detection.{} must be a positive number, got {}",
+                name,
+                value
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl CorrectionConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.anchor_distance.is_finite() && self.anchor_distance >= 0.,
+            "corrections.anchor_distance must not be negative, got {}",
+            self.anchor_distance
+        );
+        Ok(())
+    }
+}
+
+impl ExportConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.diff_tolerance.is_finite() && self.diff_tolerance >= 0.,
+            "export.diff_tolerance must not be negative, got {}",
+            self.diff_tolerance
+        );
+        Ok(())
+    }
+}
+
+impl ThicknessConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.siding.is_finite() && self.siding > 0.,
+            "thickness.siding must be positive, got {}",
+            self.siding
+        );
+        ensure!(
+            self.main_track.is_finite() && self.main_track > self.siding,
+            "thickness.main_track ({}) must be larger than thickness.siding ({})",
+            self.main_track,
+            self.siding
+        );
+        Ok(())
+    }
+
+    /// Class of a track drawn with the given stroke width in millimetres
+    pub fn class_of(&self, stroke_width: f32) -> ThicknessClass {
+        if stroke_width >= self.main_track {
+            ThicknessClass::MainTrack
+        } else if stroke_width >= self.siding {
+            ThicknessClass::Siding
+        } else {
+            ThicknessClass::Auxiliary
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+render_width = 1000
+
+[detection]
+min_line_length = 250
+merge_similarity = 25.0
+
+[profiles.large]
+render_width = 3000
+detection = { min_line_length = 750 }
+
+[profiles.otsu.binarization]
+method = "otsu"
+"#;
+
+    fn error(content: &str, profile: Option<&str>) -> String {
+        format!(
+            "{:#}",
+            PipelineConfig::from_toml(content, profile).unwrap_err()
+        )
+    }
+
+    #[test]
+    fn settings_left_out_keep_their_defaults() {
+        let config = PipelineConfig::from_toml(CONFIG, None).unwrap();
+        let defaults = PipelineConfig::default();
+
+        assert_eq!(config.render_width, 1000);
+        assert_eq!(config.detection.min_line_length, 250);
+        assert_eq!(config.detection.merge_similarity, 25.);
+        assert_eq!(
+            config.detection.double_max_gap,
+            defaults.detection.double_max_gap
+        );
+        assert_eq!(config.binarization.method, defaults.binarization.method);
+    }
+
+    #[test]
+    fn profiles_override_single_nested_settings() {
+        let config = PipelineConfig::from_toml(CONFIG, Some("large")).unwrap();
+
+        assert_eq!(config.render_width, 3000);
+        assert_eq!(config.detection.min_line_length, 750);
+        // Kept from the top level, not reset to the default by the profile
+        assert_eq!(config.detection.merge_similarity, 25.);
+
+        let config = PipelineConfig::from_toml(CONFIG, Some("otsu")).unwrap();
+        assert_eq!(config.binarization.method, BinarizationMethod::Otsu);
+        assert_eq!(config.render_width, 1000);
+    }
+
+    #[test]
+    fn merging_tables_replaces_values_and_recurses_into_tables() {
+        let mut base: Table = toml::from_str("a = 1\nb = [1, 2]\n[t]\nx = 1\ny = 2").unwrap();
+        let overrides: Table = toml::from_str("b = [3]\nc = true\n[t]\ny = 5").unwrap();
+
+        merge_tables(&mut base, overrides);
+
+        let expected: Table =
+            toml::from_str("a = 1\nb = [3]\nc = true\n[t]\nx = 1\ny = 5").unwrap();
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn unknown_profiles_list_the_available_ones() {
+        let message = error(CONFIG, Some("scanned"));
+
+        assert!(message.contains("unknown profile `scanned`"), "{}", message);
+        assert!(message.contains("large, otsu"), "{}", message);
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        let message = error("render_widht = 1000", None);
+        assert!(message.contains("render_widht"), "{}", message);
+
+        let message = error("[detection]\nmin_line_lenght = 1", None);
+        assert!(message.contains("min_line_lenght"), "{}", message);
+
+        let message = error(
+            "[profiles.typo.detection]\nmin_line_lenght = 1",
+            Some("typo"),
+        );
+        assert!(message.contains("profile `typo`"), "{}", message);
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let message = error("render_width = 0", None);
+        assert!(
+            message.contains("render_width must be positive"),
+            "{}",
+            message
+        );
+
+        let message = error("[binarization]\nk = 1.5", None);
+        assert!(message.contains("binarization.k"), "{}", message);
+
+        let message = error("[deskew]\nmax_angle = 2.0\nmin_angle = 3.0", None);
+        assert!(
+            message.contains("deskew.min_angle must be at most deskew.max_angle"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        PipelineConfig::default().validate().unwrap();
+    }
+
+    #[test]
+    fn bundled_config_loads_with_every_profile() {
+        let content = include_str!("../config/railify.toml");
+        let ConfigFile { profiles, .. } = toml::from_str(content).unwrap();
+
+        PipelineConfig::from_toml(content, None).unwrap();
+        for profile in profiles.keys() {
+            PipelineConfig::from_toml(content, Some(profile)).unwrap();
+        }
+        assert!(profiles.contains_key("high-dpi"));
+    }
+}
@@ -0,0 +1,453 @@
+use std::{fmt, path::Path};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::CorrectionConfig,
+    plan_graph::{Item, PlanGraph},
+    shape_finder::Point,
+    transform::Transform,
+};
+
+/// What a correction applies to, by where it lies on the sheet rather than by
+/// an index that changes with every detection
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anchor {
+    /// Node closest to the position
+    Node(Point),
+    /// Track between the nodes closest to the positions
+    Edge(Point, Point),
+    /// Symbol with its centre closest to the position
+    Symbol(Point),
+}
+
+/// Change made by hand to a [`PlanGraph`], positions in sheet millimetres
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub enum Correction {
+    AddNode {
+        at: Point,
+    },
+    Delete {
+        anchor: Anchor,
+    },
+    MoveNode {
+        node: Point,
+        to: Point,
+    },
+    Connect {
+        from: Point,
+        to: Point,
+    },
+    Disconnect {
+        from: Point,
+        to: Point,
+    },
+    /// A kind of `None` makes the symbol unrecognised again
+    SetKind {
+        symbol: Point,
+        kind: Option<String>,
+    },
+    /// A label of `None` removes it
+    SetLabel {
+        anchor: Anchor,
+        label: Option<String>,
+    },
+}
+
+/// Finds the anchors of corrections in a graph
+pub struct Anchoring {
+    /// From the positions of the graph to sheet millimetres
+    pub to_sheet: Transform,
+    /// See [`CorrectionConfig::anchor_distance`]
+    pub distance: f32,
+}
+
+impl Anchoring {
+    pub fn new(to_sheet: Transform, config: &CorrectionConfig) -> Self {
+        Anchoring {
+            to_sheet,
+            distance: config.anchor_distance,
+        }
+    }
+
+    /// Position of the graph in sheet millimetres
+    pub fn sheet(&self, p: &Point) -> Point {
+        self.to_sheet.apply(p)
+    }
+
+    /// Sheet millimetres to a position of the graph
+    pub fn graph(&self, p: &Point) -> anyhow::Result<Point> {
+        let to_graph = self
+            .to_sheet
+            .inverse()
+            .context("the page has no sheet coordinates")?;
+        Ok(to_graph.apply(p))
+    }
+
+    /// Index of the closest of the positions within the distance
+    fn closest<'a>(&self, positions: impl Iterator<Item = &'a Point>, at: &Point) -> Option<usize> {
+        positions
+            .map(|p| self.sheet(p).distance(at))
+            .enumerate()
+            .filter(|(_, d)| *d <= self.distance)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+
+    pub fn node(&self, graph: &PlanGraph, at: &Point) -> anyhow::Result<usize> {
+        self.closest(graph.nodes.iter().map(|n| &n.position), at)
+            .with_context(|| format!("no node within {} mm of {}", self.distance, mm(at)))
+    }
+
+    pub fn symbol(&self, graph: &PlanGraph, at: &Point) -> anyhow::Result<usize> {
+        self.closest(graph.symbols.iter().map(|s| &s.center), at)
+            .with_context(|| format!("no symbol within {} mm of {}", self.distance, mm(at)))
+    }
+
+    pub fn item(&self, graph: &PlanGraph, anchor: &Anchor) -> anyhow::Result<Item> {
+        match anchor {
+            Anchor::Node(at) => Ok(Item::Node(self.node(graph, at)?)),
+            Anchor::Symbol(at) => Ok(Item::Symbol(self.symbol(graph, at)?)),
+            Anchor::Edge(from, to) => {
+                let (a, b) = (self.node(graph, from)?, self.node(graph, to)?);
+                match graph.edges_between(a, b).first() {
+                    Some(edge) => Ok(Item::Edge(*edge)),
+                    None => bail!("no track between {} and {}", mm(from), mm(to)),
+                }
+            }
+        }
+    }
+
+    /// Anchor of a part of the graph
+    pub fn anchor(&self, graph: &PlanGraph, item: Item) -> Option<Anchor> {
+        match item {
+            Item::Node(i) => Some(Anchor::Node(self.sheet(&graph.nodes.get(i)?.position))),
+            Item::Symbol(i) => Some(Anchor::Symbol(self.sheet(&graph.symbols.get(i)?.center))),
+            Item::Edge(i) => {
+                let edge = graph.edges.get(i)?;
+                Some(Anchor::Edge(
+                    self.sheet(&graph.nodes[edge.from].position),
+                    self.sheet(&graph.nodes[edge.to].position),
+                ))
+            }
+        }
+    }
+}
+
+fn mm(p: &Point) -> String {
+    format!("({:.1}, {:.1}) mm", p.0, p.1)
+}
+
+impl Correction {
+    /// Makes the correction to the graph, failing if what it applies to
+    /// can't be found
+    pub fn apply(&self, graph: &mut PlanGraph, anchoring: &Anchoring) -> anyhow::Result<()> {
+        match self {
+            Correction::AddNode { at } => {
+                graph.add_node(anchoring.graph(at)?);
+            }
+            Correction::Delete { anchor } => match anchoring.item(graph, anchor)? {
+                Item::Node(i) => graph.remove_node(i)?,
+                Item::Edge(i) => graph.remove_edge(i)?,
+                Item::Symbol(i) => graph.remove_symbol(i)?,
+            },
+            Correction::MoveNode { node, to } => {
+                let node = anchoring.node(graph, node)?;
+                graph.move_node(node, anchoring.graph(to)?)?
+            }
+            Correction::Connect { from, to } => {
+                let (a, b) = (anchoring.node(graph, from)?, anchoring.node(graph, to)?);
+                graph.connect(a, b)?;
+            }
+            Correction::Disconnect { from, to } => {
+                let (a, b) = (anchoring.node(graph, from)?, anchoring.node(graph, to)?);
+                graph.disconnect(a, b)?
+            }
+            Correction::SetKind { symbol, kind } => {
+                let symbol = anchoring.symbol(graph, symbol)?;
+                graph.set_kind(symbol, kind.clone())?
+            }
+            Correction::SetLabel { anchor, label } => {
+                let item = anchoring.item(graph, anchor)?;
+                graph.set_label(item, label.clone())?
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Name of the operation as written in the patch file
+    pub fn op(&self) -> &'static str {
+        match self {
+            Correction::AddNode { .. } => "add-node",
+            Correction::Delete { .. } => "delete",
+            Correction::MoveNode { .. } => "move-node",
+            Correction::Connect { .. } => "connect",
+            Correction::Disconnect { .. } => "disconnect",
+            Correction::SetKind { .. } => "set-kind",
+            Correction::SetLabel { .. } => "set-label",
+        }
+    }
+}
+
+/// Corrections of a plan in the order they were made, replayed in the same
+/// order on later detections
+///
+/// ```json
+/// {
+///   "corrections": [
+///     { "op": "move-node", "node": [21.5, 266.8], "to": [22.0, 270.1] },
+///     { "op": "set-label", "anchor": { "node": [22.0, 270.1] }, "label": "W1" }
+///   ]
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Patch {
+    pub corrections: Vec<Correction>,
+}
+
+/// Correction that no longer applies to the graph it was replayed on
+#[derive(Clone, Debug, PartialEq)]
+pub struct StaleCorrection {
+    /// Index into [`Patch::corrections`]
+    pub index: usize,
+    pub correction: Correction,
+    pub reason: String,
+}
+
+/// Outcome of [`Patch::replay`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayReport {
+    pub applied: usize,
+    pub stale: Vec<StaleCorrection>,
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Applied {} of {} corrections",
+            self.applied,
+            self.applied + self.stale.len()
+        )?;
+        for stale in &self.stale {
+            write!(
+                f,
+                "\nCorrection {} ({}) no longer applies: {}",
+                stale.index,
+                stale.correction.op(),
+                stale.reason
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Patch {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read corrections {}", path.display()))?;
+
+        serde_json::from_str(&content)
+            .with_context(|| format!("failed to load corrections {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("failed to write corrections {}", path.display()))
+    }
+
+    /// Adds a correction made after the others, a node dragged around is
+    /// kept as a single move
+    pub fn push(&mut self, correction: Correction) {
+        if let (Some(Correction::MoveNode { to: last, .. }), Correction::MoveNode { node, to }) =
+            (self.corrections.last_mut(), &correction)
+        {
+            if last.distance(node) < MOVE_MERGE_DISTANCE {
+                *last = to.clone();
+                return;
+            }
+        }
+
+        self.corrections.push(correction);
+    }
+
+    /// Makes every correction that still applies to the graph, in order, and
+    /// reports the others
+    pub fn replay(&self, graph: &mut PlanGraph, anchoring: &Anchoring) -> ReplayReport {
+        let mut report = ReplayReport::default();
+
+        for (index, correction) in self.corrections.iter().enumerate() {
+            match correction.apply(graph, anchoring) {
+                Ok(()) => report.applied += 1,
+                Err(e) => report.stale.push(StaleCorrection {
+                    index,
+                    correction: correction.clone(),
+                    reason: format!("{:#}", e),
+                }),
+            }
+        }
+
+        report
+    }
+}
+
+/// Moves starting this close to where the last one ended, in millimetres,
+/// continue it
+const MOVE_MERGE_DISTANCE: f32 = 1e-3;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        plan_graph::{GraphEdge, GraphNode},
+        shape_finder::{LineStyle, ThicknessClass},
+    };
+
+    /// Nodes at the positions, graph positions being sheet millimetres
+    fn graph(positions: &[(f32, f32)], edges: &[(usize, usize)]) -> PlanGraph {
+        PlanGraph {
+            nodes: positions
+                .iter()
+                .map(|(x, y)| GraphNode {
+                    position: Point(*x, *y),
+                    pinpoint: false,
+                    label: None,
+                })
+                .collect(),
+            edges: edges
+                .iter()
+                .map(|(from, to)| GraphEdge {
+                    from: *from,
+                    to: *to,
+                    color: "#000000".to_string(),
+                    layer: "existing".to_string(),
+                    style: LineStyle::Solid,
+                    stroke_width: 0.5,
+                    thickness_class: ThicknessClass::MainTrack,
+                    geometry: None,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn anchoring() -> Anchoring {
+        Anchoring::new(Transform::identity(), &CorrectionConfig::default())
+    }
+
+    fn node_at(graph: &PlanGraph, at: (f32, f32)) -> usize {
+        anchoring().node(graph, &Point(at.0, at.1)).unwrap()
+    }
+
+    #[test]
+    fn corrections_reanchor_on_a_detection_with_other_indices() {
+        let patch = Patch {
+            corrections: vec![
+                Correction::MoveNode {
+                    node: Point(10., 10.),
+                    to: Point(12., 15.),
+                },
+                Correction::Connect {
+                    from: Point(12., 15.),
+                    to: Point(50., 10.),
+                },
+                Correction::SetLabel {
+                    anchor: Anchor::Node(Point(50., 10.)),
+                    label: Some("W1".to_string()),
+                },
+            ],
+        };
+
+        // Nodes found in another order, slightly off and with one more
+        let mut fresh = graph(
+            &[(90., 10.), (50.5, 10.2), (30., 30.), (9.6, 10.3)],
+            &[(0, 1)],
+        );
+        let report = patch.replay(&mut fresh, &anchoring());
+
+        assert_eq!(report.applied, 3, "{}", report);
+        assert!(report.stale.is_empty());
+        assert_eq!(fresh.nodes[3].position, Point(12., 15.));
+        assert_eq!(fresh.edges_between(3, 1).len(), 1);
+        assert_eq!(fresh.nodes[1].label.as_deref(), Some("W1"));
+        assert_eq!(node_at(&fresh, (12., 15.)), 3);
+    }
+
+    #[test]
+    fn anchors_beyond_the_distance_are_reported_stale() {
+        let patch = Patch {
+            corrections: vec![
+                Correction::Delete {
+                    anchor: Anchor::Node(Point(10., 10.)),
+                },
+                Correction::Disconnect {
+                    from: Point(50., 10.),
+                    to: Point(90., 10.),
+                },
+            ],
+        };
+
+        // The first node moved further than the anchor distance of 2 mm
+        let mut fresh = graph(&[(13., 10.), (50., 10.), (90., 10.)], &[(1, 2)]);
+        let report = patch.replay(&mut fresh, &anchoring());
+
+        assert_eq!(report.applied, 1);
+        assert_eq!(report.stale.len(), 1);
+        let stale = &report.stale[0];
+        assert_eq!(stale.index, 0);
+        assert_eq!(stale.correction, patch.corrections[0]);
+        assert!(
+            stale.reason.contains("no node within 2 mm"),
+            "{}",
+            stale.reason
+        );
+        assert!(report
+            .to_string()
+            .contains("Correction 0 (delete) no longer applies"));
+        // The stale correction left the graph alone
+        assert_eq!(fresh.nodes.len(), 3);
+        assert!(fresh.edges.is_empty());
+    }
+
+    #[test]
+    fn edges_are_anchored_by_their_nodes() {
+        let mut fresh = graph(&[(0., 0.), (10., 0.), (20., 0.)], &[(0, 1), (1, 2)]);
+        let delete = Correction::Delete {
+            anchor: Anchor::Edge(Point(20.5, 0.), Point(10., 0.5)),
+        };
+
+        delete.apply(&mut fresh, &anchoring()).unwrap();
+
+        assert_eq!(fresh.edges.len(), 1);
+        assert_eq!((fresh.edges[0].from, fresh.edges[0].to), (0, 1));
+        assert!(delete.apply(&mut fresh, &anchoring()).is_err());
+    }
+
+    #[test]
+    fn consecutive_moves_of_a_node_are_merged() {
+        let mut patch = Patch::default();
+        let r#move = |from: (f32, f32), to: (f32, f32)| Correction::MoveNode {
+            node: Point(from.0, from.1),
+            to: Point(to.0, to.1),
+        };
+
+        patch.push(r#move((0., 0.), (1., 0.)));
+        patch.push(r#move((1., 0.), (2., 1.)));
+        patch.push(r#move((2., 1.), (3., 3.)));
+
+        assert_eq!(patch.corrections, vec![r#move((0., 0.), (3., 3.))]);
+
+        // Another node, and the same one again after something else
+        patch.push(r#move((10., 0.), (11., 0.)));
+        patch.push(Correction::AddNode { at: Point(5., 5.) });
+        patch.push(r#move((11., 0.), (12., 0.)));
+
+        assert_eq!(patch.corrections.len(), 4);
+    }
+}
@@ -2,8 +2,11 @@ use std::{cell::RefCell, rc::Rc};
 
 use svg::node::element;
 
-use crate::svg_helper;
+use draw::{WINDOW_HEIGHT, WINDOW_WIDTH};
 
+use crate::{metadata::PlanMetadata, svg_helper, transform::Transform};
+
+#[allow(dead_code)]
 #[derive(Debug)]
 struct Circle {
     coordinates: (i32, i32),
@@ -94,7 +97,7 @@ impl Node {
     }
 }
 
-pub fn example() {
+pub fn example() -> anyhow::Result<()> {
     let node_1 = Rc::new(RefCell::new(Node {
         coordinates: (100, 100),
         prev: Vec::new(),
@@ -135,7 +138,14 @@ pub fn example() {
     svg_helper::insert_svg(&mut svg_schema, Connectable::Label(hi_label.clone()));
     svg_helper::insert_svg(&mut svg_schema, Connectable::PinPoint(angle.clone()));
 
-    svg_helper::save_and_draw_svg(&mut svg_schema);
+    let size = (WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32);
+    svg_helper::save_and_draw_svg(
+        &mut svg_schema,
+        &PlanMetadata::default(),
+        &Transform::flip_y(size.1),
+        size,
+        "plan.svg",
+    )?;
     // svg_helper::insert_svg(&mut svg_schema, Object::Node(node));
     let node_4 = Rc::new(Node {
         coordinates: (7, 0),
@@ -176,4 +186,6 @@ pub fn example() {
         node_4.get_circle(),
         node_4.get_lines()
     );
+
+    Ok(())
 }
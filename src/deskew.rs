@@ -0,0 +1,252 @@
+use image::{Rgb, RgbImage};
+use imageproc::geometric_transformations::{warp_into_with, Interpolation};
+use rayon::prelude::*;
+
+use crate::{
+    binarize::BinaryMask, config::DeskewConfig, shape_finder::Point, transform::Transform,
+};
+
+/// Most ink pixels the projection profiles are built from
+const MAX_SAMPLES: usize = 50_000;
+
+/// Rotation applied to a page to straighten it, kept to map the results back
+/// onto the original scan.
+///
+/// The straightened page is larger than the scan so that none of its corners
+/// are cut off, the rotated scan sits in its middle.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Deskew {
+    /// Clockwise rotation on screen in radians
+    pub angle: f32,
+    /// Centre of the rotation in pixels of the original scan
+    pub center: (f32, f32),
+    /// Shift of the rotated scan into the straightened page, in pixels
+    pub offset: (f32, f32),
+}
+
+/// `(x, y)` turned by `angle` around `center`, clockwise on screen
+fn rotate(angle: f32, center: (f32, f32), x: f32, y: f32) -> (f32, f32) {
+    let (s, c) = angle.sin_cos();
+    let (dx, dy) = (x - center.0, y - center.1);
+
+    (center.0 + c * dx - s * dy, center.1 + s * dx + c * dy)
+}
+
+/// Bounds of `size` pixels turned by `angle`
+fn rotated_size(angle: f32, size: (u32, u32)) -> (u32, u32) {
+    let (s, c) = (angle.sin().abs(), angle.cos().abs());
+    let (width, height) = (size.0 as f32, size.1 as f32);
+
+    // Float noise is rounded away first, so a straight page keeps its size
+    let fit = |v: f32| ((v * 1e3).round() / 1e3).ceil() as u32;
+    (fit(width * c + height * s), fit(width * s + height * c))
+}
+
+impl Deskew {
+    /// Rotation of a scan of `size` pixels by `angle`, onto a page large
+    /// enough to hold all of it
+    pub fn new(angle: f32, size: (u32, u32)) -> Self {
+        let (width, height) = (size.0 as f32, size.1 as f32);
+        let straight = rotated_size(angle, size);
+
+        Deskew {
+            angle,
+            center: (width / 2., height / 2.),
+            offset: (
+                (straight.0 as f32 - width) / 2.,
+                (straight.1 as f32 - height) / 2.,
+            ),
+        }
+    }
+
+    /// Size of the straightened page for a scan of `size` pixels
+    pub fn straight_size(&self, size: (u32, u32)) -> (u32, u32) {
+        rotated_size(self.angle, size)
+    }
+
+    /// Pixel of the straightened page the given pixel of the original scan ends up on
+    pub fn to_straight(&self, x: f32, y: f32) -> (f32, f32) {
+        let (x, y) = rotate(self.angle, self.center, x, y);
+        (x + self.offset.0, y + self.offset.1)
+    }
+
+    /// Pixel of the original scan the given pixel of the straightened page came from
+    pub fn to_original(&self, x: f32, y: f32) -> (f32, f32) {
+        rotate(
+            -self.angle,
+            self.center,
+            x - self.offset.0,
+            y - self.offset.1,
+        )
+    }
+
+    /// [`Deskew::to_original`] as a transform
+    pub fn straight_to_original(&self) -> Transform {
+        Transform::translation(-self.offset.0, -self.offset.1).then(&Transform::rotation_about(
+            -self.angle,
+            &Point(self.center.0, self.center.1),
+        ))
+    }
+}
+
+/// Angle in radians the lines of the page run at, positive when they fall to
+/// the right on screen.
+///
+/// Every candidate angle projects the ink onto an axis across the lines, the
+/// one where the ink piles up in the fewest rows wins.
+pub fn estimate_skew(mask: &BinaryMask, config: &DeskewConfig) -> f32 {
+    let (width, height) = (mask.width(), mask.height());
+
+    let ink: Vec<_> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|(x, y)| mask.is_ink(*x, *y))
+        .collect();
+
+    let step = ink.len().div_ceil(MAX_SAMPLES).max(1);
+    let samples: Vec<_> = ink
+        .iter()
+        .step_by(step)
+        .map(|(x, y)| (*x as f32, *y as f32))
+        .collect();
+
+    let steps = (config.max_angle / config.step).round() as i32;
+    let rows = (width + height) as usize * 2 + 1;
+
+    let score = |angle: f32| {
+        let (s, c) = angle.sin_cos();
+        let mut histogram = vec![0u64; rows];
+        for (x, y) in &samples {
+            let row = (y * c - x * s + width as f32).round() as usize;
+            histogram[row.min(rows - 1)] += 1;
+        }
+        histogram.iter().map(|n| n * n).sum::<u64>()
+    };
+
+    (-steps..=steps)
+        .into_par_iter()
+        .map(|k| {
+            let angle = (k as f32 * config.step).to_radians();
+            (angle, score(angle), k.abs())
+        })
+        // Straight pages stay untouched when nothing stands out
+        .max_by_key(|(_, score, k)| (*score, -k))
+        .map(|(angle, _, _)| angle)
+        .unwrap_or_default()
+}
+
+/// The page rotated so its lines run straight, `None` if it already is
+pub fn deskew(
+    img: &RgbImage,
+    mask: &BinaryMask,
+    config: &DeskewConfig,
+) -> Option<(RgbImage, Deskew)> {
+    let skew = estimate_skew(mask, config);
+
+    if skew.abs() < config.min_angle.to_radians() {
+        return None;
+    }
+
+    let deskew = Deskew::new(-skew, img.dimensions());
+    let (width, height) = deskew.straight_size(img.dimensions());
+
+    let mut straight = RgbImage::new(width, height);
+    warp_into_with(
+        img,
+        |x, y| deskew.to_original(x, y),
+        Interpolation::Bilinear,
+        Rgb([255, 255, 255]),
+        &mut straight,
+    );
+
+    Some((straight, deskew))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: (f32, f32), b: (f32, f32)) {
+        assert!(
+            (a.0 - b.0).abs() < 1e-2 && (a.1 - b.1).abs() < 1e-2,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn straight_page_keeps_its_size() {
+        let deskew = Deskew::new(0., (200, 100));
+
+        assert_eq!(deskew.straight_size((200, 100)), (200, 100));
+        assert_eq!(deskew.offset, (0., 0.));
+    }
+
+    #[test]
+    fn mapping_to_straight_and_back_is_the_identity() {
+        let deskew = Deskew::new(5_f32.to_radians(), (800, 600));
+
+        for p in [(0., 0.), (800., 0.), (412.5, 300.25), (0., 600.)] {
+            let (x, y) = deskew.to_straight(p.0, p.1);
+            assert_close(deskew.to_original(x, y), p);
+
+            let original = deskew.straight_to_original().apply(&Point(x, y));
+            assert_close((original.0, original.1), p);
+        }
+    }
+
+    #[test]
+    fn straightened_page_holds_every_corner_of_the_scan() {
+        let (width, height) = (400, 300);
+        for angle in [-5_f32, 3., 5.] {
+            let deskew = Deskew::new(angle.to_radians(), (width, height));
+            let (w, h) = deskew.straight_size((width, height));
+
+            assert!(w > width && h > height);
+            for (x, y) in [(0., 0.), (400., 0.), (400., 300.), (0., 300.)] {
+                let (x, y) = deskew.to_straight(x, y);
+                assert!(
+                    (-0.5..=w as f32 + 0.5).contains(&x) && (-0.5..=h as f32 + 0.5).contains(&y),
+                    "corner at ({}, {}) outside of {} x {}",
+                    x,
+                    y,
+                    w,
+                    h
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ink_in_a_corner_survives_straightening() {
+        // A skewed page with a long line to find the skew from and a block in
+        // the bottom right corner, where title blocks are
+        let (width, height) = (400_u32, 300_u32);
+        let slope = 4_f32.to_radians().tan();
+        let mut img = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+        for x in 20..380 {
+            let y = (150. + (x as f32 - 200.) * slope).round() as u32;
+            img.put_pixel(x, y, Rgb([0, 0, 0]));
+            img.put_pixel(x, y + 1, Rgb([0, 0, 0]));
+        }
+        for y in height - 12..height - 2 {
+            for x in width - 12..width - 2 {
+                img.put_pixel(x, y, Rgb([0, 0, 0]));
+            }
+        }
+        let mask = BinaryMask::from_fn(width, height, |x, y| img.get_pixel(x, y)[0] < 128);
+
+        let (straight, deskew) = deskew(&img, &mask, &DeskewConfig::default()).expect("a skew");
+
+        assert_eq!(
+            (straight.width(), straight.height()),
+            deskew.straight_size((width, height))
+        );
+        let (x, y) = deskew.to_straight((width - 7) as f32, (height - 7) as f32);
+        assert!(x >= 0. && y >= 0.);
+        assert_eq!(
+            straight.get_pixel(x.round() as u32, y.round() as u32),
+            &Rgb([0, 0, 0])
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+use nalgebra::{DMatrix, DVector, Matrix3, SymmetricEigen};
+use serde::Deserialize;
+
+use crate::transform::Transform;
+
+/// Coordinate reference system the control points are given in
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Crs {
+    /// Swiss LV95, easting and northing in metres (EPSG:2056)
+    Lv95,
+    /// Longitude and latitude in degrees (EPSG:4326)
+    Wgs84,
+}
+
+impl Crs {
+    pub fn epsg(&self) -> u32 {
+        match self {
+            Crs::Lv95 => 2056,
+            Crs::Wgs84 => 4326,
+        }
+    }
+}
+
+/// Kind of transform fitted to the control points
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransformModel {
+    /// Needs at least 3 control points
+    #[default]
+    Affine,
+    /// Needs at least 4 control points, for photographed or distorted plans
+    Projective,
+}
+
+impl TransformModel {
+    pub fn min_points(&self) -> usize {
+        match self {
+            TransformModel::Affine => 3,
+            TransformModel::Projective => 4,
+        }
+    }
+}
+
+/// Point of the sheet and the coordinates it lies at. The sheet doesn't change
+/// with the resolution the page is rendered at, unlike its pixels.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ControlPoint {
+    /// Millimetres from the top left corner of the sheet, y down, as in the
+    /// exported SVG
+    pub sheet: (f64, f64),
+    /// Easting and northing for LV95, longitude and latitude for WGS84
+    pub world: (f64, f64),
+}
+
+/// Control points file
+///
+/// ```toml
+/// crs = "LV95"
+/// model = "affine"
+///
+/// [[points]]
+/// sheet = [112.0, 640.5]
+/// world = [2611250.0, 1267430.0]
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ControlPoints {
+    pub crs: Crs,
+    #[serde(default)]
+    pub model: TransformModel,
+    pub points: Vec<ControlPoint>,
+}
+
+impl ControlPoints {
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read control points {}", path.display()))?;
+
+        toml::from_str(&content)
+            .with_context(|| format!("failed to load control points {}", path.display()))
+    }
+}
+
+/// Transform from the sheet to world coordinates fitted to control points
+#[derive(Clone, Debug, PartialEq)]
+pub struct Georeference {
+    pub crs: Crs,
+    /// From sheet millimetres with y down, see [`ControlPoint::sheet`]
+    pub svg_to_world: Transform,
+    /// Distance between where every control point is and where the
+    /// transform puts it, in world units
+    pub residuals: Vec<f64>,
+}
+
+impl Georeference {
+    pub fn rms(&self) -> f64 {
+        (self.residuals.iter().map(|r| r * r).sum::<f64>() / self.residuals.len() as f64).sqrt()
+    }
+
+    pub fn max(&self) -> f64 {
+        self.residuals.iter().cloned().fold(0., f64::max)
+    }
+}
+
+/// Moves the points to their centroid and scales them to a mean distance of
+/// √2 from it, so the fit doesn't suffer from coordinates in the millions
+fn normalization(points: &[(f64, f64)]) -> Matrix3<f64> {
+    let n = points.len() as f64;
+    let (cx, cy) = points
+        .iter()
+        .fold((0., 0.), |(x, y), p| (x + p.0 / n, y + p.1 / n));
+    let mean = points
+        .iter()
+        .map(|p| ((p.0 - cx).powi(2) + (p.1 - cy).powi(2)).sqrt())
+        .sum::<f64>()
+        / n;
+    let s = match mean > 0. {
+        true => std::f64::consts::SQRT_2 / mean,
+        false => 1.,
+    };
+
+    Matrix3::new(s, 0., -s * cx, 0., s, -s * cy, 0., 0., 1.)
+}
+
+/// Whether normalised points spread over the plane rather than along a line
+fn spans_plane(points: &[(f64, f64)]) -> bool {
+    let n = points.len() as f64;
+    let (xx, xy, yy) = points.iter().fold((0., 0., 0.), |(xx, xy, yy), (x, y)| {
+        (xx + x * x / n, xy + x * y / n, yy + y * y / n)
+    });
+
+    xx * yy - xy * xy > COLLINEAR_EPSILON
+}
+
+/// Determinant of the scatter of normalised points below which they count
+/// as collinear, a square of them has 1
+const COLLINEAR_EPSILON: f64 = 1e-6;
+
+fn apply(m: &Matrix3<f64>, p: (f64, f64)) -> (f64, f64) {
+    Transform(*m).apply_f64(p.0, p.1)
+}
+
+/// Least squares affine transform between normalised points
+fn fit_affine(from: &[(f64, f64)], to: &[(f64, f64)]) -> Option<Matrix3<f64>> {
+    let a = DMatrix::from_fn(from.len(), 3, |i, j| match j {
+        0 => from[i].0,
+        1 => from[i].1,
+        _ => 1.,
+    });
+    let svd = a.svd(true, true);
+
+    let x = svd
+        .solve(
+            &DVector::from_iterator(to.len(), to.iter().map(|p| p.0)),
+            1e-12,
+        )
+        .ok()?;
+    let y = svd
+        .solve(
+            &DVector::from_iterator(to.len(), to.iter().map(|p| p.1)),
+            1e-12,
+        )
+        .ok()?;
+
+    Some(Matrix3::new(x[0], x[1], x[2], y[0], y[1], y[2], 0., 0., 1.))
+}
+
+/// Homography between normalised points by the direct linear transform, the
+/// null vector of the equations found as the eigenvector of the smallest
+/// eigenvalue of their normal matrix
+fn fit_projective(from: &[(f64, f64)], to: &[(f64, f64)]) -> Option<Matrix3<f64>> {
+    let mut a = DMatrix::zeros(from.len() * 2, 9);
+    for (i, ((x, y), (u, v))) in from.iter().zip(to).enumerate() {
+        let rows = [
+            [-x, -y, -1., 0., 0., 0., u * x, u * y, *u],
+            [0., 0., 0., -x, -y, -1., v * x, v * y, *v],
+        ];
+        for (k, row) in rows.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                a[(2 * i + k, j)] = *value;
+            }
+        }
+    }
+
+    let eigen = SymmetricEigen::new(a.transpose() * a);
+    let smallest = eigen.eigenvalues.imin();
+    let h = eigen.eigenvectors.column(smallest);
+
+    (h[8].abs() > f64::EPSILON).then(|| Matrix3::from_row_slice(h.as_slice()) / h[8])
+}
+
+/// Fits the transform of the model to the control points
+pub fn fit(control_points: &ControlPoints) -> anyhow::Result<Georeference> {
+    let model = control_points.model;
+    let points = &control_points.points;
+
+    ensure!(
+        points.len() >= model.min_points(),
+        "{:?} georeferencing needs at least {} control points, got {}",
+        model,
+        model.min_points(),
+        points.len()
+    );
+
+    let sheet: Vec<_> = points.iter().map(|p| p.sheet).collect();
+    let world: Vec<_> = points.iter().map(|p| p.world).collect();
+
+    let (from_norm, to_norm) = (normalization(&sheet), normalization(&world));
+    let from: Vec<_> = sheet.iter().map(|p| apply(&from_norm, *p)).collect();
+    let to: Vec<_> = world.iter().map(|p| apply(&to_norm, *p)).collect();
+
+    ensure!(
+        spans_plane(&from) && spans_plane(&to),
+        "the control points lie on a line, they don't define a transform"
+    );
+
+    let fitted = match model {
+        TransformModel::Affine => fit_affine(&from, &to),
+        TransformModel::Projective => fit_projective(&from, &to),
+    };
+
+    let matrix = fitted
+        .zip(to_norm.try_inverse())
+        .map(|(fitted, to_back)| to_back * fitted * from_norm)
+        .filter(|m| m.try_inverse().is_some() && m.iter().all(|v| v.is_finite()))
+        .context("the control points don't define a transform, are they collinear?")?;
+
+    let svg_to_world = Transform(matrix);
+    let residuals = points
+        .iter()
+        .map(|p| {
+            let (x, y) = svg_to_world.apply_f64(p.sheet.0, p.sheet.1);
+            ((x - p.world.0).powi(2) + (y - p.world.1).powi(2)).sqrt()
+        })
+        .collect();
+
+    Ok(Georeference {
+        crs: control_points.crs,
+        svg_to_world,
+        residuals,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{deskew::Deskew, transform::PageFrames};
+
+    fn control_points(
+        model: TransformModel,
+        sheet: &[(f64, f64)],
+        to_world: impl Fn((f64, f64)) -> (f64, f64),
+    ) -> ControlPoints {
+        ControlPoints {
+            crs: Crs::Lv95,
+            model,
+            points: sheet
+                .iter()
+                .map(|p| ControlPoint {
+                    sheet: *p,
+                    world: to_world(*p),
+                })
+                .collect(),
+        }
+    }
+
+    /// Millimetres on an A0 sheet of 1189 × 841
+    const SHEET: [(f64, f64); 5] = [
+        (30., 60.),
+        (1189., 75.),
+        (1160., 841.),
+        (45., 810.),
+        (600., 450.),
+    ];
+
+    /// A 1:500 plan turned by 2°, y flipped to north up, in LV95
+    fn lv95((x, y): (f64, f64)) -> (f64, f64) {
+        let (s, c) = 2_f64.to_radians().sin_cos();
+        (
+            2_611_000. + 0.5 * (c * x - s * y),
+            1_267_000. - 0.5 * (s * x + c * y),
+        )
+    }
+
+    fn assert_maps(georeference: &Georeference, to_world: impl Fn((f64, f64)) -> (f64, f64)) {
+        for p in [(0., 0.), (370.5, 296.25), (1189., 841.)] {
+            let found = georeference.svg_to_world.apply_f64(p.0, p.1);
+            let expected = to_world(p);
+            assert!(
+                (found.0 - expected.0).abs() < 1e-4 && (found.1 - expected.1).abs() < 1e-4,
+                "{:?} maps to {:?} instead of {:?}",
+                p,
+                found,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn affine_fit_recovers_an_lv95_transform() {
+        let georeference = fit(&control_points(TransformModel::Affine, &SHEET, lv95)).unwrap();
+
+        assert_eq!(georeference.crs, Crs::Lv95);
+        assert!(georeference.svg_to_world.is_affine());
+        assert!(georeference.max() < 1e-6, "{:?}", georeference.residuals);
+        assert_maps(&georeference, lv95);
+    }
+
+    #[test]
+    fn affine_fit_spreads_noise_over_the_residuals() {
+        let noisy = |p: (f64, f64)| {
+            let (x, y) = lv95(p);
+            let noise = if p.0 > 900. { 0.1 } else { -0.1 };
+            (x + noise, y)
+        };
+
+        let georeference = fit(&control_points(TransformModel::Affine, &SHEET, noisy)).unwrap();
+
+        assert!(georeference.rms() > 0.01 && georeference.max() < 0.2);
+    }
+
+    #[test]
+    fn projective_fit_recovers_a_homography_from_four_points() {
+        let h = Matrix3::new(0.9, 0.05, 10., 0.02, 1.1, 20., 1e-4, 2e-4, 1.);
+        let homography = |p: (f64, f64)| Transform(h).apply_f64(p.0, p.1);
+
+        let georeference = fit(&control_points(
+            TransformModel::Projective,
+            &SHEET[..4],
+            homography,
+        ))
+        .unwrap();
+
+        assert!(!georeference.svg_to_world.is_affine());
+        assert!(georeference.max() < 1e-6, "{:?}", georeference.residuals);
+        assert_maps(&georeference, homography);
+    }
+
+    #[test]
+    fn collinear_points_are_rejected() {
+        let collinear = [(0., 0.), (100., 50.), (300., 150.), (400., 200.)];
+
+        for model in [TransformModel::Affine, TransformModel::Projective] {
+            let result = fit(&control_points(model, &collinear, lv95));
+
+            assert!(result.is_err(), "{:?} fit {:?}", model, result);
+        }
+    }
+
+    #[test]
+    fn too_few_points_are_rejected() {
+        let result = fit(&control_points(
+            TransformModel::Projective,
+            &SHEET[..3],
+            lv95,
+        ));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn normalization_centres_the_points() {
+        let world: Vec<_> = SHEET.iter().map(|p| lv95(*p)).collect();
+        let m = normalization(&world);
+
+        let moved: Vec<_> = world.iter().map(|p| apply(&m, *p)).collect();
+        let n = moved.len() as f64;
+        let centre = moved
+            .iter()
+            .fold((0., 0.), |(x, y), p| (x + p.0 / n, y + p.1 / n));
+        let mean = moved.iter().map(|p| p.0.hypot(p.1)).sum::<f64>() / n;
+
+        assert!(centre.0.abs() < 1e-9 && centre.1.abs() < 1e-9);
+        assert!((mean - std::f64::consts::SQRT_2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn world_coordinates_do_not_depend_on_the_render_width() {
+        let georeference = fit(&control_points(TransformModel::Affine, &SHEET, lv95)).unwrap();
+        // A0 landscape in PDF points
+        let page_size = (3370.39, 2383.94);
+
+        let corners: Vec<_> = [(1600, 1131), (4800, 3394)]
+            .into_iter()
+            .map(|image_size| {
+                let mut frames = PageFrames::new(image_size, page_size, &Deskew::default());
+                frames.sheet_to_world =
+                    Some(frames.sheet_to_svg().then(&georeference.svg_to_world));
+                let to_world = frames.shapes_to_world().unwrap();
+                let (width, height) = (image_size.0 as f64, image_size.1 as f64);
+
+                to_world.apply_f64(width, height)
+            })
+            .collect();
+
+        assert!(
+            (corners[0].0 - corners[1].0).abs() < 0.01
+                && (corners[0].1 - corners[1].1).abs() < 0.01,
+            "{:?}",
+            corners
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use image::{Rgb, RgbImage};
+use imageproc::drawing::draw_hollow_circle_mut;
+
+use crate::binarize::BinaryMask;
+
+pub enum EasyColor {
+    Red,
+    Green,
+}
+
+pub struct CircleCoordinates {
+    pub x: i32,
+    pub y: i32,
+    pub radius: i32,
+    pub width: u8,
+    pub color: EasyColor,
+}
+
+pub fn draw_circles(img: &mut RgbImage, circle_coordinates_list: &[CircleCoordinates]) {
+    for circle_coordinates in circle_coordinates_list {
+        let color_rgba = match circle_coordinates.color {
+            EasyColor::Green => Rgb([0, 255, 0]),
+            EasyColor::Red => Rgb([255, 0, 0]),
+        };
+
+        for i in 0..circle_coordinates.width {
+            draw_hollow_circle_mut(
+                img,
+                (circle_coordinates.x, circle_coordinates.y),
+                circle_coordinates.radius + i as i32,
+                color_rgba,
+            );
+        }
+    }
+}
+
+/// Marks every pixel still left as ink in the mask red
+pub fn mark_unresolved_pixels(image: &mut RgbImage, mask: &BinaryMask) {
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            if mask.is_ink(x, y) {
+                mark_pixel(image, x, y);
+            }
+        }
+    }
+}
+
+fn mark_pixel(image: &mut RgbImage, x: u32, y: u32) {
+    let color = Rgb([255, 0, 0]);
+    image.put_pixel(x, y, color);
+}
+
+pub fn is_white_pixel(r: u8, g: u8, b: u8) -> bool {
+    (r, g, b) == (255, 255, 255)
+}
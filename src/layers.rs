@@ -0,0 +1,274 @@
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+
+use crate::{binarize::BinaryMask, config::LayersConfig};
+
+const NO_CLUSTER: u8 = u8::MAX;
+
+/// Most ink pixels sampled for the clustering, the rest only gets assigned
+const MAX_SAMPLES: usize = 50_000;
+
+const ITERATIONS: usize = 10;
+
+/// Ink pixels grouped by colour, so shapes drawn in different colours can be
+/// told apart and mapped to the layers of the config
+#[derive(Clone, Debug)]
+pub struct ColorLayers {
+    width: u32,
+    clusters: Vec<u8>,
+    /// Average colour of every cluster
+    pub palette: Vec<Rgb<u8>>,
+    config: LayersConfig,
+}
+
+/// Weight of differences in lightness against differences in hue. The edges
+/// of ink blend into the paper, so they differ from the ink mostly in lightness.
+const LIGHTNESS_WEIGHT: f32 = 0.2;
+
+/// Squared distance between two colours, on a lightness axis and two
+/// opponent colour axes, red against green and yellow against blue
+fn distance_2(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    let d = [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+
+    let lightness = (d[0] + d[1] + d[2]) / 3. * LIGHTNESS_WEIGHT;
+    let red_green = d[0] - d[1];
+    let yellow_blue = (d[0] + d[1]) / 2. - d[2];
+
+    lightness.powi(2) + red_green.powi(2) + yellow_blue.powi(2)
+}
+
+fn nearest(centroids: &[[f32; 3]], c: &[f32; 3]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .min_by(|a, b| distance_2(a.1, c).total_cmp(&distance_2(b.1, c)))
+        .map(|(i, _)| i)
+        .unwrap_or_default()
+}
+
+fn to_f32(c: &Rgb<u8>) -> [f32; 3] {
+    [c[0] as f32, c[1] as f32, c[2] as f32]
+}
+
+/// k-means with farthest point initialisation, so rare but distinct colours
+/// like a few red signals still get their own cluster. Clusters are only added
+/// while the farthest colour is at least `min_distance` from every centroid.
+fn kmeans(samples: &[[f32; 3]], k: usize, min_distance: f32) -> Vec<[f32; 3]> {
+    if samples.is_empty() || k == 0 {
+        return vec![];
+    }
+
+    let mean = samples.iter().fold([0.; 3], |acc, c| {
+        [acc[0] + c[0], acc[1] + c[1], acc[2] + c[2]]
+    });
+    let n = samples.len() as f32;
+    let mut centroids = vec![[mean[0] / n, mean[1] / n, mean[2] / n]];
+
+    while centroids.len() < k {
+        let farthest = samples
+            .par_iter()
+            .map(|c| distance_2(&centroids[nearest(&centroids, c)], c))
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .filter(|(_, d)| *d > 0. && *d >= min_distance.powi(2));
+
+        match farthest {
+            Some((i, _)) => centroids.push(samples[i]),
+            None => break,
+        }
+    }
+
+    let mut counts = vec![];
+    for _ in 0..ITERATIONS {
+        let sums = samples
+            .par_iter()
+            .fold(
+                || vec![([0f32; 3], 0usize); centroids.len()],
+                |mut sums, c| {
+                    let i = nearest(&centroids, c);
+                    sums[i].0[0] += c[0];
+                    sums[i].0[1] += c[1];
+                    sums[i].0[2] += c[2];
+                    sums[i].1 += 1;
+                    sums
+                },
+            )
+            .reduce(
+                || vec![([0f32; 3], 0usize); centroids.len()],
+                |mut a, b| {
+                    for (a, b) in a.iter_mut().zip(b) {
+                        a.0[0] += b.0[0];
+                        a.0[1] += b.0[1];
+                        a.0[2] += b.0[2];
+                        a.1 += b.1;
+                    }
+                    a
+                },
+            );
+
+        let clusters: Vec<_> = sums.into_iter().filter(|(_, count)| *count > 0).collect();
+        counts = clusters.iter().map(|(_, count)| *count as f32).collect();
+        centroids = clusters
+            .into_iter()
+            .map(|(sum, count)| {
+                let count = count as f32;
+                [sum[0] / count, sum[1] / count, sum[2] / count]
+            })
+            .collect();
+    }
+
+    // Centroids that converged closer than `min_distance` are one colour
+    loop {
+        let closest = (0..centroids.len())
+            .flat_map(|i| (i + 1..centroids.len()).map(move |j| (i, j)))
+            .map(|(i, j)| (i, j, distance_2(&centroids[i], &centroids[j])))
+            .filter(|(_, _, d)| *d < min_distance.powi(2))
+            .min_by(|a, b| a.2.total_cmp(&b.2));
+        let Some((i, j, _)) = closest else {
+            break;
+        };
+
+        let (a, b) = (counts[i], counts.remove(j));
+        let merged = centroids.remove(j);
+        centroids[i] = std::array::from_fn(|c| (centroids[i][c] * a + merged[c] * b) / (a + b));
+        counts[i] = a + b;
+    }
+
+    centroids
+}
+
+impl ColorLayers {
+    pub fn new(img: &RgbImage, mask: &BinaryMask, config: &LayersConfig) -> Self {
+        let ink: Vec<_> = img
+            .enumerate_pixels()
+            .filter(|(x, y, _)| mask.is_ink(*x, *y))
+            .map(|(_, _, c)| to_f32(c))
+            .collect();
+
+        let step = ink.len().div_ceil(MAX_SAMPLES).max(1);
+        let samples: Vec<_> = ink.iter().step_by(step).cloned().collect();
+
+        let centroids = kmeans(&samples, config.clusters.max(1), config.min_distance);
+
+        let clusters = (0..img.width() * img.height())
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (i % img.width(), i / img.width());
+                match mask.is_ink(x, y) {
+                    true => nearest(&centroids, &to_f32(img.get_pixel(x, y))) as u8,
+                    false => NO_CLUSTER,
+                }
+            })
+            .collect();
+
+        ColorLayers {
+            width: img.width(),
+            clusters,
+            palette: centroids
+                .iter()
+                .map(|c| Rgb([c[0].round() as u8, c[1].round() as u8, c[2].round() as u8]))
+                .collect(),
+            config: config.clone(),
+        }
+    }
+
+    /// Colour of the cluster the pixel belongs to, `None` for background
+    pub fn color_at(&self, x: u32, y: u32) -> Option<Rgb<u8>> {
+        match self.clusters[(y * self.width + x) as usize] {
+            NO_CLUSTER => None,
+            c => Some(self.palette[c as usize]),
+        }
+    }
+
+    /// Colour most of the given pixels have, black if none of them is ink
+    pub fn dominant_color(&self, pixels: impl IntoIterator<Item = (u32, u32)>) -> Rgb<u8> {
+        let mut counts = vec![0usize; self.palette.len()];
+        for (x, y) in pixels {
+            let c = self.clusters[(y * self.width + x) as usize];
+            if c != NO_CLUSTER {
+                counts[c as usize] += 1;
+            }
+        }
+
+        counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .max_by_key(|(_, count)| **count)
+            .map(|(i, _)| self.palette[i])
+            .unwrap_or(Rgb([0, 0, 0]))
+    }
+
+    /// Name of the configured layer the colour belongs to
+    pub fn layer_of(&self, color: &Rgb<u8>) -> &str {
+        self.config.layer_of(color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Strokes in the given colours, each one ten rows high, on white paper
+    fn strokes(colors: &[Rgb<u8>]) -> (RgbImage, BinaryMask) {
+        let img = RgbImage::from_fn(100, 10 * colors.len() as u32, |_, y| match y % 10 {
+            0 | 9 => Rgb([255, 255, 255]),
+            _ => colors[y as usize / 10],
+        });
+        let mask = BinaryMask::from_fn(img.width(), img.height(), |x, y| {
+            img.get_pixel(x, y) != &Rgb([255, 255, 255])
+        });
+
+        (img, mask)
+    }
+
+    #[test]
+    fn black_ink_with_grey_edges_is_one_layer() {
+        let (img, mask) = strokes(&[
+            Rgb([0, 0, 0]),
+            Rgb([20, 20, 20]),
+            Rgb([90, 90, 90]),
+            Rgb([160, 160, 160]),
+            Rgb([210, 210, 210]),
+        ]);
+
+        let layers = ColorLayers::new(&img, &mask, &LayersConfig::default());
+
+        assert_eq!(layers.palette.len(), 1, "{:?}", layers.palette);
+        assert_eq!(layers.color_at(0, 1), Some(layers.palette[0]));
+        assert_eq!(layers.color_at(0, 0), None);
+    }
+
+    #[test]
+    fn black_and_red_ink_are_two_layers() {
+        let (img, mask) = strokes(&[
+            Rgb([0, 0, 0]),
+            Rgb([120, 120, 120]),
+            Rgb([230, 10, 10]),
+            Rgb([200, 30, 20]),
+        ]);
+
+        let layers = ColorLayers::new(&img, &mask, &LayersConfig::default());
+
+        assert_eq!(layers.palette.len(), 2, "{:?}", layers.palette);
+        let (black, red) = (layers.color_at(0, 1), layers.color_at(0, 21));
+        assert_ne!(black, red);
+        assert_eq!(layers.color_at(0, 11), black);
+        assert_eq!(layers.color_at(0, 31), red);
+
+        let red = red.unwrap();
+        assert!(red[0] > 200 && red[1] < 30, "{:?}", red);
+        assert_eq!(layers.dominant_color((0..100).map(|x| (x, 25))), red);
+    }
+
+    #[test]
+    fn every_colour_gets_a_cluster_without_a_minimum_distance() {
+        let (img, mask) = strokes(&[Rgb([0, 0, 0]), Rgb([90, 90, 90])]);
+        let config = LayersConfig {
+            min_distance: 0.,
+            ..LayersConfig::default()
+        };
+
+        assert_eq!(ColorLayers::new(&img, &mask, &config).palette.len(), 2);
+    }
+}
@@ -0,0 +1,20 @@
+pub mod binarize;
+pub mod config;
+pub mod correction;
+pub mod data_structures;
+pub mod deskew;
+pub mod georeference;
+pub mod image_helper;
+pub mod layers;
+pub mod metadata;
+mod pipeline;
+pub mod plan_graph;
+pub mod render_svg;
+pub mod shape_finder;
+pub mod sheet;
+pub mod spatial_index;
+pub mod svg_helper;
+pub mod transform;
+
+pub use config::PipelineConfig;
+pub use pipeline::{Pipeline, RenderedPage, Topology, TopologyEdge, TopologyNode};
@@ -1,261 +1,227 @@
-use data_structures::{Connectable, Node, PinPoint};
-use draw::{model, update, view, WINDOW_HEIGHT, WINDOW_WIDTH};
-use image::Rgb;
-use imageproc::drawing::draw_hollow_circle_mut;
-
-use nannou::{color::rgba, glam::Vec2};
-use svg::node::element::Rectangle;
-use svg::Document;
-
-use svg::node::element;
-
-use image::RgbImage;
-use pdfium_render::prelude::*;
-use std::{
-    cell::RefCell,
-    future,
-    path::{Path, PathBuf},
-    rc::Rc,
+use draw::{event, model, update, view, WINDOW_HEIGHT, WINDOW_WIDTH};
+use hackaton_railify::{
+    correction::Patch,
+    georeference::{self, ControlPoints},
+    plan_graph::{GraphEditor, PlanGraph},
+    Pipeline, PipelineConfig,
 };
 
-use clap::Parser;
-
-mod data_structures;
-mod shape_finder;
-mod svg_helper;
-
-enum EasyColor {
-    Red,
-    Green,
-}
-
-struct CircleCoordinates {
-    x: i32,
-    y: i32,
-    radius: i32,
-    width: u8,
-    color: EasyColor,
-}
-
-fn draw_circles(img: &mut RgbImage, circle_coordinates_list: &[CircleCoordinates]) {
-    for circle_coordinates in circle_coordinates_list {
-        let color_rgba = match circle_coordinates.color {
-            EasyColor::Green => Rgb([0, 255, 0]),
-            EasyColor::Red => Rgb([255, 0, 0]),
-        };
+use std::{future, path::PathBuf};
 
-        for i in 0..circle_coordinates.width {
-            draw_hollow_circle_mut(
-                img,
-                (circle_coordinates.x, circle_coordinates.y),
-                circle_coordinates.radius + i as i32,
-                color_rgba,
-            );
-        }
-    }
-}
+use clap::Parser;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// Plan PDF, or a scanned page in any format supported by `image`
     #[arg(short, long)]
     input: PathBuf,
 
-    /// Page number
-    #[arg(short, long, default_value_t = 0)]
-    page: u32,
+    /// Page number, overrides the one in the config
+    #[arg(short, long)]
+    page: Option<u32>,
 
-    /// Rerender
+    /// Seconds each stage of the detection is shown for in the viewer
     #[arg(short, long, default_value_t = 1.0)]
     render_interval: f64,
+
+    /// Detect shapes on every page in parallel, the viewer still shows `--page`
+    #[arg(long, default_value_t = false)]
+    all_pages: bool,
+
+    /// TOML file with the pipeline settings
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Profile of the config file to apply on top of its defaults
+    #[arg(long, requires = "config")]
+    profile: Option<String>,
+
+    /// TOML file with control points, georeferences the page and writes GeoJSON
+    #[arg(long)]
+    control_points: Option<PathBuf>,
+
+    /// Graph saved from the viewer to correct further instead of the
+    /// detected one, saved back to the same file
+    #[arg(long, conflicts_with = "corrections")]
+    graph: Option<PathBuf>,
+
+    /// Corrections saved from the viewer to replay on the detected graph,
+    /// further corrections are saved back to the same file
+    #[arg(long)]
+    corrections: Option<PathBuf>,
+
+    /// Renders the shapes as the viewer shows them into a PNG, without a window
+    #[arg(long)]
+    snapshot: Option<PathBuf>,
+
+    /// Width and height of the snapshot in pixels
+    #[arg(long, num_args = 2, value_names = ["WIDTH", "HEIGHT"], default_values_t = [1600, 1600])]
+    snapshot_size: Vec<u32>,
+
+    /// Rasterises the SVG, compares it with the page and writes a heatmap of
+    /// where they differ
+    #[arg(long, default_value_t = false)]
+    render_svg: bool,
+
+    /// Writes the exports and the snapshot without opening the viewer
+    #[arg(long, default_value_t = false)]
+    headless: bool,
 }
 
 fn main() -> anyhow::Result<()> {
-    // data_structures::example();
+    // data_structures::example()?;
 
     let args = Args::parse();
 
-    let mut images = pdf_images(&args.input, None)?;
-    let img = &mut images[args.page as usize];
-
-    let lines = shape_finder::shapes_from_image(img);
-
-    mark_all_unresolved_pixels(img);
-    images[args.page as usize]
-        .save("non-resolved-parts.png")
-        .expect("Failed to save image");
-
-    let shapes: Vec<_> = lines
-        .clone()
-        .into_iter()
-        .flat_map(|s| match s {
-            shape_finder::Shape::Line(l) => vec![draw::Shape::Line {
-                start: l.start.into(),
-                end: l.end.into(),
-                color: rgba(0., 0., 0., 1.),
-                weight: l.thickness,
-            }],
-            shape_finder::Shape::Point(p, prevs, pinpoint) if pinpoint => {
-                vec![draw::Shape::Circle {
-                    position: Vec2::new(p.0 as _, p.1 as _),
-                    color: rgba(0., 255., 0., 1.),
-                    radius: 10.,
-                }]
-            }
-            shape_finder::Shape::Point(p, prevs, _) => {
-                vec![draw::Shape::Circle {
-                    position: Vec2::new(p.0 as _, p.1 as _),
-                    color: rgba(0., 0., 255., 1.),
-                    radius: 10.,
-                }]
-            }
-            shape_finder::Shape::Custom(pixels) => pixels
-                .iter()
-                .map(|p| draw::Shape::Point {
-                    position: Vec2::new(p.0 as _, p.1 as _),
-                    color: rgba(255., 0., 0., 1.),
-                })
-                .collect(),
-        })
-        .collect();
-
-    let mut nodes: Vec<_> = lines
-        .clone()
-        .into_iter()
-        .filter_map(|s| match s {
-            shape_finder::Shape::Point(p, prevs, _) => Some((
-                Rc::new(RefCell::new(Node {
-                    coordinates: (p.0 as _, p.1 as _),
-                    prev: vec![],
-                })),
-                prevs,
-            )),
-            _ => None,
-        })
-        .collect();
-
-    let node_clone = nodes.clone();
-
-    for node in &mut nodes {
-        node.0.borrow_mut().prev = node_clone
-            .iter()
-            .filter(|other| {
-                node.1
-                    .iter()
-                    .find(|prev| {
-                        prev.0 as i32 == other.0.borrow().coordinates.0
-                            && prev.1 as i32 == other.0.borrow().coordinates.1
-                    })
-                    .is_some()
-            })
-            .map(|o| Connectable::Node(o.0.clone()))
-            .collect()
+    let mut config = match &args.config {
+        Some(path) => PipelineConfig::from_file(path, args.profile.as_deref())?,
+        None => PipelineConfig::default(),
+    };
+    if let Some(page) = args.page {
+        config.page = page;
     }
+    let export = config.export.clone();
 
-    /*
-    let pinpoints: Vec<_> = lines
-        .iter()
-        .filter_map(|s| match s {
-            shape_finder::Shape::Point(p, prevs, pinpoint) if *pinpoint => {
-                Some(Connectable::PinPoint(Rc::new(PinPoint {
-                    coordinates: (p.0 as _, p.1 as _),
-                    prev: Connectable::Node(
-                        nodes
-                            .iter()
-                            .find(|el| el.0.borrow().coordinates.0 == prevs[0].0 as i32)
-                            .unwrap()
-                            .0
-                            .clone(),
-                    ),
-                })))
-            }
-            _ => None,
-        })
-        .collect();
-     */
-
-    let mut svg_schema: Vec<element::Element> = Vec::new();
+    let pipeline = Pipeline::new(config);
 
-    for node in nodes {
-        svg_helper::insert_svg(&mut svg_schema, Connectable::Node(node.0));
-    }
-    svg_helper::save_and_draw_svg(&mut svg_schema);
+    let is_pdf = args
+        .input
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("pdf"));
 
-    nannou::app::Builder::new_async(move |app| {
-        Box::new(future::ready(model(app, shapes, args.render_interval)))
-    })
-    .update(update)
-    .simple_window(view)
-    .size(WINDOW_WIDTH, WINDOW_HEIGHT)
-    .run();
+    let mut topology = if !is_pdf {
+        let topology = pipeline.process_image_file(&args.input)?;
 
-    Ok(())
-}
+        topology.save_unresolved(&export.unresolved_path)?;
 
-fn mark_all_unresolved_pixels(image: &mut RgbImage) {
-    for y in 0..image.height() {
-        for x in 0..image.width() {
-            let pixel = image.get_pixel(x, y);
-            let (r, g, b) = (pixel[0], pixel[1], pixel[2]);
+        topology
+    } else if args.all_pages {
+        let mut pages = pipeline.process_pdf_pages(&args.input)?;
 
-            if !is_white_pixel(r, g, b) {
-                mark_pixel(image, x, y);
-            }
+        for (num, page) in pages.iter().enumerate() {
+            let mut path = export.unresolved_path.clone();
+            path.set_file_name(format!(
+                "{}-{}.{}",
+                path.file_stem().unwrap_or_default().to_string_lossy(),
+                num,
+                path.extension().unwrap_or_default().to_string_lossy()
+            ));
+            page.save_unresolved(path)?;
         }
-    }
-}
 
-fn mark_pixel(image: &mut RgbImage, x: u32, y: u32) {
-    let color = Rgb([255, 0, 0]);
-    image.put_pixel(x, y, color);
-}
+        let page = pipeline.config().page as usize;
+        anyhow::ensure!(
+            page < pages.len(),
+            "page {} does not exist, the document has {} pages",
+            page,
+            pages.len()
+        );
 
-fn is_white_pixel(r: u8, g: u8, b: u8) -> bool {
-    (r, g, b) == (255, 255, 255)
-}
+        pages.swap_remove(page)
+    } else {
+        let topology = pipeline.process_pdf(&args.input)?;
 
-fn convert_image_to_svg(image: &RgbImage) -> Document {
-    let mut svg = Document::new();
-    for y in 0..image.height() {
-        for x in 0..image.width() {
-            let pixel = image.get_pixel(x, y);
-            let (r, g, b) = (pixel[0], pixel[1], pixel[2]);
+        topology.save_unresolved(&export.unresolved_path)?;
 
-            if is_white_pixel(r, g, b) {
-                continue;
-            }
+        topology
+    };
 
-            let rect = Rectangle::new()
-                .set("x", x as i32)
-                .set("y", y as i32)
-                .set("width", 1)
-                .set("height", 1)
-                .set("fill", format!("rgb({},{},{})", r, g, b));
+    topology.save_svg(&export.svg_path)?;
+    topology.save_json(&export.graph_path)?;
 
-            svg = svg.add(rect);
-        }
+    if args.render_svg {
+        let diff = topology.render_svg(&export.svg_path, export.diff_tolerance)?;
+        topology
+            .metadata
+            .save_png(&diff.heatmap, &export.heatmap_path)?;
+        println!(
+            "SVG similarity {:.3}: precision {:.3}, recall {:.3}",
+            diff.similarity, diff.precision, diff.recall
+        );
     }
-    svg
-}
 
-fn pdf_images(
-    path: &impl AsRef<Path>,
-    password: Option<&str>,
-) -> Result<Vec<RgbImage>, PdfiumError> {
-    let pdfium = Pdfium::default();
+    if let Some(path) = &args.control_points {
+        let georeference = georeference::fit(&ControlPoints::from_file(path)?)?;
+
+        for (i, residual) in georeference.residuals.iter().enumerate() {
+            println!("Control point {}: residual {:.3}", i, residual);
+        }
+        println!(
+            "Residuals in {:?} units: RMS {:.3}, max {:.3}",
+            georeference.crs,
+            georeference.rms(),
+            georeference.max()
+        );
 
-    let document = pdfium.load_pdf_from_file(path, password)?;
+        topology.set_georeference(georeference);
+        topology.save_geojson(&export.geojson_path)?;
+    }
 
-    let render_config = PdfRenderConfig::new().set_target_width(800);
+    let stages = topology.draw_stages();
+    let backgrounds = topology.draw_backgrounds();
+    let editor = match (&args.graph, &args.corrections) {
+        (Some(path), _) => GraphEditor {
+            graph: PlanGraph::load(path)?,
+            path: path.clone(),
+            // The corrections made so far continue
+            patch: match export.corrections_path.exists() {
+                true => Patch::load(&export.corrections_path)?,
+                false => Patch::default(),
+            },
+            patch_path: export.corrections_path.clone(),
+            anchoring: pipeline.anchoring(&topology),
+        },
+        (None, Some(patch_path)) => {
+            let patch = Patch::load(patch_path)?;
+            let (graph, report) = pipeline.correct(&topology, &patch);
+            println!("{}", report);
+            graph.save(&export.corrected_graph_path)?;
+
+            GraphEditor {
+                graph,
+                path: export.corrected_graph_path.clone(),
+                patch,
+                patch_path: patch_path.clone(),
+                anchoring: pipeline.anchoring(&topology),
+            }
+        }
+        (None, None) => GraphEditor {
+            graph: PlanGraph::from(&topology),
+            path: export.corrected_graph_path.clone(),
+            patch: Patch::default(),
+            patch_path: export.corrections_path.clone(),
+            anchoring: pipeline.anchoring(&topology),
+        },
+    };
+
+    if let Some(path) = &args.snapshot {
+        // The corrected graph once there is one
+        let shapes = match args.graph.is_some() || args.corrections.is_some() {
+            true => editor.graph.draw_shapes(),
+            false => topology.draw_shapes(),
+        };
+        draw::offscreen::save_png(&shapes, args.snapshot_size[0], args.snapshot_size[1], path)?;
+    }
 
-    let mut images = vec![];
-    for (_, page) in document.pages().iter().enumerate() {
-        images.push(
-            page.render_with_config(&render_config)?
-                .as_image()
-                .into_rgb8(),
-        );
+    if args.headless {
+        return Ok(());
     }
 
-    Ok(images)
+    nannou::app::Builder::new_async(move |app| {
+        Box::new(future::ready(model(
+            app,
+            stages,
+            backgrounds,
+            args.render_interval,
+            Some(Box::new(editor)),
+        )))
+    })
+    .update(update)
+    .event(event)
+    .simple_window(view)
+    .size(WINDOW_WIDTH, WINDOW_HEIGHT)
+    .run();
+
+    Ok(())
 }
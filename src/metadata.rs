@@ -0,0 +1,313 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::sheet::TitleBlock;
+
+/// What is known about the plan a page comes from, written into every export
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlanMetadata {
+    /// Number of the station, e.g. `27895`
+    pub station_id: Option<String>,
+    pub station_name: Option<String>,
+    pub plan_number: Option<String>,
+    pub sheet: Option<String>,
+    pub revision: Option<String>,
+    /// As written on the plan, e.g. `27.09.2024`
+    pub date: Option<String>,
+    /// As written on the plan, e.g. `1:500`
+    pub scale: Option<String>,
+    /// Hex encoded SHA-256 of the source file
+    pub source_hash: Option<String>,
+    /// Page of the source file, counting from 0
+    pub page: u32,
+}
+
+impl PlanMetadata {
+    /// Station id, name and revision from a name like `L1_27895_Oberfeld` or
+    /// `L3_17825_Zurichwald_versionB`, `None` if it carries no station id
+    pub fn parse_name(name: &str) -> Option<Self> {
+        let tokens: Vec<_> = name.split(['_', ' ']).filter(|t| !t.is_empty()).collect();
+
+        let id = tokens
+            .iter()
+            .position(|t| t.len() >= 3 && t.chars().all(|c| c.is_ascii_digit()))?;
+
+        let mut name = vec![];
+        let mut revision = None;
+        for token in &tokens[id + 1..] {
+            match token.get(..7) {
+                Some(prefix) if prefix.eq_ignore_ascii_case("version") && token.len() > 7 => {
+                    revision = Some(token[7..].to_string())
+                }
+                _ => name.push(*token),
+            }
+        }
+
+        Some(PlanMetadata {
+            station_id: Some(tokens[id].to_string()),
+            station_name: (!name.is_empty()).then(|| name.join(" ")),
+            revision,
+            ..Default::default()
+        })
+    }
+
+    /// Names and hashes the source file. The station is taken from the file
+    /// name, or from the folder for pages exported as images.
+    pub fn from_file(path: impl AsRef<Path>, page: u32) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let hash = Sha256::digest(std::fs::read(path)?);
+
+        let parsed = [path.file_stem(), path.parent().and_then(|p| p.file_name())]
+            .into_iter()
+            .flatten()
+            // Pages exported as images lie in a folder named `<plan> images`
+            .find_map(|name| Self::parse_name(name.to_string_lossy().trim_end_matches(" images")));
+
+        Ok(PlanMetadata {
+            source_hash: Some(format!("{:x}", hash)),
+            page,
+            ..parsed.unwrap_or_default()
+        })
+    }
+
+    /// Fills what is still missing from the title of the PDF info dictionary
+    pub fn with_document_title(self, title: &str) -> Self {
+        let title = title.trim();
+        let parsed = Self::parse_name(title).unwrap_or_else(|| PlanMetadata {
+            station_name: (!title.is_empty()).then(|| title.to_string()),
+            ..Default::default()
+        });
+
+        PlanMetadata {
+            station_id: self.station_id.or(parsed.station_id),
+            station_name: self.station_name.or(parsed.station_name),
+            revision: self.revision.or(parsed.revision),
+            ..self
+        }
+    }
+
+    /// Takes every field the title block has over the rest, it is what the
+    /// plan itself says
+    pub fn with_title_block(self, title_block: &TitleBlock) -> Self {
+        PlanMetadata {
+            station_name: title_block.station_name.clone().or(self.station_name),
+            plan_number: title_block.plan_number.clone().or(self.plan_number),
+            sheet: title_block.sheet.clone().or(self.sheet),
+            revision: title_block.revision.clone().or(self.revision),
+            date: title_block.date.clone().or(self.date),
+            scale: title_block.scale.clone().or(self.scale),
+            ..self
+        }
+    }
+
+    /// Every known field as key and value, keys in kebab case
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        [
+            ("station-id", &self.station_id),
+            ("station-name", &self.station_name),
+            ("plan-number", &self.plan_number),
+            ("sheet", &self.sheet),
+            ("revision", &self.revision),
+            ("date", &self.date),
+            ("scale", &self.scale),
+            ("source-hash", &self.source_hash),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value.clone()?)))
+        .chain([("page", self.page.to_string())])
+        .collect()
+    }
+
+    /// Short description, e.g. `27895 Oberfeld, page 0`
+    pub fn title(&self) -> String {
+        let station: Vec<_> = [&self.station_id, &self.station_name]
+            .into_iter()
+            .flatten()
+            .map(|s| s.as_str())
+            .collect();
+
+        match station.is_empty() {
+            true => format!("Page {}", self.page),
+            false => format!("{}, page {}", station.join(" "), self.page),
+        }
+    }
+
+    /// Saves the image as PNG with the fields as text chunks
+    pub fn save_png(&self, img: &RgbImage, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(File::create(path)?),
+            img.width(),
+            img.height(),
+        );
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        encoder.add_itxt_chunk("Title".to_string(), self.title())?;
+        for (key, value) in self.fields() {
+            encoder.add_itxt_chunk(key.to_string(), value)?;
+        }
+
+        encoder.write_header()?.write_image_data(img.as_raw())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn some(value: &str) -> Option<String> {
+        Some(value.to_string())
+    }
+
+    #[test]
+    fn names_are_parsed_into_station_and_revision() {
+        let cases = [
+            ("L1_27895_Oberfeld", some("27895"), some("Oberfeld"), None),
+            (
+                "L3_17825_Zurichwald_versionB",
+                some("17825"),
+                some("Zurichwald"),
+                some("B"),
+            ),
+            (
+                "L2_04711_Bad Ragaz_VERSION3",
+                some("04711"),
+                some("Bad Ragaz"),
+                some("3"),
+            ),
+            ("27895", some("27895"), None, None),
+            ("123_Nord_Süd", some("123"), some("Nord Süd"), None),
+            // A bare "version" is part of the name
+            ("L1_27895_version", some("27895"), some("version"), None),
+        ];
+
+        for (name, station_id, station_name, revision) in cases {
+            let parsed = PlanMetadata::parse_name(name).unwrap();
+
+            assert_eq!(parsed.station_id, station_id, "{}", name);
+            assert_eq!(parsed.station_name, station_name, "{}", name);
+            assert_eq!(parsed.revision, revision, "{}", name);
+            assert_eq!(parsed.plan_number, None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn names_without_station_id_are_not_parsed() {
+        for name in [
+            "",
+            "ExamplePlan",
+            "L1_Oberfeld",
+            "L1_27_Oberfeld",
+            "Plan 12a45",
+        ] {
+            assert_eq!(PlanMetadata::parse_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn files_are_named_after_the_file_or_the_image_folder() {
+        let dir = std::env::temp_dir().join("railify_metadata_test");
+        let images = dir.join("L3_17825_Zurichwald_versionB images");
+        std::fs::create_dir_all(&images).unwrap();
+        let named = dir.join("L1_27895_Oberfeld.pdf");
+        let page = images.join("page-2.png");
+        std::fs::write(&named, b"plan").unwrap();
+        std::fs::write(&page, b"plan").unwrap();
+
+        let from_name = PlanMetadata::from_file(&named, 0).unwrap();
+        let from_folder = PlanMetadata::from_file(&page, 2).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(from_name.station_id, some("27895"));
+        assert_eq!(from_name.station_name, some("Oberfeld"));
+        // SHA-256 of "plan"
+        assert_eq!(
+            from_name.source_hash.as_deref(),
+            Some("64879f7d6b960a01909762d911a32d4582c20010c5641ee90278b644a9e3b525")
+        );
+        assert_eq!(from_name.source_hash, from_folder.source_hash);
+
+        assert_eq!(from_folder.station_id, some("17825"));
+        assert_eq!(from_folder.revision, some("B"));
+        assert_eq!(from_folder.page, 2);
+
+        assert!(PlanMetadata::from_file(dir.join("missing.pdf"), 0).is_err());
+    }
+
+    #[test]
+    fn title_block_takes_precedence_over_the_file_name() {
+        let from_name = PlanMetadata {
+            station_id: some("27895"),
+            station_name: some("Oberfeld"),
+            revision: some("B"),
+            page: 1,
+            ..Default::default()
+        };
+        let title_block = TitleBlock {
+            station_name: some("Oberfeld Nord"),
+            plan_number: some("4711-02"),
+            date: some("12.03.2020"),
+            sheet: some("2"),
+            ..Default::default()
+        };
+
+        let metadata = from_name.with_title_block(&title_block);
+
+        assert_eq!(
+            metadata,
+            PlanMetadata {
+                station_id: some("27895"),
+                station_name: some("Oberfeld Nord"),
+                plan_number: some("4711-02"),
+                sheet: some("2"),
+                revision: some("B"),
+                date: some("12.03.2020"),
+                scale: None,
+                source_hash: None,
+                page: 1,
+            }
+        );
+        assert_eq!(
+            metadata.fields(),
+            vec![
+                ("station-id", "27895".to_string()),
+                ("station-name", "Oberfeld Nord".to_string()),
+                ("plan-number", "4711-02".to_string()),
+                ("sheet", "2".to_string()),
+                ("revision", "B".to_string()),
+                ("date", "12.03.2020".to_string()),
+                ("page", "1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn document_title_only_fills_what_is_missing() {
+        let from_name = PlanMetadata {
+            station_id: some("27895"),
+            ..Default::default()
+        };
+
+        let metadata = from_name
+            .clone()
+            .with_document_title("L1_11111_Oberfeld_versionC");
+        assert_eq!(metadata.station_id, some("27895"));
+        assert_eq!(metadata.station_name, some("Oberfeld"));
+        assert_eq!(metadata.revision, some("C"));
+
+        let metadata = from_name.with_document_title(" Bahnhof Oberfeld ");
+        assert_eq!(metadata.station_name, some("Bahnhof Oberfeld"));
+        assert_eq!(metadata.title(), "27895 Bahnhof Oberfeld, page 0");
+
+        assert_eq!(
+            PlanMetadata::default().with_document_title("").title(),
+            "Page 0"
+        );
+    }
+}
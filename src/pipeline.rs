@@ -0,0 +1,931 @@
+use std::{cell::RefCell, path::Path, rc::Rc};
+
+use image::{Rgb, RgbImage, Rgba, RgbaImage};
+use iter_tools::Itertools;
+use nannou::{color::rgba, glam::Vec2};
+use pdfium_render::prelude::*;
+use rayon::prelude::*;
+use serde_json::json;
+use svg::node::element;
+
+use crate::{
+    binarize::{binarize, BinaryMask},
+    config::{PipelineConfig, ThicknessConfig},
+    correction::{Anchoring, Patch, ReplayReport},
+    data_structures::{Connectable, Node},
+    deskew::{self, Deskew},
+    georeference::Georeference,
+    image_helper::mark_unresolved_pixels,
+    layers::ColorLayers,
+    metadata::PlanMetadata,
+    plan_graph::PlanGraph,
+    render_svg::{self, SvgDiff},
+    shape_finder::{self, Curve, CurveGeometry, LineStyle, Point, Shape, Stage, ThicknessClass},
+    sheet::{self, SheetLayout, TextSpan, TitleBlock},
+    spatial_index::{BoundingBox, SpatialIndex},
+    svg_helper,
+    transform::{image_to_shapes, PageFrames, MM_PER_POINT},
+};
+
+/// Node of the detected track graph
+#[derive(Clone, Debug, PartialEq)]
+pub struct TopologyNode {
+    pub position: Point,
+    pub pinpoint: bool,
+    /// Indices into [`Topology::nodes`] of the nodes this one is connected to
+    pub prev: Vec<usize>,
+}
+
+/// Track between two nodes of the graph
+#[derive(Clone, Debug, PartialEq)]
+pub struct TopologyEdge {
+    /// Index into [`Topology::nodes`]
+    pub from: usize,
+    /// Index into [`Topology::nodes`]
+    pub to: usize,
+    /// Colour the track was drawn in
+    pub color: Rgb<u8>,
+    /// Layer of the colour as configured in [`crate::config::LayersConfig`]
+    pub layer: String,
+    pub style: LineStyle,
+    /// Stroke width in sheet millimetres
+    pub stroke_width: f32,
+    pub thickness_class: ThicknessClass,
+    /// Course of curved tracks, `None` for straight ones
+    pub geometry: Option<CurveGeometry>,
+}
+
+impl TopologyEdge {
+    /// Points the track runs through from `from` to `to`, the end nodes only
+    /// for straight tracks
+    pub fn course(&self, nodes: &[TopologyNode]) -> Vec<Point> {
+        let (start, end) = (&nodes[self.from].position, &nodes[self.to].position);
+
+        match &self.geometry {
+            Some(geometry) => Curve {
+                start: start.clone(),
+                end: end.clone(),
+                geometry: geometry.clone(),
+                thickness: 0.,
+                stroke_width: self.stroke_width,
+                color: self.color,
+                fit: None,
+            }
+            .points(),
+            None => vec![start.clone(), end.clone()],
+        }
+    }
+}
+
+/// Everything recognised on a single page
+#[derive(Clone, Debug)]
+pub struct Topology {
+    pub shapes: Vec<Shape>,
+    pub nodes: Vec<TopologyNode>,
+    pub edges: Vec<TopologyEdge>,
+    /// The page with every ink pixel that could not be attributed to a shape marked red
+    pub unresolved: RgbImage,
+    /// The page the shapes were detected on, straightened
+    pub page: RgbImage,
+    /// Ink of [`Topology::page`] the detection started with, without the sheet layout
+    pub mask: BinaryMask,
+    /// Sheet millimetres covered by one pixel of the page
+    pub mm_per_pixel: f32,
+    /// Rotation that straightened the page before the detection
+    pub deskew: Deskew,
+    /// Frame and boxes of the sheet, left out of the detection
+    pub layout: SheetLayout,
+    pub title_block: TitleBlock,
+    pub metadata: PlanMetadata,
+    /// From the coordinates of the shapes to pixels, points, sheet and world
+    pub frames: PageFrames,
+    pub georeference: Option<Georeference>,
+    /// What the stages of the detection found before the final shapes
+    pub stages: Vec<Stage>,
+}
+
+impl Topology {
+    fn new(
+        mut shapes: Vec<Shape>,
+        layers: &ColorLayers,
+        thickness: &ThicknessConfig,
+        unresolved: RgbImage,
+        mm_per_pixel: f32,
+    ) -> Self {
+        for shape in &mut shapes {
+            match shape {
+                Shape::Line(l) => l.stroke_width = l.thickness * mm_per_pixel,
+                Shape::Curve(c) => c.stroke_width = c.thickness * mm_per_pixel,
+                _ => {}
+            }
+        }
+
+        let points: Vec<_> = shapes
+            .iter()
+            .filter_map(|s| match s {
+                Shape::Point(p, prevs, pinpoint) => Some((p.clone(), prevs.clone(), *pinpoint)),
+                _ => None,
+            })
+            .collect();
+
+        let index = SpatialIndex::new(points.iter().map(|(p, _, _)| p.clone()));
+
+        // What the edges are built from, curves by their chord
+        let tracks: Vec<_> = shapes
+            .iter()
+            .filter_map(|s| match s {
+                Shape::Line(l) => Some((l.clone(), None)),
+                Shape::Curve(c) => Some((c.chord(), Some(&c.geometry))),
+                _ => None,
+            })
+            .collect();
+        let starts = SpatialIndex::new(tracks.iter().map(|(l, _)| l.start.clone()));
+
+        let mut edges = vec![];
+
+        let nodes = points
+            .iter()
+            .enumerate()
+            .map(|(to, (position, prevs, pinpoint))| {
+                let mut prev: Vec<_> = prevs
+                    .iter()
+                    .flat_map(|p| {
+                        // The track the edge was built from
+                        let track = starts
+                            .within_distance(p, 0.)
+                            .map(|(id, _)| &tracks[id])
+                            .min_by(|(a, _), (b, _)| {
+                                a.end
+                                    .distance(position)
+                                    .total_cmp(&b.end.distance(position))
+                            });
+
+                        index
+                            .within_distance(p, 1.5)
+                            .filter(|(_, other)| {
+                                p.0 as i32 == other.0 as i32 && p.1 as i32 == other.1 as i32
+                            })
+                            .map(|(id, _)| (id, track))
+                            .collect::<Vec<_>>()
+                    })
+                    .unique_by(|(id, _)| *id)
+                    .collect();
+                prev.sort_by_key(|(id, _)| *id);
+
+                edges.extend(prev.iter().map(|(from, track)| {
+                    let line = track.map(|(l, _)| l);
+                    let color = line.map(|l| l.color).unwrap_or(Rgb([0, 0, 0]));
+                    let stroke_width = line.map(|l| l.stroke_width).unwrap_or_default();
+
+                    TopologyEdge {
+                        from: *from,
+                        to,
+                        color,
+                        layer: layers.layer_of(&color).to_string(),
+                        style: line.map(|l| l.style).unwrap_or(LineStyle::Solid),
+                        stroke_width,
+                        thickness_class: thickness.class_of(stroke_width),
+                        geometry: track.and_then(|(_, g)| g.cloned()),
+                    }
+                }));
+
+                let prev = prev.into_iter().map(|(id, _)| id).collect();
+
+                TopologyNode {
+                    position: position.clone(),
+                    pinpoint: *pinpoint,
+                    prev,
+                }
+            })
+            .collect();
+
+        Topology {
+            shapes,
+            nodes,
+            edges,
+            page: RgbImage::new(0, 0),
+            mask: BinaryMask::new(0, 0),
+            unresolved,
+            mm_per_pixel,
+            deskew: Deskew::default(),
+            layout: SheetLayout::default(),
+            title_block: TitleBlock::default(),
+            metadata: PlanMetadata::default(),
+            frames: PageFrames::default(),
+            georeference: None,
+            stages: vec![],
+        }
+    }
+
+    /// Writes the graph as JSON, see [`PlanGraph`]
+    pub fn save_json(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        PlanGraph::from(self).save(path)
+    }
+
+    /// Places the page in the world, see [`crate::georeference::fit`]
+    pub fn set_georeference(&mut self, georeference: Georeference) {
+        self.frames.sheet_to_world =
+            Some(self.frames.sheet_to_svg().then(&georeference.svg_to_world));
+        self.georeference = Some(georeference);
+    }
+
+    /// Writes the graph as GeoJSON, nodes as points and tracks as line strings
+    /// with their attributes. Fails unless the page is georeferenced.
+    pub fn save_geojson(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let (Some(georeference), Some(to_world)) =
+            (&self.georeference, self.frames.shapes_to_world())
+        else {
+            anyhow::bail!("the page has no georeference, control points are needed");
+        };
+
+        let world = |p: &Point| {
+            let (x, y) = to_world.apply_f64(p.0 as f64, p.1 as f64);
+            [x, y]
+        };
+
+        let nodes = self.nodes.iter().enumerate().map(|(id, node)| {
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": world(&node.position) },
+                "properties": { "kind": "node", "id": id, "pinpoint": node.pinpoint },
+            })
+        });
+
+        let edges = self.edges.iter().map(|edge| {
+            let coordinates: Vec<_> = edge.course(&self.nodes).iter().map(world).collect();
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "LineString", "coordinates": coordinates },
+                "properties": {
+                    "kind": "track",
+                    "from": edge.from,
+                    "to": edge.to,
+                    "layer": edge.layer,
+                    "style": edge.style,
+                    "stroke_width": edge.stroke_width,
+                    "thickness_class": edge.thickness_class,
+                    "color": format!("#{:02x}{:02x}{:02x}", edge.color[0], edge.color[1], edge.color[2]),
+                },
+            })
+        });
+
+        let collection = json!({
+            "type": "FeatureCollection",
+            // Named CRS as in GeoJSON 2008, RFC 7946 only knows WGS84
+            "crs": {
+                "type": "name",
+                "properties": { "name": format!("urn:ogc:def:crs:EPSG::{}", georeference.crs.epsg()) },
+            },
+            "metadata": self.metadata,
+            "features": nodes.chain(edges).collect::<Vec<_>>(),
+        });
+
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(std::io::BufWriter::new(file), &collection)?;
+
+        Ok(())
+    }
+
+    /// Pixel of the original page or scan a point of the shapes lies on
+    pub fn original_position(&self, point: &Point) -> (f32, f32) {
+        let p = self.frames.shapes_to_pixels.apply(point);
+        (p.0, p.1)
+    }
+
+    /// The nodes as a linked graph, as used by the SVG export
+    pub fn graph(&self) -> Vec<Rc<RefCell<Node>>> {
+        let graph: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|n| {
+                Rc::new(RefCell::new(Node {
+                    coordinates: (n.position.0 as _, n.position.1 as _),
+                    prev: vec![],
+                }))
+            })
+            .collect();
+
+        for (node, rc) in self.nodes.iter().zip(&graph) {
+            rc.borrow_mut().prev = node
+                .prev
+                .iter()
+                .map(|id| Connectable::Node(graph[*id].clone()))
+                .collect();
+        }
+
+        graph
+    }
+
+    /// Writes the graph as SVG
+    pub fn save_svg(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let mut svg_schema: Vec<element::Element> = Vec::new();
+
+        let graph = self.graph();
+
+        // Curved edges are drawn as paths instead of straight lines between their nodes
+        for edge in self.edges.iter().filter(|e| e.geometry.is_some()) {
+            graph[edge.to]
+                .borrow_mut()
+                .prev
+                .retain(|c| !matches!(c, Connectable::Node(n) if Rc::ptr_eq(n, &graph[edge.from])));
+        }
+
+        for node in graph {
+            svg_helper::insert_svg(&mut svg_schema, Connectable::Node(node));
+        }
+
+        for edge in &self.edges {
+            if let Some(geometry) = &edge.geometry {
+                svg_helper::insert_curve(
+                    &mut svg_schema,
+                    &self.nodes[edge.from].position,
+                    &self.nodes[edge.to].position,
+                    geometry,
+                );
+            }
+        }
+        svg_helper::save_and_draw_svg(
+            &mut svg_schema,
+            &self.metadata,
+            &self
+                .frames
+                .shapes_to_sheet()
+                .then(&self.frames.sheet_to_svg()),
+            self.frames.sheet_size,
+            path,
+        )
+    }
+
+    /// Rasterises the SVG [`Topology::save_svg`] wrote to `path` onto the
+    /// straightened page and compares it with [`Topology::mask`], ink within
+    /// `tolerance` pixels matching
+    pub fn render_svg(&self, path: impl AsRef<Path>, tolerance: f32) -> anyhow::Result<SvgDiff> {
+        let (width, height) = self.page.dimensions();
+        let page_to_svg = image_to_shapes(height)
+            .then(&self.frames.shapes_to_sheet())
+            .then(&self.frames.sheet_to_svg());
+        let Some(svg_to_page) = page_to_svg.inverse() else {
+            anyhow::bail!("the page has no sheet coordinates");
+        };
+
+        let rendered =
+            render_svg::rasterize(path, &svg_to_page, self.frames.sheet_size, (width, height))?;
+        Ok(render_svg::compare(&self.mask, &rendered, tolerance))
+    }
+
+    /// Saves [`Topology::unresolved`], PNGs with the metadata as text chunks
+    pub fn save_unresolved(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        match path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+        {
+            true => self.metadata.save_png(&self.unresolved, path),
+            false => Ok(self.unresolved.save(path)?),
+        }
+    }
+
+    /// The shapes as primitives for the `draw` viewer, tagged with their
+    /// index in [`Topology::shapes`]
+    pub fn draw_shapes(&self) -> Vec<draw::Shape> {
+        to_draw_shapes(&self.shapes)
+    }
+
+    /// Every stage of the detection for the `draw` viewer to step through,
+    /// ending with the final shapes and their descriptions
+    pub fn draw_stages(&self) -> Vec<draw::Stage> {
+        self.stages
+            .iter()
+            .map(|stage| draw::Stage {
+                name: stage.name.to_string(),
+                shapes: to_draw_shapes(&stage.shapes),
+                infos: vec![],
+            })
+            .chain([draw::Stage {
+                name: "graph".to_string(),
+                shapes: self.draw_shapes(),
+                infos: self.draw_infos(),
+            }])
+            .collect()
+    }
+
+    /// Page, mask and unresolved pixels as backgrounds for the viewer, the
+    /// latter two transparent where there is no ink
+    pub fn draw_backgrounds(&self) -> Vec<draw::Background> {
+        let background = |name: &str, img: RgbaImage| draw::Background {
+            name: name.to_string(),
+            width: img.width(),
+            height: img.height(),
+            rgba: img.into_raw(),
+        };
+
+        let (width, height) = self.page.dimensions();
+
+        let page = RgbaImage::from_fn(width, height, |x, y| {
+            let [r, g, b] = self.page.get_pixel(x, y).0;
+            Rgba([r, g, b, 255])
+        });
+        let mask = RgbaImage::from_fn(width, height, |x, y| match self.mask.is_ink(x, y) {
+            true => Rgba([0, 0, 0, 255]),
+            false => Rgba([0, 0, 0, 0]),
+        });
+        // Marked pixels are the ones differing from the page
+        let unresolved = RgbaImage::from_fn(width, height, |x, y| {
+            let [r, g, b] = self.unresolved.get_pixel(x, y).0;
+            match self.page.get_pixel(x, y) == self.unresolved.get_pixel(x, y) {
+                true => Rgba([0, 0, 0, 0]),
+                false => Rgba([r, g, b, 255]),
+            }
+        });
+
+        vec![
+            background("page", page),
+            background("mask", mask),
+            background("unresolved", unresolved),
+        ]
+    }
+
+    /// Descriptions of [`Topology::shapes`] for the inspector of the viewer
+    pub fn draw_infos(&self) -> Vec<draw::ShapeInfo> {
+        let to_sheet = self.frames.shapes_to_sheet();
+        let position = |p: &Point| {
+            let mm = to_sheet.apply(p);
+            format!("({:.1}, {:.1}) px, ({:.1}, {:.1}) mm", p.0, p.1, mm.0, mm.1)
+        };
+        let color = |c: &Rgb<u8>| format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2]);
+        let list = |ids: Vec<usize>| match ids.is_empty() {
+            true => "none".to_string(),
+            false => ids.iter().map(|id| id.to_string()).join(", "),
+        };
+        // Nodes a track starts or ends at
+        let nodes_at = |p: &Point| {
+            let ids = self.nodes.iter().enumerate();
+            list(
+                ids.filter(|(_, n)| n.position.distance(p) <= NODE_DISTANCE)
+                    .map(|(id, _)| id)
+                    .collect(),
+            )
+        };
+
+        let mut node_ids = 0..;
+
+        self.shapes
+            .iter()
+            .map(|s| {
+                let (kind, mut properties): (&str, Vec<(&str, String)>) = match s {
+                    Shape::Line(l) => (
+                        "Line",
+                        vec![
+                            ("start", position(&l.start)),
+                            ("end", position(&l.end)),
+                            ("length", format!("{:.1} px", l.start.distance(&l.end))),
+                            ("thickness", format!("{:.1} px", l.thickness)),
+                            ("stroke width", format!("{:.2} mm", l.stroke_width)),
+                            ("style", format!("{:?}", l.style)),
+                            ("color", color(&l.color)),
+                            ("nodes at start", nodes_at(&l.start)),
+                            ("nodes at end", nodes_at(&l.end)),
+                        ],
+                    ),
+                    Shape::Curve(c) => (
+                        "Curve",
+                        vec![
+                            ("start", position(&c.start)),
+                            ("end", position(&c.end)),
+                            ("geometry", format!("{:?}", c.geometry)),
+                            ("thickness", format!("{:.1} px", c.thickness)),
+                            ("stroke width", format!("{:.2} mm", c.stroke_width)),
+                            ("color", color(&c.color)),
+                            ("nodes at start", nodes_at(&c.start)),
+                            ("nodes at end", nodes_at(&c.end)),
+                        ],
+                    ),
+                    Shape::Circle(c) => (
+                        "Circle",
+                        vec![
+                            ("center", position(&c.center)),
+                            ("radius", format!("{:.1} px", c.radius)),
+                            ("filled", c.filled.to_string()),
+                            ("color", color(&c.color)),
+                        ],
+                    ),
+                    Shape::Rectangle(r) => (
+                        "Rectangle",
+                        vec![
+                            ("center", position(&r.center)),
+                            ("size", format!("{:.1} x {:.1} px", r.width, r.height)),
+                            ("angle", format!("{:.1}°", r.angle.to_degrees())),
+                            ("filled", r.filled.to_string()),
+                            ("color", color(&r.color)),
+                        ],
+                    ),
+                    Shape::Point(p, _, pinpoint) => {
+                        let id = node_ids.next().unwrap_or_default();
+                        // Connected both ways, the graph only keeps the previous nodes
+                        let neighbours = self
+                            .nodes
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, n)| n.prev.contains(&id))
+                            .map(|(other, _)| other)
+                            .chain(self.nodes[id].prev.iter().cloned())
+                            .filter(|other| *other != id)
+                            .unique()
+                            .sorted()
+                            .collect();
+                        let edges = self
+                            .edges
+                            .iter()
+                            .filter(|e| e.from == id || e.to == id)
+                            .map(|e| format!("{} {:?} {:?}", e.layer, e.style, e.thickness_class))
+                            .unique()
+                            .join(", ");
+
+                        (
+                            match pinpoint {
+                                true => "Pinpoint",
+                                false => "Node",
+                            },
+                            vec![
+                                ("id", id.to_string()),
+                                ("position", position(p)),
+                                ("neighbours", list(neighbours)),
+                                ("tracks", edges),
+                            ],
+                        )
+                    }
+                    Shape::Custom(pixels, c) => (
+                        "Unrecognised symbol",
+                        vec![("pixels", pixels.len().to_string()), ("color", color(c))],
+                    ),
+                };
+
+                if let Some(fit) = s.fit() {
+                    properties.push(("pixels", fit.pixels.to_string()));
+                    properties.push(("fit residual", format!("{:.2} px", fit.residual)));
+                }
+
+                draw::ShapeInfo {
+                    kind: kind.to_string(),
+                    properties: properties
+                        .into_iter()
+                        .map(|(name, value)| (name.to_string(), value))
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Lines hollow circles are drawn with in the viewer
+const CIRCLE_SEGMENTS: usize = 32;
+
+/// Tracks end at nodes this close to their end points, in pixels
+const NODE_DISTANCE: f32 = 2.;
+
+/// Primitives for the `draw` viewer, tagged with their index in `shapes`
+fn to_draw_shapes(shapes: &[Shape]) -> Vec<draw::Shape> {
+    shapes
+        .iter()
+        .enumerate()
+        .flat_map(|(i, s)| {
+            let layer = match s {
+                Shape::Line(_) | Shape::Curve(_) => draw::Layer::Lines,
+                Shape::Circle(_) | Shape::Rectangle(_) => draw::Layer::Symbols,
+                Shape::Point(_, _, true) => draw::Layer::Pinpoints,
+                Shape::Point(_, _, false) => draw::Layer::Nodes,
+                Shape::Custom(..) => draw::Layer::Unresolved,
+            };
+            let meta = draw::Meta {
+                source: Some(i),
+                layer,
+            };
+
+            match s {
+                Shape::Line(l) => vec![draw::Shape::Line {
+                    start: l.start.clone().into(),
+                    end: l.end.clone().into(),
+                    color: to_rgba(&l.color),
+                    weight: l.thickness,
+                    meta,
+                }],
+                Shape::Curve(c) => c
+                    .points()
+                    .windows(2)
+                    .map(|w| draw::Shape::Line {
+                        start: w[0].clone().into(),
+                        end: w[1].clone().into(),
+                        color: to_rgba(&c.color),
+                        weight: c.thickness,
+                        meta,
+                    })
+                    .collect(),
+                Shape::Circle(c) if c.filled => vec![draw::Shape::Circle {
+                    position: c.center.clone().into(),
+                    radius: c.radius,
+                    color: to_rgba(&c.color),
+                    meta,
+                }],
+                Shape::Circle(c) => {
+                    let outline: Vec<_> = (0..=CIRCLE_SEGMENTS)
+                        .map(|i| {
+                            let angle = std::f32::consts::TAU * i as f32 / CIRCLE_SEGMENTS as f32;
+                            Point(
+                                c.center.0 + c.radius * angle.cos(),
+                                c.center.1 + c.radius * angle.sin(),
+                            )
+                        })
+                        .collect();
+                    outline_shapes(&outline, &c.color, meta)
+                }
+                Shape::Rectangle(r) if r.filled && r.angle == 0. => {
+                    vec![draw::Shape::Rectangle {
+                        position: r.center.clone().into(),
+                        width: r.width,
+                        height: r.height,
+                        color: to_rgba(&r.color),
+                        meta,
+                    }]
+                }
+                Shape::Rectangle(r) => {
+                    let mut corners = r.corners().to_vec();
+                    corners.push(corners[0].clone());
+                    outline_shapes(&corners, &r.color, meta)
+                }
+                Shape::Point(p, _, pinpoint) if *pinpoint => {
+                    vec![draw::Shape::Circle {
+                        position: Vec2::new(p.0 as _, p.1 as _),
+                        color: rgba(0., 255., 0., 1.),
+                        radius: 10.,
+                        meta,
+                    }]
+                }
+                Shape::Point(p, _, _) => {
+                    vec![draw::Shape::Circle {
+                        position: Vec2::new(p.0 as _, p.1 as _),
+                        color: rgba(0., 0., 255., 1.),
+                        radius: 10.,
+                        meta,
+                    }]
+                }
+                Shape::Custom(pixels, _) => pixels
+                    .iter()
+                    .map(|p| draw::Shape::Point {
+                        position: Vec2::new(p.0 as _, p.1 as _),
+                        color: rgba(255., 0., 0., 1.),
+                        meta,
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Lines through the points, for outlines the viewer can't draw directly
+fn outline_shapes(points: &[Point], color: &Rgb<u8>, meta: draw::Meta) -> Vec<draw::Shape> {
+    points
+        .windows(2)
+        .map(|w| draw::Shape::Line {
+            start: w[0].clone().into(),
+            end: w[1].clone().into(),
+            color: to_rgba(color),
+            weight: 1.,
+            meta,
+        })
+        .collect()
+}
+
+fn to_rgba(color: &Rgb<u8>) -> nannou::color::Rgba {
+    rgba(
+        color[0] as f32 / 255.,
+        color[1] as f32 / 255.,
+        color[2] as f32 / 255.,
+        1.,
+    )
+}
+
+/// A PDF page rendered for recognition
+#[derive(Clone, Debug)]
+pub struct RenderedPage {
+    pub image: RgbImage,
+    /// Width and height of the page in PDF points
+    pub size: (f32, f32),
+    /// Text of the page in image pixels, empty for scans
+    pub text: Vec<TextSpan>,
+    /// What the source file tells about the plan, completed from the title block
+    pub metadata: PlanMetadata,
+}
+
+impl RenderedPage {
+    /// Sheet millimetres covered by one pixel of the image
+    pub fn mm_per_pixel(&self) -> f32 {
+        self.size.0 * MM_PER_POINT / self.image.width() as f32
+    }
+}
+
+/// Turns plan PDFs into a [`Topology`]
+///
+/// ```no_run
+/// use hackaton_railify::{Pipeline, PipelineConfig};
+///
+/// let topology = Pipeline::new(PipelineConfig::default())
+///     .process_pdf("assets/plans/ExamplePlan.pdf")
+///     .unwrap();
+/// println!("{} nodes", topology.nodes.len());
+/// ```
+pub struct Pipeline {
+    config: PipelineConfig,
+}
+
+impl Pipeline {
+    pub fn new(config: PipelineConfig) -> Self {
+        Pipeline { config }
+    }
+
+    pub fn config(&self) -> &PipelineConfig {
+        &self.config
+    }
+
+    /// Finds corrections in the graph of the page by their sheet positions
+    pub fn anchoring(&self, topology: &Topology) -> Anchoring {
+        Anchoring::new(topology.frames.shapes_to_sheet(), &self.config.corrections)
+    }
+
+    /// The graph of the page with the corrections of the patch replayed on
+    /// it, and which of them no longer apply
+    pub fn correct(&self, topology: &Topology, patch: &Patch) -> (PlanGraph, ReplayReport) {
+        let mut graph = PlanGraph::from(topology);
+        let report = patch.replay(&mut graph, &self.anchoring(topology));
+        (graph, report)
+    }
+
+    /// Renders every page of the PDF
+    pub fn render_pdf(&self, path: impl AsRef<Path>) -> Result<Vec<RenderedPage>, PdfiumError> {
+        let pdfium = Pdfium::default();
+
+        let document = pdfium.load_pdf_from_file(&path, self.config.password.as_deref())?;
+
+        let mut metadata = PlanMetadata::from_file(&path, 0).map_err(PdfiumError::IoError)?;
+        if let Some(title) = document.metadata().get(PdfDocumentMetadataTagType::Title) {
+            metadata = metadata.with_document_title(title.value());
+        }
+
+        let render_config = PdfRenderConfig::new().set_target_width(self.config.render_width);
+
+        let mut pages = vec![];
+        for (index, page) in document.pages().iter().enumerate() {
+            let image = page
+                .render_with_config(&render_config)?
+                .as_image()
+                .into_rgb8();
+            let (width, height) = (page.width().value, page.height().value);
+            let to_pixels =
+                PageFrames::new(image.dimensions(), (width, height), &Deskew::default())
+                    .pixels_to_points
+                    .inverse()
+                    .unwrap_or_default();
+
+            let text = page
+                .text()?
+                .segments()
+                .iter()
+                .map(|segment| {
+                    let bounds = segment.bounds();
+                    TextSpan {
+                        text: segment.text(),
+                        bounds: BoundingBox::new(
+                            &to_pixels.apply(&Point(bounds.left.value, bounds.top.value)),
+                            &to_pixels.apply(&Point(bounds.right.value, bounds.bottom.value)),
+                        ),
+                    }
+                })
+                .collect();
+
+            pages.push(RenderedPage {
+                image,
+                size: (width, height),
+                text,
+                metadata: PlanMetadata {
+                    page: index as u32,
+                    ..metadata.clone()
+                },
+            });
+        }
+
+        Ok(pages)
+    }
+
+    /// Recognises the configured page of the PDF
+    pub fn process_pdf(&self, path: impl AsRef<Path>) -> anyhow::Result<Topology> {
+        let mut pages = self.render_pdf(path)?;
+        let page = self.config.page as usize;
+
+        anyhow::ensure!(
+            page < pages.len(),
+            "page {} does not exist, the document has {} pages",
+            page,
+            pages.len()
+        );
+
+        Ok(self.process_page(pages.swap_remove(page)))
+    }
+
+    /// Recognises every page of the PDF in parallel
+    pub fn process_pdf_pages(&self, path: impl AsRef<Path>) -> anyhow::Result<Vec<Topology>> {
+        let pages = self.render_pdf(path)?;
+
+        Ok(pages
+            .into_par_iter()
+            .map(|page| self.process_page(page))
+            .collect())
+    }
+
+    /// Recognises an image scanned at [`PipelineConfig::image_dpi`]
+    pub fn process_image(&self, img: RgbImage) -> Topology {
+        self.process_page(self.scanned_page(img, PlanMetadata::default()))
+    }
+
+    /// Recognises a scanned page read from the file, see [`Pipeline::process_image`]
+    pub fn process_image_file(&self, path: impl AsRef<Path>) -> anyhow::Result<Topology> {
+        let metadata = PlanMetadata::from_file(&path, 0)?;
+        let img = image::open(&path)?.into_rgb8();
+
+        Ok(self.process_page(self.scanned_page(img, metadata)))
+    }
+
+    fn scanned_page(&self, img: RgbImage, metadata: PlanMetadata) -> RenderedPage {
+        let points_per_pixel = 72. / self.config.image_dpi;
+
+        RenderedPage {
+            size: (
+                img.width() as f32 * points_per_pixel,
+                img.height() as f32 * points_per_pixel,
+            ),
+            image: img,
+            text: vec![],
+            metadata,
+        }
+    }
+
+    /// Recognises a rendered page
+    pub fn process_page(&self, page: RenderedPage) -> Topology {
+        let mm_per_pixel = page.mm_per_pixel();
+        let RenderedPage {
+            image: mut img,
+            size,
+            mut text,
+            metadata,
+        } = page;
+
+        let mut mask = binarize(&img, &self.config.binarization);
+        let scan_size = img.dimensions();
+
+        let mut rotation = Deskew::default();
+        if self.config.deskew.enabled {
+            if let Some((straight, applied)) = deskew::deskew(&img, &mask, &self.config.deskew) {
+                img = straight;
+                mask = binarize(&img, &self.config.binarization);
+                rotation = applied;
+
+                for span in &mut text {
+                    let (min, max) = (&span.bounds.min, &span.bounds.max);
+                    let (x0, y0) = applied.to_straight(min.0, min.1);
+                    let (x1, y1) = applied.to_straight(max.0, max.1);
+                    span.bounds = BoundingBox::new(&Point(x0, y0), &Point(x1, y1));
+                }
+            }
+        }
+
+        let mut layout = SheetLayout::default();
+        let mut title_block = TitleBlock::default();
+        if self.config.sheet.enabled {
+            layout = sheet::detect_layout(&mask, &self.config.sheet);
+            layout.exclude(&mut mask, &self.config.sheet);
+
+            if let Some(region) = &layout.title_block {
+                title_block = sheet::read_title_block(&text, region, &self.config.sheet);
+            }
+        }
+
+        let layers = ColorLayers::new(&img, &mask, &self.config.layers);
+        let (page, ink) = (img.clone(), mask.clone());
+
+        let (shapes, stages) =
+            shape_finder::shapes_and_stages_from_image(&mut mask, &layers, &self.config.detection);
+
+        mark_unresolved_pixels(&mut img, &mask);
+
+        Topology {
+            deskew: rotation,
+            layout,
+            metadata: metadata.with_title_block(&title_block),
+            frames: PageFrames::new(scan_size, size, &rotation),
+            page,
+            mask: ink,
+            title_block,
+            stages,
+            ..Topology::new(shapes, &layers, &self.config.thickness, img, mm_per_pixel)
+        }
+    }
+}
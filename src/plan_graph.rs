@@ -0,0 +1,849 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context};
+use image::Rgb;
+use iter_tools::Itertools;
+use nannou::{color::rgba, glam::Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    correction::{Anchor, Anchoring, Correction, Patch},
+    metadata::PlanMetadata,
+    pipeline::Topology,
+    shape_finder::{CurveGeometry, LineStyle, Point, Shape, ThicknessClass},
+};
+
+/// Node of a [`PlanGraph`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GraphNode {
+    /// In the frame the shapes were detected in, see [`crate::transform::PageFrames`]
+    pub position: Point,
+    pub pinpoint: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Track between two nodes of a [`PlanGraph`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GraphEdge {
+    /// Index into [`PlanGraph::nodes`]
+    pub from: usize,
+    /// Index into [`PlanGraph::nodes`]
+    pub to: usize,
+    /// As `#rrggbb`
+    pub color: String,
+    pub layer: String,
+    pub style: LineStyle,
+    /// Stroke width in sheet millimetres
+    pub stroke_width: f32,
+    pub thickness_class: ThicknessClass,
+    /// Course of curved tracks, `None` for straight ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geometry: Option<CurveGeometry>,
+}
+
+/// Circle, rectangle or blob of ink on the plan that is no track
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Symbol {
+    /// What the symbol stands for, `None` for blobs nothing was recognised in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub center: Point,
+    /// Size of the box around the symbol, in pixels
+    pub width: f32,
+    pub height: f32,
+    /// As `#rrggbb`
+    pub color: String,
+}
+
+/// Graph of a page as exported to JSON, the detected one or one corrected by
+/// hand in the viewer
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlanGraph {
+    #[serde(default)]
+    pub metadata: PlanMetadata,
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+    #[serde(default)]
+    pub symbols: Vec<Symbol>,
+}
+
+/// Part of a [`PlanGraph`], by its index
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Item {
+    Node(usize),
+    Edge(usize),
+    Symbol(usize),
+}
+
+fn hex(color: &Rgb<u8>) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+/// Black unless the colour is `#rrggbb`
+fn from_hex(color: &str) -> Rgb<u8> {
+    let channel = |i: usize| {
+        color
+            .get(1 + 2 * i..3 + 2 * i)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .unwrap_or_default()
+    };
+    Rgb([channel(0), channel(1), channel(2)])
+}
+
+impl From<&Topology> for PlanGraph {
+    fn from(topology: &Topology) -> Self {
+        let nodes = topology
+            .nodes
+            .iter()
+            .map(|n| GraphNode {
+                position: n.position.clone(),
+                pinpoint: n.pinpoint,
+                label: None,
+            })
+            .collect();
+
+        let edges = topology
+            .edges
+            .iter()
+            .map(|e| GraphEdge {
+                from: e.from,
+                to: e.to,
+                color: hex(&e.color),
+                layer: e.layer.clone(),
+                style: e.style,
+                stroke_width: e.stroke_width,
+                thickness_class: e.thickness_class,
+                geometry: e.geometry.clone(),
+            })
+            .collect();
+
+        let symbols = topology
+            .shapes
+            .iter()
+            .filter_map(|s| {
+                let (kind, center, width, height, color) = match s {
+                    Shape::Circle(c) => (
+                        Some("circle"),
+                        c.center.clone(),
+                        2. * c.radius,
+                        2. * c.radius,
+                        c.color,
+                    ),
+                    Shape::Rectangle(r) => (
+                        Some("rectangle"),
+                        r.center.clone(),
+                        r.width,
+                        r.height,
+                        r.color,
+                    ),
+                    Shape::Custom(pixels, color) => {
+                        let (x0, x1) = pixels.iter().map(|p| p.0).minmax().into_option()?;
+                        let (y0, y1) = pixels.iter().map(|p| p.1).minmax().into_option()?;
+                        (
+                            None,
+                            Point((x0 + x1) as f32 / 2., (y0 + y1) as f32 / 2.),
+                            (x1 - x0 + 1) as f32,
+                            (y1 - y0 + 1) as f32,
+                            *color,
+                        )
+                    }
+                    _ => return None,
+                };
+
+                Some(Symbol {
+                    kind: kind.map(str::to_string),
+                    label: None,
+                    center,
+                    width,
+                    height,
+                    color: hex(&color),
+                })
+            })
+            .collect();
+
+        PlanGraph {
+            metadata: topology.metadata.clone(),
+            nodes,
+            edges,
+            symbols,
+        }
+    }
+}
+
+impl PlanGraph {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read graph {}", path.display()))?;
+        let graph: PlanGraph = serde_json::from_str(&content)
+            .with_context(|| format!("failed to load graph {}", path.display()))?;
+
+        let nodes = graph.nodes.len();
+        ensure!(
+            graph.edges.iter().all(|e| e.from < nodes && e.to < nodes),
+            "graph {} has edges to nodes it doesn't have",
+            path.display()
+        );
+
+        Ok(graph)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("failed to write graph {}", path.display()))
+    }
+
+    /// Adds a node no track is connected to, returning its index
+    pub fn add_node(&mut self, position: Point) -> usize {
+        self.nodes.push(GraphNode {
+            position,
+            pinpoint: false,
+            label: None,
+        });
+        self.nodes.len() - 1
+    }
+
+    /// Removes the node with its tracks, later nodes move down by one
+    pub fn remove_node(&mut self, node: usize) -> anyhow::Result<()> {
+        ensure!(node < self.nodes.len(), "there is no node {}", node);
+
+        self.nodes.remove(node);
+        self.edges.retain(|e| e.from != node && e.to != node);
+        for edge in &mut self.edges {
+            edge.from -= (edge.from > node) as usize;
+            edge.to -= (edge.to > node) as usize;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the node, its curved tracks become straight as their course no
+    /// longer fits
+    pub fn move_node(&mut self, node: usize, position: Point) -> anyhow::Result<()> {
+        let Some(n) = self.nodes.get_mut(node) else {
+            bail!("there is no node {}", node);
+        };
+        n.position = position;
+
+        for edge in &mut self.edges {
+            if edge.from == node || edge.to == node {
+                edge.geometry = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a straight track between the nodes, drawn like another track at
+    /// either of them or a thin black one
+    pub fn connect(&mut self, from: usize, to: usize) -> anyhow::Result<usize> {
+        let nodes = self.nodes.len();
+        ensure!(
+            from < nodes && to < nodes,
+            "there is no node {}",
+            from.max(to)
+        );
+        ensure!(from != to, "a node can't be connected to itself");
+        ensure!(
+            self.edges_between(from, to).is_empty(),
+            "nodes {} and {} are connected already",
+            from,
+            to
+        );
+
+        let edge = match self
+            .edges
+            .iter()
+            .find(|e| [e.from, e.to].iter().any(|n| *n == from || *n == to))
+        {
+            Some(like) => GraphEdge {
+                from,
+                to,
+                geometry: None,
+                ..like.clone()
+            },
+            None => GraphEdge {
+                from,
+                to,
+                color: "#000000".to_string(),
+                layer: String::new(),
+                style: LineStyle::Solid,
+                stroke_width: 0.,
+                thickness_class: ThicknessClass::Auxiliary,
+                geometry: None,
+            },
+        };
+
+        self.edges.push(edge);
+
+        Ok(self.edges.len() - 1)
+    }
+
+    /// Removes every track between the nodes
+    pub fn disconnect(&mut self, a: usize, b: usize) -> anyhow::Result<()> {
+        ensure!(
+            !self.edges_between(a, b).is_empty(),
+            "nodes {} and {} aren't connected",
+            a,
+            b
+        );
+
+        self.edges
+            .retain(|e| !((e.from == a && e.to == b) || (e.from == b && e.to == a)));
+
+        Ok(())
+    }
+
+    pub fn remove_edge(&mut self, edge: usize) -> anyhow::Result<()> {
+        ensure!(edge < self.edges.len(), "there is no track {}", edge);
+        self.edges.remove(edge);
+        Ok(())
+    }
+
+    pub fn remove_symbol(&mut self, symbol: usize) -> anyhow::Result<()> {
+        ensure!(symbol < self.symbols.len(), "there is no symbol {}", symbol);
+        self.symbols.remove(symbol);
+        Ok(())
+    }
+
+    /// Says what the symbol stands for, `None` makes it unrecognised again
+    pub fn set_kind(&mut self, symbol: usize, kind: Option<String>) -> anyhow::Result<()> {
+        let Some(symbol) = self.symbols.get_mut(symbol) else {
+            bail!("only symbols have a kind");
+        };
+        symbol.kind = kind;
+        Ok(())
+    }
+
+    /// Labels a node or symbol, `None` removes the label
+    pub fn set_label(&mut self, item: Item, label: Option<String>) -> anyhow::Result<()> {
+        match item {
+            Item::Node(i) if i < self.nodes.len() => self.nodes[i].label = label,
+            Item::Symbol(i) if i < self.symbols.len() => self.symbols[i].label = label,
+            _ => bail!("only nodes and symbols have labels"),
+        }
+        Ok(())
+    }
+
+    pub fn label(&self, item: Item) -> Option<&str> {
+        match item {
+            Item::Node(i) => self.nodes.get(i)?.label.as_deref(),
+            Item::Symbol(i) => self.symbols.get(i)?.label.as_deref(),
+            Item::Edge(_) => None,
+        }
+    }
+
+    /// Indices of the tracks between the nodes, either way
+    pub fn edges_between(&self, a: usize, b: usize) -> Vec<usize> {
+        self.edges
+            .iter()
+            .positions(|e| (e.from == a && e.to == b) || (e.from == b && e.to == a))
+            .collect()
+    }
+
+    /// Nodes come first, then tracks, then symbols
+    pub fn item(&self, source: usize) -> Option<Item> {
+        let (nodes, edges) = (self.nodes.len(), self.edges.len());
+        match source {
+            s if s < nodes => Some(Item::Node(s)),
+            s if s < nodes + edges => Some(Item::Edge(s - nodes)),
+            s if s < nodes + edges + self.symbols.len() => Some(Item::Symbol(s - nodes - edges)),
+            _ => None,
+        }
+    }
+
+    /// Inverse of [`PlanGraph::item`]
+    pub fn source(&self, item: Item) -> usize {
+        match item {
+            Item::Node(i) => i,
+            Item::Edge(i) => self.nodes.len() + i,
+            Item::Symbol(i) => self.nodes.len() + self.edges.len() + i,
+        }
+    }
+
+    /// The graph as primitives for the `draw` viewer, tagged with the sources
+    /// of [`PlanGraph::item`]
+    pub fn draw_shapes(&self) -> Vec<draw::Shape> {
+        let label = |content: &str, position: &Point, source: usize| draw::Shape::Text {
+            content: content.to_string(),
+            position: Vec2::new(position.0, position.1 + LABEL_OFFSET),
+            font_size: 12,
+            color: rgba(0., 0., 0., 1.),
+            meta: draw::Meta {
+                source: Some(source),
+                layer: draw::Layer::Labels,
+            },
+        };
+
+        let edges = self.edges.iter().enumerate().flat_map(|(i, e)| {
+            let meta = draw::Meta {
+                source: Some(self.source(Item::Edge(i))),
+                layer: draw::Layer::Lines,
+            };
+            let color = from_hex(&e.color);
+            let (start, end) = (&self.nodes[e.from].position, &self.nodes[e.to].position);
+
+            let course = match &e.geometry {
+                Some(geometry) => crate::shape_finder::Curve {
+                    start: start.clone(),
+                    end: end.clone(),
+                    geometry: geometry.clone(),
+                    thickness: 0.,
+                    stroke_width: e.stroke_width,
+                    color,
+                    fit: None,
+                }
+                .points(),
+                None => vec![start.clone(), end.clone()],
+            };
+
+            course
+                .windows(2)
+                .map(|w| draw::Shape::Line {
+                    start: w[0].clone().into(),
+                    end: w[1].clone().into(),
+                    color: rgba(
+                        color[0] as f32 / 255.,
+                        color[1] as f32 / 255.,
+                        color[2] as f32 / 255.,
+                        1.,
+                    ),
+                    weight: 2.,
+                    meta,
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let nodes = self.nodes.iter().enumerate().flat_map(|(i, n)| {
+            let source = self.source(Item::Node(i));
+            let (layer, color) = match n.pinpoint {
+                true => (draw::Layer::Pinpoints, rgba(0., 1., 0., 1.)),
+                false => (draw::Layer::Nodes, rgba(0., 0., 1., 1.)),
+            };
+
+            std::iter::once(draw::Shape::Circle {
+                position: n.position.clone().into(),
+                radius: 10.,
+                color,
+                meta: draw::Meta {
+                    source: Some(source),
+                    layer,
+                },
+            })
+            .chain(n.label.as_ref().map(|l| label(l, &n.position, source)))
+        });
+
+        let symbols = self.symbols.iter().enumerate().flat_map(|(i, s)| {
+            let source = self.source(Item::Symbol(i));
+            let (layer, color) = match s.kind {
+                Some(_) => (draw::Layer::Symbols, rgba(0.6, 0., 0.8, 1.)),
+                None => (draw::Layer::Unresolved, rgba(1., 0., 0., 1.)),
+            };
+            let (w, h) = (s.width / 2., s.height / 2.);
+            let c = Vec2::new(s.center.0, s.center.1);
+            let corners = [(-w, -h), (w, -h), (w, h), (-w, h), (-w, -h)]
+                .map(|(dx, dy)| c + Vec2::new(dx, dy));
+
+            let text = [s.kind.as_deref(), s.label.as_deref()]
+                .into_iter()
+                .flatten()
+                .join(": ");
+
+            corners
+                .windows(2)
+                .map(|w| draw::Shape::Line {
+                    start: w[0],
+                    end: w[1],
+                    color,
+                    weight: 1.,
+                    meta: draw::Meta {
+                        source: Some(source),
+                        layer,
+                    },
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .chain(
+                    (!text.is_empty())
+                        .then(|| label(&text, &Point(s.center.0, s.center.1 + h), source)),
+                )
+        });
+
+        edges.chain(nodes).chain(symbols).collect()
+    }
+
+    /// Descriptions of the items for the viewer, indexed by their sources
+    pub fn draw_infos(&self) -> Vec<draw::ShapeInfo> {
+        let position = |p: &Point| format!("({:.1}, {:.1}) px", p.0, p.1);
+        let label = |l: &Option<String>| l.clone().unwrap_or_else(|| "none".to_string());
+
+        let nodes = self.nodes.iter().enumerate().map(|(id, n)| {
+            let neighbours = self
+                .edges
+                .iter()
+                .filter_map(|e| match (e.from == id, e.to == id) {
+                    (true, _) => Some(e.to),
+                    (_, true) => Some(e.from),
+                    _ => None,
+                })
+                .unique()
+                .sorted()
+                .join(", ");
+
+            draw::ShapeInfo {
+                kind: match n.pinpoint {
+                    true => "Pinpoint".to_string(),
+                    false => "Node".to_string(),
+                },
+                properties: vec![
+                    ("id".to_string(), id.to_string()),
+                    ("position".to_string(), position(&n.position)),
+                    ("neighbours".to_string(), neighbours),
+                    ("label".to_string(), label(&n.label)),
+                ],
+            }
+        });
+
+        let edges = self.edges.iter().map(|e| draw::ShapeInfo {
+            kind: "Track".to_string(),
+            properties: vec![
+                ("from".to_string(), e.from.to_string()),
+                ("to".to_string(), e.to.to_string()),
+                ("layer".to_string(), e.layer.clone()),
+                ("style".to_string(), format!("{:?}", e.style)),
+                (
+                    "stroke width".to_string(),
+                    format!("{:.2} mm", e.stroke_width),
+                ),
+                ("class".to_string(), format!("{:?}", e.thickness_class)),
+                ("color".to_string(), e.color.clone()),
+                ("curved".to_string(), e.geometry.is_some().to_string()),
+            ],
+        });
+
+        let symbols = self.symbols.iter().map(|s| draw::ShapeInfo {
+            kind: match &s.kind {
+                Some(kind) => format!("Symbol: {}", kind),
+                None => "Unrecognised symbol".to_string(),
+            },
+            properties: vec![
+                ("center".to_string(), position(&s.center)),
+                (
+                    "size".to_string(),
+                    format!("{:.1} x {:.1} px", s.width, s.height),
+                ),
+                ("color".to_string(), s.color.clone()),
+                ("label".to_string(), label(&s.label)),
+            ],
+        });
+
+        nodes.chain(edges).chain(symbols).collect()
+    }
+}
+
+/// Labels are drawn this many pixels above what they label
+const LABEL_OFFSET: f32 = 14.;
+
+/// A [`PlanGraph`] corrected in the viewer, recording the corrections
+pub struct GraphEditor {
+    pub graph: PlanGraph,
+    pub path: PathBuf,
+    /// Corrections made so far, see [`crate::correction`]
+    pub patch: Patch,
+    pub patch_path: PathBuf,
+    pub anchoring: Anchoring,
+}
+
+impl GraphEditor {
+    /// The edit as a correction anchored where the shapes are now
+    fn correction(&self, edit: draw::Edit) -> anyhow::Result<Correction> {
+        let item = |source: usize| {
+            self.graph
+                .item(source)
+                .with_context(|| format!("there is nothing with source {}", source))
+        };
+        let anchor = |source: usize| {
+            let item = item(source)?;
+            self.anchoring
+                .anchor(&self.graph, item)
+                .with_context(|| format!("there is nothing with source {}", source))
+        };
+        let node = |source: usize| match anchor(source)? {
+            Anchor::Node(at) => Ok(at),
+            _ => bail!("only nodes can be moved and connected"),
+        };
+        let sheet = |p: Vec2| self.anchoring.sheet(&Point(p.x, p.y));
+        let text = |text: String| Some(text.trim().to_string()).filter(|t| !t.is_empty());
+
+        Ok(match edit {
+            draw::Edit::AddNode(p) => Correction::AddNode { at: sheet(p) },
+            draw::Edit::Delete(source) => Correction::Delete {
+                anchor: anchor(source)?,
+            },
+            draw::Edit::Move(source, p) => Correction::MoveNode {
+                node: node(source)?,
+                to: sheet(p),
+            },
+            draw::Edit::Connect(a, b) => Correction::Connect {
+                from: node(a)?,
+                to: node(b)?,
+            },
+            draw::Edit::Disconnect(a, b) => Correction::Disconnect {
+                from: node(a)?,
+                to: node(b)?,
+            },
+            draw::Edit::SetKind(source, kind) => match anchor(source)? {
+                Anchor::Symbol(symbol) => Correction::SetKind {
+                    symbol,
+                    kind: text(kind),
+                },
+                _ => bail!("only symbols have a kind"),
+            },
+            draw::Edit::SetLabel(source, label) => Correction::SetLabel {
+                anchor: anchor(source)?,
+                label: text(label),
+            },
+        })
+    }
+
+    fn apply(&mut self, edit: draw::Edit) -> anyhow::Result<()> {
+        let correction = self.correction(edit)?;
+        correction.apply(&mut self.graph, &self.anchoring)?;
+        self.patch.push(correction);
+
+        Ok(())
+    }
+
+    fn save(&self) -> anyhow::Result<String> {
+        self.graph.save(&self.path)?;
+        self.patch.save(&self.patch_path)?;
+
+        Ok(format!(
+            "saved {} and {}",
+            self.path.display(),
+            self.patch_path.display()
+        ))
+    }
+}
+
+impl draw::Document for GraphEditor {
+    fn stage(&self) -> draw::Stage {
+        draw::Stage {
+            name: "corrected graph".to_string(),
+            shapes: self.graph.draw_shapes(),
+            infos: self.graph.draw_infos(),
+        }
+    }
+
+    fn label(&self, source: usize) -> Option<String> {
+        self.graph
+            .label(self.graph.item(source)?)
+            .map(str::to_string)
+    }
+
+    fn apply(&mut self, edit: draw::Edit) -> Result<(), String> {
+        GraphEditor::apply(self, edit).map_err(|e| format!("{:#}", e))
+    }
+
+    fn save(&self) -> Result<String, String> {
+        GraphEditor::save(self).map_err(|e| format!("{:#}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(from: usize, to: usize, color: &str) -> GraphEdge {
+        GraphEdge {
+            from,
+            to,
+            color: color.to_string(),
+            layer: "existing".to_string(),
+            style: LineStyle::Solid,
+            stroke_width: 0.5,
+            thickness_class: ThicknessClass::MainTrack,
+            geometry: None,
+        }
+    }
+
+    /// Four nodes on a square, connected all the way round, and two symbols
+    fn square() -> PlanGraph {
+        let node = |x, y| GraphNode {
+            position: Point(x, y),
+            pinpoint: true,
+            label: None,
+        };
+
+        PlanGraph {
+            metadata: PlanMetadata {
+                station_id: Some("27895".to_string()),
+                station_name: Some("Oberfeld".to_string()),
+                ..Default::default()
+            },
+            nodes: vec![
+                node(0., 0.),
+                node(100., 0.),
+                node(100., 100.),
+                node(0., 100.),
+            ],
+            edges: vec![
+                GraphEdge {
+                    style: LineStyle::Dashed,
+                    geometry: Some(CurveGeometry::Arc {
+                        center: Point(50., -20.),
+                        radius: 53.85,
+                        sweep: 0.76,
+                    }),
+                    ..edge(0, 1, "#ff0000")
+                },
+                edge(1, 2, "#000000"),
+                edge(2, 3, "#000000"),
+                edge(3, 0, "#0000ff"),
+            ],
+            symbols: vec![
+                Symbol {
+                    kind: Some("signal".to_string()),
+                    label: Some("A1".to_string()),
+                    center: Point(50., 20.),
+                    width: 8.,
+                    height: 8.,
+                    color: "#000000".to_string(),
+                },
+                Symbol {
+                    kind: None,
+                    label: None,
+                    center: Point(50., 80.),
+                    width: 5.,
+                    height: 3.,
+                    color: "#00ff00".to_string(),
+                },
+            ],
+        }
+    }
+
+    fn ends(graph: &PlanGraph) -> Vec<(usize, usize)> {
+        graph.edges.iter().map(|e| (e.from, e.to)).collect()
+    }
+
+    #[test]
+    fn removing_a_node_renumbers_the_tracks_of_later_nodes() {
+        let mut graph = square();
+
+        graph.remove_node(1).unwrap();
+
+        assert_eq!(graph.nodes.len(), 3);
+        assert_eq!(graph.nodes[1].position, Point(100., 100.));
+        assert_eq!(ends(&graph), vec![(1, 2), (2, 0)]);
+        assert_eq!(graph.edges[1].color, "#0000ff");
+        assert!(graph.remove_node(3).is_err());
+    }
+
+    #[test]
+    fn connecting_copies_a_neighbouring_track() {
+        let mut graph = square();
+
+        let added = graph.connect(0, 2).unwrap();
+
+        assert_eq!(ends(&graph)[added], (0, 2));
+        assert_eq!(
+            graph.edges[added],
+            GraphEdge {
+                from: 0,
+                to: 2,
+                geometry: None,
+                ..graph.edges[0].clone()
+            }
+        );
+        assert!(graph.connect(2, 0).is_err());
+        assert!(graph.connect(1, 1).is_err());
+        assert!(graph.connect(0, 4).is_err());
+    }
+
+    #[test]
+    fn connecting_lone_nodes_draws_a_thin_black_track() {
+        let mut graph = square();
+        let a = graph.add_node(Point(200., 0.));
+        let b = graph.add_node(Point(300., 0.));
+
+        let added = graph.connect(a, b).unwrap();
+
+        assert_eq!(graph.edges[added].color, "#000000");
+        assert_eq!(
+            graph.edges[added].thickness_class,
+            ThicknessClass::Auxiliary
+        );
+    }
+
+    #[test]
+    fn disconnecting_removes_the_tracks_either_way() {
+        let mut graph = square();
+        graph.edges.push(edge(0, 3, "#000000"));
+
+        graph.disconnect(0, 3).unwrap();
+
+        assert_eq!(ends(&graph), vec![(0, 1), (1, 2), (2, 3)]);
+        assert!(graph.disconnect(0, 3).is_err());
+    }
+
+    #[test]
+    fn moving_a_node_straightens_its_curves() {
+        let mut graph = square();
+
+        graph.move_node(1, Point(110., 0.)).unwrap();
+
+        assert_eq!(graph.nodes[1].position, Point(110., 0.));
+        assert_eq!(graph.edges[0].geometry, None);
+        assert!(graph.move_node(4, Point(0., 0.)).is_err());
+    }
+
+    #[test]
+    fn items_and_sources_round_trip() {
+        let graph = square();
+
+        for source in 0..10 {
+            let item = graph.item(source).unwrap();
+            assert_eq!(graph.source(item), source);
+        }
+        assert_eq!(graph.item(4), Some(Item::Edge(0)));
+        assert_eq!(graph.item(8), Some(Item::Symbol(0)));
+        assert_eq!(graph.item(10), None);
+    }
+
+    #[test]
+    fn graph_survives_saving_and_loading() {
+        let mut graph = square();
+        graph
+            .set_label(Item::Node(2), Some("W1".to_string()))
+            .unwrap();
+        graph.set_kind(1, Some("axle counter".to_string())).unwrap();
+        let path = std::env::temp_dir().join(format!("plan-graph-{}.json", std::process::id()));
+
+        graph.save(&path).unwrap();
+        let loaded = PlanGraph::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, graph);
+        assert_eq!(loaded.label(Item::Node(2)), Some("W1"));
+        assert_eq!(loaded.symbols[1].kind.as_deref(), Some("axle counter"));
+    }
+
+    #[test]
+    fn loading_rejects_tracks_to_missing_nodes() {
+        let mut graph = square();
+        graph.edges.push(edge(0, 7, "#000000"));
+        let path =
+            std::env::temp_dir().join(format!("plan-graph-dangling-{}.json", std::process::id()));
+
+        graph.save(&path).unwrap();
+        let loaded = PlanGraph::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(loaded.is_err());
+    }
+}
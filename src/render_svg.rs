@@ -0,0 +1,238 @@
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+use image::{GrayImage, Luma, Rgb, RgbImage};
+use imageproc::distance_transform::euclidean_squared_distance_transform;
+use resvg::{tiny_skia, usvg};
+
+use crate::{binarize::BinaryMask, transform::Transform};
+
+/// How the SVG compares with the ink of the page, see [`compare`]
+#[derive(Clone, Debug)]
+pub struct SvgDiff {
+    /// Share of the rendered ink within the tolerance of ink of the page
+    pub precision: f32,
+    /// Share of the ink of the page within the tolerance of rendered ink
+    pub recall: f32,
+    /// Harmonic mean of precision and recall, 1 for a perfect match
+    pub similarity: f32,
+    /// Ink of both in grey, ink of the page the SVG misses from yellow to
+    /// red and ink the SVG adds from cyan to blue, the further from the
+    /// other the stronger
+    pub heatmap: RgbImage,
+}
+
+/// Rasterises an SVG written by [`crate::svg_helper::save_and_draw_svg`]
+/// into a mask of `size` pixels. `to_pixels` maps its user units, which span
+/// `view_box` units, to the pixels. Text is left out, no fonts are loaded.
+pub fn rasterize(
+    path: impl AsRef<Path>,
+    to_pixels: &Transform,
+    view_box: (f32, f32),
+    size: (u32, u32),
+) -> anyhow::Result<BinaryMask> {
+    let path = path.as_ref();
+    ensure!(
+        to_pixels.is_affine(),
+        "an SVG can only be rasterised with an affine transform"
+    );
+
+    let data = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let tree = usvg::Tree::from_data(&data, &usvg::Options::default())
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+
+    // The tree is laid out in CSS pixels of its width and height, not user units
+    let units_per_pixel = Transform::scale(
+        view_box.0 / tree.size().width(),
+        view_box.1 / tree.size().height(),
+    );
+    let m = units_per_pixel.then(to_pixels).0;
+
+    let mut pixmap = tiny_skia::Pixmap::new(size.0, size.1)
+        .with_context(|| format!("can't rasterise {} x {} pixels", size.0, size.1))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_row(
+            m[(0, 0)] as f32,
+            m[(1, 0)] as f32,
+            m[(0, 1)] as f32,
+            m[(1, 1)] as f32,
+            m[(0, 2)] as f32,
+            m[(1, 2)] as f32,
+        ),
+        &mut pixmap.as_mut(),
+    );
+
+    let pixels = pixmap.pixels();
+    Ok(BinaryMask::from_fn(size.0, size.1, |x, y| {
+        pixels[(y * size.0 + x) as usize].alpha() >= 128
+    }))
+}
+
+/// Distance of every pixel to the closest ink of the mask, row by row,
+/// infinite without any ink
+fn distances(mask: &BinaryMask) -> Vec<f32> {
+    // The transform measures the distance to the closest non-zero pixel
+    let ink = GrayImage::from_fn(mask.width(), mask.height(), |x, y| {
+        Luma([mask.is_ink(x, y) as u8 * 255])
+    });
+    euclidean_squared_distance_transform(&ink)
+        .pixels()
+        .map(|d| (d.0[0] as f32).sqrt())
+        .collect()
+}
+
+/// Colour between `near` and `far` for a distance of `distance`, reaching
+/// `far` at `full`
+fn ramp(near: [u8; 3], far: [u8; 3], distance: f32, full: f32) -> Rgb<u8> {
+    let t = (distance / full).clamp(0., 1.);
+    Rgb(std::array::from_fn(|i| {
+        (near[i] as f32 + (far[i] as f32 - near[i] as f32) * t).round() as u8
+    }))
+}
+
+/// Compares the rendered ink with the ink of the page, ink counting as
+/// matched within `tolerance` pixels of ink of the other
+pub fn compare(page: &BinaryMask, rendered: &BinaryMask, tolerance: f32) -> SvgDiff {
+    let (width, height) = (page.width(), page.height());
+    let to_page = distances(page);
+    let to_rendered = distances(rendered);
+
+    let mut heatmap = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+    let (mut page_ink, mut found) = (0, 0);
+    let (mut rendered_ink, mut confirmed) = (0, 0);
+    // Mismatches this far off get the strongest colour
+    let full = 4. * tolerance.max(1.);
+
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) as usize;
+            let (on_page, on_render) = (page.is_ink(x, y), rendered.is_ink(x, y));
+
+            let missed = on_page && to_rendered[i] > tolerance;
+            let added = on_render && to_page[i] > tolerance;
+
+            page_ink += on_page as usize;
+            found += (on_page && !missed) as usize;
+            rendered_ink += on_render as usize;
+            confirmed += (on_render && !added) as usize;
+
+            let color = match (missed, added) {
+                (true, _) => ramp([255, 220, 0], [220, 0, 0], to_rendered[i], full),
+                (_, true) => ramp([0, 220, 255], [0, 0, 220], to_page[i], full),
+                _ if on_page || on_render => Rgb([170, 170, 170]),
+                _ => continue,
+            };
+            heatmap.put_pixel(x, y, color);
+        }
+    }
+
+    let share = |part: usize, whole: usize| match whole {
+        0 => 1.,
+        _ => part as f32 / whole as f32,
+    };
+    let (precision, recall) = (share(confirmed, rendered_ink), share(found, page_ink));
+    let similarity = match precision + recall {
+        sum if sum > 0. => 2. * precision * recall / sum,
+        _ => 0.,
+    };
+
+    SvgDiff {
+        precision,
+        recall,
+        similarity,
+        heatmap,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mask of a horizontal line `thickness` pixels thick at row `y`
+    fn line(y: u32, thickness: u32) -> BinaryMask {
+        BinaryMask::from_fn(100, 60, |x, row| {
+            (10..90).contains(&x) && (y..y + thickness).contains(&row)
+        })
+    }
+
+    #[test]
+    fn identical_masks_match_perfectly() {
+        let page = line(30, 3);
+        let diff = compare(&page, &page, 1.);
+
+        assert_eq!((diff.precision, diff.recall, diff.similarity), (1., 1., 1.));
+        assert_eq!(*diff.heatmap.get_pixel(50, 31), Rgb([170, 170, 170]));
+        assert_eq!(*diff.heatmap.get_pixel(50, 10), Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn an_empty_rendering_finds_nothing() {
+        let diff = compare(&line(30, 3), &BinaryMask::new(100, 60), 2.);
+
+        assert_eq!(diff.recall, 0.);
+        assert_eq!(diff.similarity, 0.);
+        assert_eq!(*diff.heatmap.get_pixel(50, 31), Rgb([220, 0, 0]));
+    }
+
+    #[test]
+    fn an_empty_page_confirms_nothing() {
+        let diff = compare(&BinaryMask::new(100, 60), &line(30, 3), 2.);
+
+        assert_eq!(diff.precision, 0.);
+        assert_eq!(diff.recall, 1.);
+        assert_eq!(diff.similarity, 0.);
+    }
+
+    #[test]
+    fn shifts_within_the_tolerance_match() {
+        let diff = compare(&line(30, 3), &line(32, 3), 2.);
+
+        assert_eq!((diff.precision, diff.recall), (1., 1.));
+    }
+
+    #[test]
+    fn shifts_beyond_the_tolerance_are_missed_and_added() {
+        let diff = compare(&line(30, 3), &line(40, 3), 2.);
+
+        assert_eq!((diff.precision, diff.recall), (0., 0.));
+        let missed = diff.heatmap.get_pixel(50, 31);
+        let added = diff.heatmap.get_pixel(50, 41);
+        // Yellow to red on the page, cyan to blue from the rendering
+        assert!(missed[0] > 200 && missed[2] == 0, "{:?}", missed);
+        assert!(added[2] > 200 && added[0] == 0, "{:?}", added);
+    }
+
+    #[test]
+    fn rasterising_scales_user_units_to_pixels() {
+        let path = std::env::temp_dir().join("railify_rasterize_test.svg");
+        std::fs::write(
+            &path,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="50mm" height="25mm" viewBox="0 0 50 25">
+  <line x1="5" y1="10" x2="45" y2="10" stroke="black" stroke-width="2"/>
+  <circle cx="40" cy="20" r="2" fill="red"/>
+  <text x="5" y="20">ignored</text>
+</svg>"#,
+        )
+        .unwrap();
+
+        // Two pixels per user unit
+        let mask = rasterize(&path, &Transform::scale(2., 2.), (50., 25.), (100, 50)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(mask.is_ink(50, 20));
+        assert!(mask.is_ink(12, 19));
+        assert!(!mask.is_ink(50, 24));
+        assert!(!mask.is_ink(5, 20));
+        assert!(mask.is_ink(80, 40));
+        assert!(!mask.is_ink(15, 38));
+    }
+
+    #[test]
+    fn rasterising_needs_an_affine_transform() {
+        let mut m = Transform::identity().0;
+        m[(2, 0)] = 0.01;
+
+        assert!(rasterize("missing.svg", &Transform(m), (1., 1.), (1, 1)).is_err());
+    }
+}
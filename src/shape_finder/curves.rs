@@ -0,0 +1,301 @@
+use std::collections::{HashMap, VecDeque};
+
+use nalgebra::{Matrix3, Vector3};
+use nannou::glam::Vec2;
+
+use super::{CurveGeometry, Point};
+use crate::config::DetectionConfig;
+
+/// Breadth first search steps grouped into one vertex of the centre line
+const BIN_STEPS: usize = 3;
+
+const NEIGHBOURS: [(isize, isize); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+/// Steps from `from` to every pixel of the component, `usize::MAX` if unreachable
+fn steps_from(
+    pixels: &[(usize, usize)],
+    index: &HashMap<(usize, usize), usize>,
+    from: usize,
+) -> Vec<usize> {
+    let mut steps = vec![usize::MAX; pixels.len()];
+    let mut queue = VecDeque::from([from]);
+    steps[from] = 0;
+
+    while let Some(i) = queue.pop_front() {
+        let (x, y) = pixels[i];
+        for (dx, dy) in NEIGHBOURS {
+            let (Some(x), Some(y)) = (x.checked_add_signed(dx), y.checked_add_signed(dy)) else {
+                continue;
+            };
+            if let Some(&j) = index.get(&(x, y)) {
+                if steps[j] == usize::MAX {
+                    steps[j] = steps[i] + 1;
+                    queue.push_back(j);
+                }
+            }
+        }
+    }
+
+    steps
+}
+
+fn farthest(steps: &[usize]) -> usize {
+    steps
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| **s != usize::MAX)
+        .max_by_key(|(_, s)| **s)
+        .map(|(i, _)| i)
+        .unwrap_or_default()
+}
+
+fn distance_to_segment(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = match ab.length_squared() {
+        l if l > 0. => ((p - a).dot(ab) / l).clamp(0., 1.),
+        _ => 0.,
+    };
+    p.distance(a + ab * t)
+}
+
+/// Centre line of a thin stroke and its mean width, `None` if the pixels are
+/// too short, too wide or branch out.
+///
+/// The pixels are walked from one end of the stroke to the other and every
+/// few steps of the walk are averaged into a vertex.
+pub(super) fn centre_line(
+    pixels: &[(usize, usize)],
+    config: &DetectionConfig,
+) -> Option<(Vec<Vec2>, f32)> {
+    if pixels.is_empty() {
+        return None;
+    }
+
+    let index: HashMap<_, _> = pixels.iter().enumerate().map(|(i, p)| (*p, i)).collect();
+
+    let start = farthest(&steps_from(pixels, &index, 0));
+    let steps = steps_from(pixels, &index, start);
+    let bins = steps[farthest(&steps)] / BIN_STEPS + 1;
+
+    let mut sums = vec![(Vec2::ZERO, 0usize); bins];
+    for (p, s) in pixels.iter().zip(&steps) {
+        let sum = &mut sums[s / BIN_STEPS];
+        sum.0 += Vec2::new(p.0 as f32, p.1 as f32);
+        sum.1 += 1;
+    }
+
+    let vertices: Vec<_> = sums.iter().map(|(sum, n)| *sum / *n as f32).collect();
+    if vertices.len() < 2 {
+        return None;
+    }
+
+    let length: f32 = vertices.windows(2).map(|w| w[0].distance(w[1])).sum();
+    if length < config.curve_min_length {
+        return None;
+    }
+
+    // Every pixel has to be close to the part of the centre line it was averaged into
+    let reach = config.curve_max_width / 2. + BIN_STEPS as f32 / 2.;
+    let close = pixels.iter().zip(&steps).all(|(p, s)| {
+        let p = Vec2::new(p.0 as f32, p.1 as f32);
+        let bin = s / BIN_STEPS;
+        let from = bin.saturating_sub(1);
+        let to = (bin + 1).min(vertices.len() - 1);
+
+        (from..to).any(|i| distance_to_segment(p, vertices[i], vertices[i + 1]) <= reach)
+    });
+    if !close {
+        return None;
+    }
+
+    Some((vertices, pixels.len() as f32 / length))
+}
+
+/// Centre and radius of the circle closest to the points, `None` if they are collinear
+fn fit_circle(points: &[Vec2]) -> Option<(Vec2, f32)> {
+    let mean = points.iter().sum::<Vec2>() / points.len() as f32;
+
+    // Least squares solution of x² + y² + d x + e y + f = 0 around the mean
+    let mut a = Matrix3::<f64>::zeros();
+    let mut b = Vector3::<f64>::zeros();
+    for p in points {
+        let (x, y) = ((p.x - mean.x) as f64, (p.y - mean.y) as f64);
+        let row = Vector3::new(x, y, 1.);
+        a += row * row.transpose();
+        b -= row * (x * x + y * y);
+    }
+
+    let solution = a.lu().solve(&b)?;
+    let centre = Vec2::new(-solution[0] as f32 / 2., -solution[1] as f32 / 2.);
+    let radius = (centre.length_squared() - solution[2] as f32).sqrt();
+
+    match radius.is_finite() {
+        true => Some((centre + mean, radius)),
+        false => None,
+    }
+}
+
+/// Douglas-Peucker simplification, keeps both ends
+fn simplify(points: &[Vec2], tolerance: f32) -> Vec<Vec2> {
+    let (first, last) = (points[0], points[points.len() - 1]);
+
+    let farthest = points[1..points.len() - 1]
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (i + 1, distance_to_segment(*p, first, last)))
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+
+    match farthest {
+        Some((i, d)) if d > tolerance => {
+            let mut res = simplify(&points[..=i], tolerance);
+            res.pop();
+            res.extend(simplify(&points[i..], tolerance));
+            res
+        }
+        _ => vec![first, last],
+    }
+}
+
+/// Course between the first and last vertex, `None` if the vertices are straight
+pub(super) fn fit(vertices: &[Vec2], tolerance: f32) -> Option<CurveGeometry> {
+    let (start, end) = (vertices[0], vertices[vertices.len() - 1]);
+
+    if vertices
+        .iter()
+        .all(|p| distance_to_segment(*p, start, end) <= tolerance)
+    {
+        return None;
+    }
+
+    if let Some((centre, radius)) = fit_circle(vertices) {
+        let error = vertices
+            .iter()
+            .map(|p| (p.distance(centre) - radius).powi(2))
+            .sum::<f32>()
+            / vertices.len() as f32;
+
+        if error.sqrt() <= tolerance {
+            let angle = |p: Vec2| (p - centre).y.atan2((p - centre).x);
+            let turn =
+                |from: Vec2, to: Vec2| (angle(to) - angle(from)).rem_euclid(std::f32::consts::TAU);
+
+            // Counter clockwise unless the middle of the stroke lies the other way round
+            let counter_clockwise = turn(start, end);
+            let middle = vertices[vertices.len() / 2];
+            let sweep = match turn(start, middle) <= counter_clockwise {
+                true => counter_clockwise,
+                false => counter_clockwise - std::f32::consts::TAU,
+            };
+
+            return Some(CurveGeometry::Arc {
+                center: Point(centre.x, centre.y),
+                radius,
+                sweep,
+            });
+        }
+    }
+
+    let points = simplify(vertices, tolerance);
+
+    Some(CurveGeometry::Polyline(
+        points[1..points.len() - 1]
+            .iter()
+            .map(|p| Point(p.x, p.y))
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    /// Pixels within 1.5 of the course, sampled from `t` 0 to 1
+    fn stroke(course: impl Fn(f32) -> Vec2) -> Vec<(usize, usize)> {
+        let mut pixels = HashSet::new();
+        for i in 0..=2000 {
+            let p = course(i as f32 / 2000.);
+            for dy in -2..=2 {
+                for dx in -2..=2 {
+                    let (x, y) = (p.x.round() + dx as f32, p.y.round() + dy as f32);
+                    if Vec2::new(x, y).distance(p) <= 1.5 {
+                        pixels.insert((x as usize, y as usize));
+                    }
+                }
+            }
+        }
+
+        let mut pixels: Vec<_> = pixels.into_iter().collect();
+        pixels.sort();
+        pixels
+    }
+
+    fn geometry(pixels: &[(usize, usize)]) -> Option<CurveGeometry> {
+        let config = DetectionConfig::default();
+        let (vertices, _) = centre_line(pixels, &config).expect("a thin stroke");
+        fit(&vertices, config.curve_tolerance)
+    }
+
+    #[test]
+    fn arc_is_recovered() {
+        let (centre, radius) = (Vec2::new(200., 200.), 100.);
+        let (from, to) = (20_f32.to_radians(), 110_f32.to_radians());
+        let pixels = stroke(|t| {
+            let angle = from + (to - from) * t;
+            centre + radius * Vec2::new(angle.cos(), angle.sin())
+        });
+
+        let tolerance = DetectionConfig::default().curve_tolerance;
+        match geometry(&pixels) {
+            Some(CurveGeometry::Arc {
+                center,
+                radius: found,
+                sweep,
+            }) => {
+                assert!(
+                    Vec2::new(center.0, center.1).distance(centre) <= tolerance,
+                    "centre {:?}",
+                    center
+                );
+                assert!((found - radius).abs() <= tolerance, "radius {}", found);
+                // The walk may start at either end
+                assert!(
+                    (sweep.abs() - (to - from)).abs() < 0.1,
+                    "sweep {}",
+                    sweep.to_degrees()
+                );
+            }
+            other => panic!("expected an arc, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn s_curve_falls_back_to_a_polyline() {
+        let pixels = stroke(|t| {
+            let x = 20. + 240. * t;
+            Vec2::new(x, 100. + 30. * (t * std::f32::consts::TAU).sin())
+        });
+
+        match geometry(&pixels) {
+            Some(CurveGeometry::Polyline(corners)) => assert!(corners.len() >= 2),
+            other => panic!("expected a polyline, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn straight_stroke_is_not_a_curve() {
+        let pixels = stroke(|t| Vec2::new(20. + 200. * t, 50. + 40. * t));
+
+        assert_eq!(geometry(&pixels), None);
+    }
+}
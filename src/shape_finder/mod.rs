@@ -1,17 +1,31 @@
-use core::{f32, panic};
-use std::{cmp::Ordering, collections::HashMap, usize};
+use core::panic;
+use std::{cmp::Ordering, collections::HashMap};
 
-use image::RgbImage;
+use image::Rgb;
 use iter_tools::Itertools;
-use nalgebra::ComplexField;
-use nannou::{glam::Vec2, prelude::Float};
+use nannou::glam::Vec2;
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    binarize::BinaryMask,
+    config::DetectionConfig,
+    layers::ColorLayers,
+    spatial_index::{Bounded, SpatialIndex},
+    transform::image_to_shapes,
+};
+
+mod curves;
+mod primitives;
+mod tiles;
+
+#[derive(Clone, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct Point(pub f32, pub f32);
 
-impl Into<Vec2> for Point {
-    fn into(self) -> Vec2 {
-        Vec2::new(self.0 as f32, self.1 as f32)
+impl From<Point> for Vec2 {
+    fn from(value: Point) -> Self {
+        Vec2::new(value.0, value.1)
     }
 }
 
@@ -21,11 +35,80 @@ impl Point {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LineStyle {
+    Solid,
+    Dashed,
+    Dotted,
+    /// Two close parallel strokes drawn as one track
+    Double,
+}
+
+/// What a track is used for, told apart by its stroke width, see
+/// [`crate::config::ThicknessConfig`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ThicknessClass {
+    MainTrack,
+    Siding,
+    Auxiliary,
+}
+
+/// How closely a shape follows the ink it was found from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fit {
+    /// Ink pixels the shape was found from
+    pub pixels: usize,
+    /// Root mean square distance of those pixels to the centre line of the
+    /// stroke, or to the area of filled shapes, in pixels
+    pub residual: f32,
+}
+
+impl Fit {
+    /// Fit of a shape joining two, whose centre lines are `offset_a` and
+    /// `offset_b` away from the joint one
+    fn joined(a: &Option<Fit>, offset_a: f32, b: &Option<Fit>, offset_b: f32) -> Option<Fit> {
+        let parts: Vec<_> = [(a, offset_a), (b, offset_b)]
+            .into_iter()
+            .filter_map(|(fit, offset)| Some((fit.as_ref()?, offset)))
+            .collect();
+        if parts.is_empty() {
+            return None;
+        }
+        let pixels: usize = parts.iter().map(|(fit, _)| fit.pixels).sum();
+
+        let squares: f32 = parts
+            .iter()
+            .map(|(fit, offset)| fit.pixels as f32 * (fit.residual.powi(2) + offset.powi(2)))
+            .sum();
+
+        Some(Fit {
+            pixels,
+            residual: (squares / pixels.max(1) as f32).sqrt(),
+        })
+    }
+
+    /// Fit of the part of a shape with `share` of its length
+    fn part(&self, share: f32) -> Fit {
+        Fit {
+            pixels: (self.pixels as f32 * share).round() as usize,
+            residual: self.residual,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Line {
     pub start: Point,
     pub end: Point,
+    /// Stroke width in rendered pixels
     pub thickness: f32,
+    /// Stroke width in sheet millimetres, known once the page scale is
+    pub stroke_width: f32,
+    /// Colour the line was drawn in, see [`ColorLayers`]
+    pub color: Rgb<u8>,
+    pub style: LineStyle,
+    /// How closely the shape follows its ink, `None` unless it was found on the page
+    pub fit: Option<Fit>,
 }
 
 impl Point {
@@ -64,8 +147,8 @@ impl TryFrom<Vec<(usize, usize)>> for Line {
     fn try_from(value: Vec<(usize, usize)>) -> Result<Self, Self::Error> {
         let mut map: HashMap<usize, usize> = HashMap::new();
 
-        for (x, y) in &value {
-            if let Some(v) = map.get_mut(&y) {
+        for (_, y) in &value {
+            if let Some(v) = map.get_mut(y) {
                 *v += 1;
             } else {
                 map.insert(*y, 1);
@@ -81,8 +164,8 @@ impl TryFrom<Vec<(usize, usize)>> for Line {
         if max - min < 3 {
             map.clear();
 
-            for (x, y) in &value {
-                if let Some(v) = map.get_mut(&x) {
+            for (x, _) in &value {
+                if let Some(v) = map.get_mut(x) {
                     *v += 1;
                 } else {
                     map.insert(*x, 1);
@@ -103,6 +186,10 @@ impl TryFrom<Vec<(usize, usize)>> for Line {
                     start: Point(*min_x as _, *max_y as _),
                     end: Point(*max_x as _, *min_y as _),
                     thickness: ((min + max) / 2) as f32,
+                    stroke_width: 0.,
+                    color: Rgb([0, 0, 0]),
+                    style: LineStyle::Solid,
+                    fit: None,
                 });
             }
         }
@@ -112,33 +199,250 @@ impl TryFrom<Vec<(usize, usize)>> for Line {
 }
 
 impl Line {
+    /// Unit vector from start to end, `None` for lines without length
+    fn direction(&self) -> Option<Vec2> {
+        (self.end.to_vec() - self.start.to_vec()).try_normalize()
+    }
+
+    /// How far `other` is from continuing this line: the distance of its end
+    /// points from the line through this one plus the gap between the two along
+    /// it, less the stroke widths so touching strokes count as identical.
+    /// The orientation of either line doesn't matter.
     fn similarity_to(&self, other: &Line) -> f32 {
-        let start_diff = self.start.to_vec() - other.start.to_vec();
-        let end_diff = self.end.to_vec() - other.end.to_vec();
+        let origin = self.start.to_vec();
+
+        let Some(direction) = self.direction() else {
+            // A single point, compare with the closest end of the other line
+            return other.distance_to_point(&self.start) - self.thickness - other.thickness;
+        };
+
+        let offset = |p: &Point| direction.perp_dot(p.to_vec() - origin).abs();
+        let along = |p: &Point| direction.dot(p.to_vec() - origin);
 
-        start_diff.length() + end_diff.length() - self.thickness - other.thickness
+        let length = self.start.distance(&self.end);
+        let (a, b) = (along(&other.start), along(&other.end));
+        let gap = (a.min(b) - length).max(-a.max(b)).max(0.);
+
+        offset(&other.start) + offset(&other.end) + gap - self.thickness - other.thickness
     }
 
+    /// Joins two roughly collinear lines into one covering both, keeping the
+    /// orientation of `self`. The stroke of the result spans both strokes.
     fn merge_with(&self, other: &Line) -> Line {
-        let start_diff = self.start.to_vec() - other.start.to_vec();
-        let end_diff = self.end.to_vec() - other.end.to_vec();
+        let direction = self
+            .direction()
+            .or_else(|| other.direction())
+            .unwrap_or(Vec2::X);
+        let normal = direction.perp();
+
+        // Across the line, relative to the centre of this stroke
+        let origin = self.start.to_vec();
+        let offset = normal.dot((other.start.to_vec() + other.end.to_vec()) / 2. - origin);
+        let low = (-self.thickness / 2.).min(offset - other.thickness / 2.);
+        let high = (self.thickness / 2.).max(offset + other.thickness / 2.);
+
+        let origin = origin + normal * (low + high) / 2.;
+        let (min, max) = [&self.start, &self.end, &other.start, &other.end]
+            .iter()
+            .map(|p| direction.dot(p.to_vec() - origin))
+            .fold((f32::MAX, f32::MIN), |(min, max), t| {
+                (min.min(t), max.max(t))
+            });
+
+        let start = origin + direction * min;
+        let end = origin + direction * max;
+        let centre = (low + high) / 2.;
+
+        Line {
+            start: Point(start.x, start.y),
+            end: Point(end.x, end.y),
+            thickness: high - low,
+            stroke_width: self.stroke_width.max(other.stroke_width),
+            color: self.color,
+            style: self.style,
+            fit: Fit::joined(&self.fit, -centre, &other.fit, offset - centre),
+        }
+    }
+}
+
+/// Course of a [`Curve`] between its end points
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CurveGeometry {
+    /// Circular arc, `sweep` is the angle from start to end in radians,
+    /// positive counter clockwise
+    Arc {
+        center: Point,
+        radius: f32,
+        sweep: f32,
+    },
+    /// Corners between start and end
+    Polyline(Vec<Point>),
+}
+
+/// Curved track
+#[derive(Clone, Debug, PartialEq)]
+pub struct Curve {
+    pub start: Point,
+    pub end: Point,
+    pub geometry: CurveGeometry,
+    /// Mean stroke width in rendered pixels
+    pub thickness: f32,
+    /// Stroke width in sheet millimetres, known once the page scale is
+    pub stroke_width: f32,
+    pub color: Rgb<u8>,
+    /// How closely the shape follows its ink, `None` unless it was found on the page
+    pub fit: Option<Fit>,
+}
+
+/// Largest angle between the points an arc is approximated with
+const ARC_STEP: f32 = std::f32::consts::PI / 36.;
+
+impl Curve {
+    /// The curve approximated by straight segments, from start to end
+    pub fn points(&self) -> Vec<Point> {
+        let mut points = vec![self.start.clone()];
+
+        match &self.geometry {
+            CurveGeometry::Arc {
+                center,
+                radius,
+                sweep,
+            } => {
+                let from = (self.start.1 - center.1).atan2(self.start.0 - center.0);
+                let steps = (sweep.abs() / ARC_STEP).ceil() as usize;
+
+                points.extend((1..steps).map(|i| {
+                    let angle = from + sweep * i as f32 / steps as f32;
+                    Point(
+                        center.0 + radius * angle.cos(),
+                        center.1 + radius * angle.sin(),
+                    )
+                }));
+            }
+            CurveGeometry::Polyline(corners) => points.extend(corners.iter().cloned()),
+        }
+
+        points.push(self.end.clone());
+        points
+    }
 
+    /// Straight line between the end points, carrying the attributes of the curve
+    pub fn chord(&self) -> Line {
         Line {
-            start: Point(self.start.0 + start_diff.x, self.start.1 + start_diff.y),
-            end: Point(self.end.0 + end_diff.x, self.end.1 + end_diff.y),
-            thickness: self.thickness + other.thickness,
+            start: self.start.clone(),
+            end: self.end.clone(),
+            thickness: self.thickness,
+            stroke_width: self.stroke_width,
+            color: self.color,
+            style: LineStyle::Solid,
+            fit: self.fit.clone(),
+        }
+    }
+
+    /// The same curve running from end to start
+    pub fn reversed(&self) -> Curve {
+        let geometry = match &self.geometry {
+            CurveGeometry::Arc {
+                center,
+                radius,
+                sweep,
+            } => CurveGeometry::Arc {
+                center: center.clone(),
+                radius: *radius,
+                sweep: -sweep,
+            },
+            CurveGeometry::Polyline(corners) => {
+                CurveGeometry::Polyline(corners.iter().rev().cloned().collect())
+            }
+        };
+
+        Curve {
+            start: self.end.clone(),
+            end: self.start.clone(),
+            geometry,
+            ..self.clone()
         }
     }
 }
 
+/// Ring or disc, e.g. part of a signal or axle counter symbol
+#[derive(Clone, Debug, PartialEq)]
+pub struct Circle {
+    pub center: Point,
+    /// To the middle of the ring for hollow circles, to the edge for filled ones
+    pub radius: f32,
+    pub filled: bool,
+    pub color: Rgb<u8>,
+    /// How closely the shape follows its ink, `None` unless it was found on the page
+    pub fit: Option<Fit>,
+}
+
+/// Box outline or filled box, possibly rotated
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rectangle {
+    pub center: Point,
+    /// Side along `angle`
+    pub width: f32,
+    pub height: f32,
+    /// Counter clockwise rotation in radians
+    pub angle: f32,
+    pub filled: bool,
+    pub color: Rgb<u8>,
+    /// How closely the shape follows its ink, `None` unless it was found on the page
+    pub fit: Option<Fit>,
+}
+
+impl Rectangle {
+    /// Corners in counter clockwise order
+    pub fn corners(&self) -> [Point; 4] {
+        let axis = Vec2::new(self.angle.cos(), self.angle.sin()) * self.width / 2.;
+        let normal = axis.perp().normalize_or_zero() * self.height / 2.;
+        let center = self.center.to_vec();
+
+        [
+            center - axis - normal,
+            center + axis - normal,
+            center + axis + normal,
+            center - axis + normal,
+        ]
+        .map(|c| Point(c.x, c.y))
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Shape {
     Line(Line),
+    Curve(Curve),
+    Circle(Circle),
+    Rectangle(Rectangle),
     Point(Point, Vec<Point>, bool),
-    Custom(Vec<(usize, usize)>),
+    Custom(Vec<(usize, usize)>, Rgb<u8>),
 }
 
 impl Shape {
+    /// Colour the shape was drawn in, junction points have none
+    pub fn color(&self) -> Option<&Rgb<u8>> {
+        match self {
+            Self::Line(l) => Some(&l.color),
+            Self::Curve(c) => Some(&c.color),
+            Self::Circle(c) => Some(&c.color),
+            Self::Rectangle(r) => Some(&r.color),
+            Self::Point(..) => None,
+            Self::Custom(_, color) => Some(color),
+        }
+    }
+
+    /// How closely the shape follows the ink it was found from, see [`Fit`]
+    pub fn fit(&self) -> Option<&Fit> {
+        match self {
+            Self::Line(l) => l.fit.as_ref(),
+            Self::Curve(c) => c.fit.as_ref(),
+            Self::Circle(c) => c.fit.as_ref(),
+            Self::Rectangle(r) => r.fit.as_ref(),
+            Self::Point(..) | Self::Custom(..) => None,
+        }
+    }
+
     fn similarity_to(&self, other: &Shape) -> f32 {
         match (self, other) {
             (Self::Line(a), Self::Line(b)) => a.similarity_to(b),
@@ -154,63 +458,42 @@ impl Shape {
     }
 }
 
-const THRESHOLD: u8 = 200;
-
-fn horizzontal_lines_from_image(img: &mut RgbImage) -> Vec<Shape> {
-    const MIN_LINE_LEN: usize = 200;
+/// Shapes as a stage of the detection left them, to step through in the viewer
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stage {
+    pub name: &'static str,
+    pub shapes: Vec<Shape>,
+}
 
+fn horizzontal_lines_from_image(
+    mask: &mut BinaryMask,
+    layers: &ColorLayers,
+    config: &DetectionConfig,
+    stages: &mut Vec<Stage>,
+) -> Vec<Shape> {
     // y , x0, x1
-    let mut horizzontal_lines: Vec<(usize, usize, usize, usize)> = vec![];
+    let mut horizzontal_lines: Vec<(usize, usize, usize, usize, LineStyle)> = vec![];
 
-    let mut min_x = usize::MAX;
-    let mut min_y = usize::MAX;
-
-    for (mut num, (_, row)) in img
-        .enumerate_rows()
-        .collect::<Vec<_>>()
-        .into_iter()
-        .rev()
-        .enumerate()
-    {
-        num += 3;
-        let mut prev: Option<usize> = None;
-        for (x, y, c) in row {
-            //if c[0] == 255 && c[1] == 255 && c[2] == 255 {
-            //    continue;
-            //}
-
-            if c[0] < THRESHOLD && c[1] < THRESHOLD && c[2] < THRESHOLD {
-                if prev.is_some() {
-                    continue;
-                }
+    let to_shapes = image_to_shapes(mask.height());
+    let row = |y: usize| to_shapes.apply(&Point(0., y as f32)).1 as usize;
 
-                prev = Some(x as usize);
-            } else {
-                if let Some(a) = prev {
-                    if x as usize - a >= MIN_LINE_LEN {
-                        min_x = min_x.min(a as usize);
-                        min_y = min_y.min(num as usize);
+    let runs = tiles::row_runs(mask);
+    let mut short_runs = vec![];
 
-                        horizzontal_lines.push((num as usize, a, x as usize - 1, y as usize));
-                    }
-                    prev = None
-                }
-            }
+    for (y, x0, x1) in runs {
+        let (y, a, x) = (y as usize, x0 as usize, x1 as usize);
+        if x - a >= config.min_line_length {
+            horizzontal_lines.push((row(y), a, x - 1, y, LineStyle::Solid));
+        } else {
+            short_runs.push((y, a, x));
         }
     }
 
-    for line in &mut horizzontal_lines {
-        for point in line.1..line.2 {
-            let pixel = img.get_pixel_mut(point as _, line.3 as _);
-            pixel.0 = [255, 255, 255];
-        }
-
-        //line.0 -= min_y;
-        //line.1 -= min_x;
-        //line.2 -= min_x;
+    for (y, a, x, style) in dash_chains(&short_runs, config) {
+        horizzontal_lines.push((row(y), a, x - 1, y, style));
     }
 
-    // merge all the lines
+    horizzontal_lines.sort_by_key(|l| (l.0, l.1));
 
     let lines: Vec<_> = horizzontal_lines
         .iter()
@@ -219,38 +502,62 @@ fn horizzontal_lines_from_image(img: &mut RgbImage) -> Vec<Shape> {
                 start: Point(l.1 as f32, l.0 as f32),
                 end: Point(l.2 as f32, l.0 as f32),
                 thickness: 1.,
+                stroke_width: 0.,
+                color: layers.dominant_color((l.1..=l.2).map(|x| (x as u32, l.3 as u32))),
+                style: l.4,
+                fit: Some(Fit {
+                    pixels: l.2 - l.1 + 1,
+                    residual: 0.,
+                }),
             })
         })
         .collect();
 
-    let mut iter = lines.iter().peekable();
+    stages.push(Stage {
+        name: "raw lines",
+        shapes: lines.clone(),
+    });
 
-    let mut lines = vec![];
+    // Runs of the rows of a stroke, as a line and the last row merged into
+    // it. Rows with a gap in between are kept apart so double lines stay two
+    // lines, and runs only join a stroke they overlap so strokes sharing
+    // their rows, like a track broken by a symbol, stay separate.
+    let mut strokes: Vec<(Shape, f32)> = vec![];
+    let mut open: Vec<usize> = vec![];
 
-    let next = iter.next();
-    if next.is_some() {
-        let mut next = next.unwrap().clone();
-        loop {
-            let peek = iter.peek();
+    for line in lines {
+        let row = row_of(&line);
+        open.retain(|i| row - strokes[*i].1 <= 1.);
 
-            if peek.is_none() {
-                lines.push(next.clone());
-                break;
-            }
-
-            let peek = peek.unwrap();
-
-            if next.similarity_to(peek) < 20. {
-                let peek = iter.next().unwrap();
+        let stroke = open
+            .iter()
+            .map(|i| (*i, &strokes[*i]))
+            .filter(|(_, (stroke, last_row))| {
+                let same_kind = match (stroke, &line) {
+                    (Shape::Line(a), Shape::Line(b)) => {
+                        a.style == b.style && layers.layer_of(&a.color) == layers.layer_of(&b.color)
+                    }
+                    _ => false,
+                };
+                let reachable = *last_row == row || columns_overlap(stroke, &line);
 
-                next = next.merge_with(peek);
-            } else {
-                lines.push(next.clone());
-                next = iter.next().unwrap().clone();
+                same_kind && reachable
+            })
+            .map(|(i, (stroke, _))| (i, stroke.similarity_to(&line)))
+            .filter(|(_, similarity)| *similarity < config.merge_similarity)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        match stroke {
+            Some((i, _)) => strokes[i] = (strokes[i].0.merge_with(&line), row),
+            None => {
+                open.push(strokes.len());
+                strokes.push((line, row));
             }
         }
     }
 
+    let lines: Vec<_> = strokes.into_iter().map(|(stroke, _)| stroke).collect();
+
     //for line in &mut lines {
     //    match line {
     //        Shape::Line(line) => println!("{:?} {:?} {}", line.start, line.end, line.thickness),
@@ -258,59 +565,444 @@ fn horizzontal_lines_from_image(img: &mut RgbImage) -> Vec<Shape> {
     //    }
     //}
 
-    return lines;
+    // Double lines are told apart by the background between their strokes,
+    // so the ink is only cleared once they are paired
+    let lines = merge_double_lines(lines, mask, layers, config);
+
+    for line in &mut horizzontal_lines {
+        for point in line.1..line.2 {
+            mask.set(point as _, line.3 as _, false);
+        }
+    }
+
+    lines
+}
+
+/// Whether the columns of two horizontal lines overlap
+fn columns_overlap(a: &Shape, b: &Shape) -> bool {
+    match (a, b) {
+        (Shape::Line(a), Shape::Line(b)) => {
+            let (a0, a1) = (a.start.0.min(a.end.0), a.start.0.max(a.end.0));
+            let (b0, b1) = (b.start.0.min(b.end.0), b.start.0.max(b.end.0));
+            a0 <= b1 && b0 <= a1
+        }
+        _ => false,
+    }
 }
 
-fn extract_shape(img: &mut RgbImage, x: usize, y: usize) -> Vec<(usize, usize)> {
-    let c = img.get_pixel_mut_checked(x as _, y as _);
+fn row_of(shape: &Shape) -> f32 {
+    match shape {
+        Shape::Line(l) => l.start.1,
+        _ => f32::MAX,
+    }
+}
 
-    if let Some(c) = c {
-        if c.0[0] < THRESHOLD && c.0[1] < THRESHOLD && c.0[2] < THRESHOLD {
-            c.0 = [255, 255, 255];
-            let mut res = vec![(x, (img.height() as usize - y))];
-            res.extend(extract_shape(img, x + 1, y));
-            res.extend(extract_shape(img, x - 1, y));
-            res.extend(extract_shape(img, x + 1, y + 1));
-            res.extend(extract_shape(img, x - 1, y + 1));
-            res.extend(extract_shape(img, x, y + 1));
-            res.extend(extract_shape(img, x + 1, y - 1));
-            res.extend(extract_shape(img, x - 1, y - 1));
-            res.extend(extract_shape(img, x, y - 1));
+/// How much the dash lengths and gaps of a row may vary, relative to their mean
+const MAX_DASH_VARIATION: f32 = 0.5;
 
-            return res;
+fn coefficient_of_variation(values: &[usize]) -> f32 {
+    let n = values.len() as f32;
+    let mean = values.iter().sum::<usize>() as f32 / n;
+    let variance = values
+        .iter()
+        .map(|v| (*v as f32 - mean).powi(2))
+        .sum::<f32>()
+        / n;
+
+    variance.sqrt() / mean
+}
+
+/// Chains of regularly spaced short runs on the same row, as (y, x0, x1 exclusive, style).
+///
+/// Text and symbols also produce short runs, but with irregular lengths and
+/// gaps, so only evenly repeating patterns become lines.
+fn dash_chains(
+    runs: &[(usize, usize, usize)],
+    config: &DetectionConfig,
+) -> Vec<(usize, usize, usize, LineStyle)> {
+    let mut res = vec![];
+
+    let accept = |chain: &[(usize, usize, usize)], res: &mut Vec<_>| {
+        if chain.len() < config.min_dashes {
+            return;
+        }
+
+        let (y, start, _) = chain[0];
+        let end = chain[chain.len() - 1].2;
+        if end - start < config.min_line_length {
+            return;
+        }
+
+        let lengths: Vec<_> = chain.iter().map(|(_, a, x)| x - a).collect();
+        let gaps: Vec<_> = chain.windows(2).map(|w| w[1].1 - w[0].2).collect();
+
+        if coefficient_of_variation(&lengths) > MAX_DASH_VARIATION
+            || coefficient_of_variation(&gaps) > MAX_DASH_VARIATION
+        {
+            return;
+        }
+
+        let mean_length = lengths.iter().sum::<usize>() as f32 / lengths.len() as f32;
+        let style = match mean_length <= config.dot_max_length as f32 {
+            true => LineStyle::Dotted,
+            false => LineStyle::Dashed,
+        };
+
+        res.push((y, start, end, style));
+    };
+
+    let mut chain: Vec<(usize, usize, usize)> = vec![];
+    for run in runs {
+        match chain.last() {
+            Some(last) if last.0 == run.0 && run.1 - last.2 <= config.dash_max_gap => {
+                chain.push(*run)
+            }
+            _ => {
+                accept(&chain, &mut res);
+                chain = vec![*run];
+            }
         }
     }
+    accept(&chain, &mut res);
 
-    vec![]
+    res
 }
 
-fn extract_shapes(img: &mut RgbImage) -> Vec<Shape> {
+/// Joins pairs of close parallel horizontal lines with background between
+/// them into a single [`LineStyle::Double`] line
+fn merge_double_lines(
+    lines: Vec<Shape>,
+    mask: &BinaryMask,
+    layers: &ColorLayers,
+    config: &DetectionConfig,
+) -> Vec<Shape> {
+    let index = SpatialIndex::new(lines.clone());
+    let mut consumed = vec![false; lines.len()];
     let mut res = vec![];
-    for y in 0..img.height() {
-        for x in 0..img.width() {
-            let c = img.get_pixel(x, y).0;
-
-            if c[0] < THRESHOLD && c[1] < THRESHOLD && c[2] < THRESHOLD {
-                let shape = extract_shape(img, x as _, y as _);
-
-                if shape.len() > 50 && shape.len() < 200 {
-                    res.push(
-                        Line::try_from(shape.clone())
-                            .map(|l| Shape::Line(l))
-                            .unwrap_or_else(|l| Shape::Custom(shape)),
-                    );
-                }
+
+    for (i, shape) in lines.iter().enumerate() {
+        if consumed[i] {
+            continue;
+        }
+        consumed[i] = true;
+
+        let Shape::Line(a) = shape else {
+            res.push(shape.clone());
+            continue;
+        };
+
+        let overlap = |b: &Line| {
+            let overlap = a.end.0.min(b.end.0) - a.start.0.max(b.start.0);
+            let shorter = (a.end.0 - a.start.0).min(b.end.0 - b.start.0);
+            overlap / shorter
+        };
+
+        let partner = index
+            .intersecting(&a.bounding_box().expanded(config.double_max_gap))
+            .filter(|(j, _)| !consumed[*j])
+            .filter_map(|(j, other)| match other {
+                Shape::Line(b) => Some((j, b)),
+                _ => None,
+            })
+            .filter(|(_, b)| {
+                a.style == LineStyle::Solid
+                    && b.style == LineStyle::Solid
+                    && layers.layer_of(&a.color) == layers.layer_of(&b.color)
+                    && (a.start.1 - b.start.1).abs() > 0.
+                    && (a.start.1 - b.start.1).abs() <= config.double_max_gap
+                    && overlap(b) >= 0.8
+                    && background_between(a, b, mask)
+            })
+            .min_by(|x, y| {
+                (a.start.1 - x.1.start.1)
+                    .abs()
+                    .total_cmp(&(a.start.1 - y.1.start.1).abs())
+            });
+
+        match partner {
+            Some((j, b)) => {
+                consumed[j] = true;
+                let y = (a.start.1 + b.start.1) / 2.;
+
+                res.push(Shape::Line(Line {
+                    start: Point(a.start.0.min(b.start.0), y),
+                    end: Point(a.end.0.max(b.end.0), y),
+                    thickness: a.thickness + b.thickness,
+                    stroke_width: a.stroke_width + b.stroke_width,
+                    color: a.color,
+                    style: LineStyle::Double,
+                    fit: Fit::joined(&a.fit, a.start.1 - y, &b.fit, b.start.1 - y),
+                }));
             }
+            None => res.push(shape.clone()),
         }
     }
 
     res
 }
 
-pub fn split_line(line: &Line, points: Vec<Point>) -> Vec<Line> {
+/// Share of the columns two overlapping horizontal strokes share that have to
+/// be background on the row halfway between them
+const MIN_BACKGROUND_BETWEEN: f32 = 0.5;
+
+/// Whether rows of background separate the strokes of two horizontal lines,
+/// rather than them being rows of one thick stroke
+fn background_between(a: &Line, b: &Line, mask: &BinaryMask) -> bool {
+    let (lower, upper) = match a.start.1 < b.start.1 {
+        true => (a, b),
+        false => (b, a),
+    };
+
+    // Rows of a stroke span its thickness around its centre
+    let first = lower.start.1 + (lower.thickness - 1.) / 2. + 1.;
+    let last = upper.start.1 - (upper.thickness - 1.) / 2. - 1.;
+    if first > last {
+        return false;
+    }
+
+    // Flipping y back from shapes to pixels
+    let to_pixels = image_to_shapes(mask.height());
+    let y = to_pixels.apply(&Point(0., (first + last) / 2.)).1.round();
+    if y < 0. || y >= mask.height() as f32 {
+        return false;
+    }
+
+    let x0 = a.start.0.max(b.start.0).max(0.) as u32;
+    let x1 = (a.end.0.min(b.end.0) as u32).min(mask.width() - 1);
+    if x0 > x1 {
+        return false;
+    }
+    let background = (x0..=x1).filter(|x| !mask.is_ink(*x, y as u32)).count();
+
+    background as f32 >= MIN_BACKGROUND_BETWEEN * (x1 - x0 + 1) as f32
+}
+
+/// Distance from the point to the centre line of the shape's stroke, or to its
+/// area if it is filled
+fn distance_to_shape(shape: &Shape, point: &Point) -> f32 {
+    match shape {
+        Shape::Line(line) => line.distance_to_point(point),
+        Shape::Curve(curve) => curve.distance_2(point).sqrt(),
+        Shape::Circle(circle) => {
+            let from_edge = circle.center.distance(point) - circle.radius;
+            match circle.filled {
+                true => from_edge.max(0.),
+                false => from_edge.abs(),
+            }
+        }
+        Shape::Rectangle(rectangle) => {
+            // Offsets from the sides in the frame of the rectangle, inside below 0
+            let axis = Vec2::new(rectangle.angle.cos(), rectangle.angle.sin());
+            let local = point.to_vec() - rectangle.center.to_vec();
+            let offset = Vec2::new(local.dot(axis).abs(), local.dot(axis.perp()).abs())
+                - Vec2::new(rectangle.width, rectangle.height) / 2.;
+
+            let outside = offset.max(Vec2::ZERO).length();
+            match (rectangle.filled, outside > 0.) {
+                (true, _) | (false, true) => outside,
+                (false, false) => -offset.max_element(),
+            }
+        }
+        Shape::Point(p, ..) => p.distance(point),
+        Shape::Custom(..) => 0.,
+    }
+}
+
+/// The shape with how closely it follows the pixels it was found from
+fn with_fit(mut shape: Shape, pixels: &[(usize, usize)]) -> Shape {
+    let squares: f32 = pixels
+        .iter()
+        .map(|(x, y)| distance_to_shape(&shape, &Point(*x as f32, *y as f32)).powi(2))
+        .sum();
+    let fit = Some(Fit {
+        pixels: pixels.len(),
+        residual: (squares / pixels.len().max(1) as f32).sqrt(),
+    });
+
+    match &mut shape {
+        Shape::Line(line) => line.fit = fit,
+        Shape::Curve(curve) => curve.fit = fit,
+        Shape::Circle(circle) => circle.fit = fit,
+        Shape::Rectangle(rectangle) => rectangle.fit = fit,
+        Shape::Point(..) | Shape::Custom(..) => {}
+    }
+    shape
+}
+
+fn extract_shapes(
+    mask: &mut BinaryMask,
+    layers: &ColorLayers,
+    config: &DetectionConfig,
+) -> Vec<Shape> {
+    let to_shapes = image_to_shapes(mask.height());
+
+    let shapes: Vec<_> = tiles::connected_components(mask)
+        .into_par_iter()
+        .filter(|pixels| pixels.len() > config.min_shape_pixels)
+        .filter_map(|pixels| {
+            let color = layers.dominant_color(pixels.iter().cloned());
+            let shape: Vec<_> = pixels
+                .iter()
+                .map(|(x, y)| {
+                    let p = to_shapes.apply(&Point(*x as f32, *y as f32));
+                    (p.0 as usize, p.1 as usize)
+                })
+                .collect();
+            let small = shape.len() < config.max_shape_pixels;
+
+            let line = match small {
+                true => Line::try_from(shape.clone()).ok(),
+                false => None,
+            };
+
+            let found = if let Some(circle) = primitives::circle(&shape, color, config) {
+                Shape::Circle(circle)
+            } else if let Some(rectangle) = primitives::rectangle(&shape, color, config) {
+                Shape::Rectangle(rectangle)
+            } else if let Some(line) = line {
+                Shape::Line(Line { color, ..line })
+            } else if let Some(stroke) = stroke_from_pixels(&shape, color, config) {
+                stroke
+            } else if small {
+                // Keeps its pixels rather than a fit
+                return Some((pixels, Shape::Custom(shape, color)));
+            } else {
+                return None;
+            };
+
+            Some((pixels, with_fit(found, &shape)))
+        })
+        .collect();
+
+    // Everything else stays in the mask as unresolved
+    for (x, y) in shapes.iter().flat_map(|(pixels, _)| pixels) {
+        mask.set(*x, *y, false);
+    }
+
+    shapes.into_iter().map(|(_, shape)| shape).collect()
+}
+
+/// Thin strokes too bent or too large for [`Line::try_from`], as a curve or
+/// a straight line running from left to right
+fn stroke_from_pixels(
+    pixels: &[(usize, usize)],
+    color: Rgb<u8>,
+    config: &DetectionConfig,
+) -> Option<Shape> {
+    let (vertices, thickness) = curves::centre_line(pixels, config)?;
+
+    let (first, last) = (vertices[0], vertices[vertices.len() - 1]);
+    let (start, end) = (Point(first.x, first.y), Point(last.x, last.y));
+    let reversed = start.partial_cmp(&end) == Some(Ordering::Greater);
+
+    let shape = match curves::fit(&vertices, config.curve_tolerance) {
+        Some(geometry) => {
+            let curve = Curve {
+                start,
+                end,
+                geometry,
+                thickness,
+                stroke_width: 0.,
+                color,
+                fit: None,
+            };
+
+            Shape::Curve(match reversed {
+                true => curve.reversed(),
+                false => curve,
+            })
+        }
+        None => {
+            let (start, end) = match reversed {
+                true => (end, start),
+                false => (start, end),
+            };
+
+            Shape::Line(Line {
+                start,
+                end,
+                thickness,
+                stroke_width: 0.,
+                color,
+                style: LineStyle::Solid,
+                fit: None,
+            })
+        }
+    };
+
+    Some(shape)
+}
+
+/// Positions along a line its stroke width is measured at
+const PROFILE_SAMPLES: usize = 9;
+
+/// Distance between the pixels sampled across a line
+const PROFILE_STEP: f32 = 0.5;
+
+/// How far from the detected centre line the stroke is looked for, in pixels
+const PROFILE_SEARCH: f32 = 4.;
+
+/// Profiles longer than this cross a filled area rather than a stroke
+const MAX_STROKE_WIDTH: f32 = 50.;
+
+/// Stroke width of the line in pixels, the median length of the ink runs
+/// crossing it perpendicularly on `ink`. `None` if none of the profiles hits ink,
+/// e.g. for lines shorter than a pixel.
+pub fn stroke_width(line: &Line, ink: &BinaryMask) -> Option<f32> {
+    let (width, height) = (ink.width() as f32, ink.height() as f32);
+
+    let to_image = image_to_shapes(ink.height()).inverse()?;
+    let a: Vec2 = to_image.apply(&line.start).into();
+    let b: Vec2 = to_image.apply(&line.end).into();
+    let normal = (b - a).perp().try_normalize()?;
+
+    let is_ink = |p: Vec2| {
+        let (x, y) = (p.x.round(), p.y.round());
+        x >= 0. && y >= 0. && x < width && y < height && ink.is_ink(x as u32, y as u32)
+    };
+
+    let search = (PROFILE_SEARCH / PROFILE_STEP) as i32;
+    let longest = (MAX_STROKE_WIDTH / PROFILE_STEP) as usize;
+
+    let mut widths: Vec<_> = (0..PROFILE_SAMPLES)
+        .filter_map(|i| {
+            let centre = a.lerp(b, (i as f32 + 0.5) / PROFILE_SAMPLES as f32);
+
+            let offset = (0..=search)
+                .flat_map(|k| [k, -k])
+                .map(|k| k as f32 * PROFILE_STEP)
+                .find(|o| is_ink(centre + normal * *o))?;
+            let centre = centre + normal * offset;
+
+            let run = |direction: f32| {
+                (1..=longest)
+                    .map(|k| centre + normal * direction * k as f32 * PROFILE_STEP)
+                    .take_while(|p| is_ink(*p))
+                    .count()
+            };
+
+            Some((run(1.) + run(-1.) + 1) as f32 * PROFILE_STEP)
+        })
+        .collect();
+
+    if widths.is_empty() {
+        return None;
+    }
+
+    widths.sort_by(f32::total_cmp);
+    Some(widths[widths.len() / 2])
+}
+
+pub fn split_line(
+    line: &Line,
+    points: &SpatialIndex<Point>,
+    config: &DetectionConfig,
+) -> Vec<Line> {
     let mut points: Vec<_> = points
-        .into_iter()
-        .filter(|p| /*&line.start != *p && &line.end != *p &&*/ line.distance_to_point(p) < 5.)
+        .intersecting(&line.bounding_box().expanded(config.split_distance))
+        .map(|(_, p)| p)
+        .filter(|p| /*&line.start != *p && &line.end != *p &&*/ line.distance_to_point(p) < config.split_distance)
+        .cloned()
         .collect();
 
     points.push(line.start.clone());
@@ -318,25 +1010,39 @@ pub fn split_line(line: &Line, points: Vec<Point>) -> Vec<Line> {
 
     points.sort_by(|a, b| a.partial_cmp(b).unwrap());
 
+    let length = line.start.distance(&line.end).max(f32::EPSILON);
+    let fit = |a: &Point, b: &Point| {
+        let share = a.distance(b) / length;
+        line.fit.as_ref().map(|fit| fit.part(share))
+    };
+
     let mut res = vec![];
     let mut iter = points.iter().peekable();
     let mut pivot = iter.next();
-    while let Some(mut peek) = iter.peek() {
-        let mut start = pivot.unwrap();
-        if start.distance(peek) > 3. {
-            match start.partial_cmp(&peek).unwrap() {
+    while let Some(peek) = iter.peek() {
+        let start = pivot.unwrap();
+        if start.distance(peek) > config.min_segment_length {
+            match start.partial_cmp(peek).unwrap() {
                 Ordering::Less => {
                     res.push(Line {
-                        start: start.clone().clone(),
-                        end: peek.clone().clone().clone(),
+                        start: start.clone(),
+                        end: (*peek).clone(),
                         thickness: line.thickness,
+                        stroke_width: line.stroke_width,
+                        color: line.color,
+                        style: line.style,
+                        fit: fit(start, peek),
                     });
                 }
                 _ => {
                     res.push(Line {
-                        end: start.clone().clone(),
-                        start: peek.clone().clone().clone(),
+                        end: start.clone(),
+                        start: (*peek).clone(),
                         thickness: line.thickness,
+                        stroke_width: line.stroke_width,
+                        color: line.color,
+                        style: line.style,
+                        fit: fit(start, peek),
                     });
                 }
             };
@@ -347,18 +1053,56 @@ pub fn split_line(line: &Line, points: Vec<Point>) -> Vec<Line> {
     res
 }
 
-pub fn shapes_from_image(img: &mut RgbImage) -> Vec<Shape> {
-    let mut figures = horizzontal_lines_from_image(img);
+pub fn shapes_from_image(
+    mask: &mut BinaryMask,
+    layers: &ColorLayers,
+    config: &DetectionConfig,
+) -> Vec<Shape> {
+    shapes_and_stages_from_image(mask, layers, config).0
+}
+
+/// [`shapes_from_image`] along with what the stages found on the way: the
+/// horizontal runs before they are merged, the merged lines with the
+/// diagonals and blobs, lines split at the ends of others and the ends of the
+/// tracks before they are clustered into junctions
+pub fn shapes_and_stages_from_image(
+    mask: &mut BinaryMask,
+    layers: &ColorLayers,
+    config: &DetectionConfig,
+) -> (Vec<Shape>, Vec<Stage>) {
+    let mut stages = vec![];
+
+    // The stages clear what they explained, the widths are measured on the full ink
+    let ink = mask.clone();
 
-    let diagonals = extract_shapes(img);
+    let mut figures = horizzontal_lines_from_image(mask, layers, config, &mut stages);
+
+    let diagonals = extract_shapes(mask, layers, config);
 
     figures.extend(diagonals);
 
+    figures.par_iter_mut().for_each(|figure| {
+        if let Shape::Line(line) = figure {
+            if let Some(width) = stroke_width(line, &ink) {
+                line.thickness = match line.style {
+                    // Only the stroke closest to the centre is crossed
+                    LineStyle::Double => 2. * width,
+                    _ => width,
+                };
+            }
+        }
+    });
+
+    stages.push(Stage {
+        name: "merged lines",
+        shapes: figures.clone(),
+    });
+
     let mut points = vec![];
 
     for figure in &figures {
         match figure {
-            Shape::Line(Line { start, end, .. }) => {
+            Shape::Line(Line { start, end, .. }) | Shape::Curve(Curve { start, end, .. }) => {
                 points.push(start.clone());
                 points.push(end.clone());
             }
@@ -366,66 +1110,98 @@ pub fn shapes_from_image(img: &mut RgbImage) -> Vec<Shape> {
         }
     }
 
+    let points = SpatialIndex::new(points);
+
     let mut real_lines = vec![];
 
     for figure in &figures {
-        match figure {
-            Shape::Line(line) => real_lines.extend(split_line(&line, points.clone())),
-            _ => {}
+        if let Shape::Line(line) = figure {
+            real_lines.extend(split_line(line, &points, config))
         }
     }
 
-    real_lines = real_lines
-        .into_iter()
-        .filter(|l| l.thickness > 1.)
+    real_lines.retain(|l| l.thickness > config.min_thickness);
+
+    let curves: Vec<_> = figures
+        .iter()
+        .filter_map(|f| match f {
+            Shape::Curve(c) if c.thickness > config.min_thickness => Some(c.clone()),
+            _ => None,
+        })
         .collect();
 
-    let mut points: Vec<_> = real_lines.iter().map(|l| l.start.clone()).collect();
-    let points2: Vec<_> = real_lines.iter().map(|l| l.end.clone()).collect();
+    // Curves connect their end points just like lines
+    let mut tracks = real_lines.clone();
+    tracks.extend(curves.iter().map(Curve::chord));
+
+    let mut points: Vec<_> = tracks.iter().map(|l| l.start.clone()).collect();
+    let points2: Vec<_> = tracks.iter().map(|l| l.end.clone()).collect();
+
+    let ends = SpatialIndex::new(points2.clone());
 
     points.extend(points2);
 
     let mut new_points = vec![];
 
     for point in points {
-        let cns: Vec<_> = real_lines
-            .iter()
-            .filter(|line| line.end == point)
-            .map(|line| line.start.clone())
+        let mut ids: Vec<_> = ends
+            .within_distance(&point, 0.)
+            .filter(|(_, end)| **end == point)
+            .map(|(id, _)| id)
             .collect();
+        ids.sort();
+
+        let cns: Vec<_> = ids.iter().map(|id| tracks[*id].start.clone()).collect();
 
         new_points.push((point, cns));
     }
 
     let mut real_lines: Vec<_> = real_lines.iter().map(|l| Shape::Line(l.clone())).collect();
+    real_lines.extend(curves.into_iter().map(Shape::Curve));
+    real_lines.extend(
+        figures
+            .iter()
+            .filter(|f| matches!(f, Shape::Circle(_) | Shape::Rectangle(_)))
+            .cloned(),
+    );
+
+    stages.push(Stage {
+        name: "split lines",
+        shapes: real_lines.clone(),
+    });
+
+    let mut ends = real_lines.clone();
+    ends.extend(
+        new_points
+            .iter()
+            .map(|(p, cns)| Shape::Point(p.clone(), cns.clone(), false)),
+    );
+    stages.push(Stage {
+        name: "track ends, before junction clustering",
+        shapes: ends,
+    });
+
+    let junctions = SpatialIndex::new(new_points.iter().map(|(p, _)| p.clone()));
 
     let mut points = vec![];
     for (a, mut cns) in new_points.clone() {
-        let mut count = 0;
-        for (b, cns2) in &new_points {
-            if &a == b {
-                cns.extend(cns2.clone());
-                count += 1;
-                continue;
-            }
+        let mut close: Vec<_> = junctions
+            .within_distance(&a, config.junction_distance)
+            .filter(|(_, b)| (a.0 - b.0).abs() + (a.1 - b.1).abs() < config.junction_distance)
+            .map(|(id, _)| id)
+            .collect();
+        close.sort();
 
-            let diff = (a.0 - b.0).abs() + (a.1 - b.1).abs();
+        for id in &close {
+            cns.extend(new_points[*id].1.clone());
+        }
 
-            if diff.abs() < 20. {
-                cns.extend(cns2.clone());
-                count += 1;
-            }
+        let cns: Vec<_> = cns
+            .into_iter()
+            .unique_by(|f| (f.0 as usize, f.1 as usize))
+            .collect();
 
-            cns = cns
-                .into_iter()
-                .unique_by(|f| (f.0 as usize, f.1 as usize))
-                .collect();
-        }
-        if count == 2 {
-            points.push((a, cns, true));
-        } else {
-            points.push((a, cns, false));
-        }
+        points.push((a, cns, close.len() == 2));
     }
 
     let points: Vec<_> = points
@@ -434,9 +1210,396 @@ pub fn shapes_from_image(img: &mut RgbImage) -> Vec<Shape> {
         .collect();
     real_lines.extend(points);
 
-    for el in &real_lines {
-        println!("{:?}", el);
+    (real_lines, stages)
+}
+
+/// Runs [`shapes_from_image`] on every page at once
+pub fn shapes_from_pages(
+    pages: &mut [BinaryMask],
+    layers: &[ColorLayers],
+    config: &DetectionConfig,
+) -> Vec<Vec<Shape>> {
+    pages
+        .par_iter_mut()
+        .zip(layers)
+        .map(|(mask, layers)| shapes_from_image(mask, layers, config))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use image::RgbImage;
+
+    use super::*;
+    use crate::config::LayersConfig;
+
+    fn line(start: (f32, f32), end: (f32, f32), thickness: f32) -> Line {
+        Line {
+            start: Point(start.0, start.1),
+            end: Point(end.0, end.1),
+            thickness,
+            stroke_width: 0.,
+            color: Rgb([0, 0, 0]),
+            style: LineStyle::Solid,
+            fit: None,
+        }
+    }
+
+    fn assert_close(a: &Point, b: (f32, f32)) {
+        assert!(
+            (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn similarity_of_identical_lines_ignores_their_strokes() {
+        let a = line((0., 0.), (10., 0.), 1.);
+
+        assert_eq!(a.similarity_to(&a), -2.);
+    }
+
+    #[test]
+    fn similarity_ignores_orientation() {
+        let a = line((0., 0.), (10., 0.), 1.);
+        let b = line((2., 1.), (12., 1.), 1.);
+        let reversed = line((12., 1.), (2., 1.), 1.);
+
+        assert_eq!(a.similarity_to(&b), a.similarity_to(&reversed));
+        assert_eq!(
+            line((10., 0.), (0., 0.), 1.).similarity_to(&b),
+            a.similarity_to(&b)
+        );
+    }
+
+    #[test]
+    fn similarity_of_touching_strokes_is_zero() {
+        let a = line((0., 0.), (10., 0.), 1.);
+        let b = line((0., 1.), (10., 1.), 1.);
+
+        assert_eq!(a.similarity_to(&b), 0.);
     }
 
-    real_lines
+    #[test]
+    fn similarity_grows_with_the_gap_along_the_line() {
+        let a = line((0., 0.), (10., 0.), 1.);
+        let touching = line((10., 0.), (20., 0.), 1.);
+        let apart = line((30., 0.), (40., 0.), 1.);
+        let before = line((-40., 0.), (-30., 0.), 1.);
+
+        assert_eq!(a.similarity_to(&touching), -2.);
+        assert_eq!(a.similarity_to(&apart), 18.);
+        assert_eq!(a.similarity_to(&before), 28.);
+    }
+
+    #[test]
+    fn similarity_grows_with_the_angle() {
+        let a = line((0., 0.), (10., 0.), 1.);
+        let b = line((0., 0.), (10., 10.), 1.);
+
+        assert_eq!(a.similarity_to(&b), 8.);
+    }
+
+    #[test]
+    fn merging_overlapping_lines_covers_both() {
+        let a = line((0., 0.), (10., 0.), 1.);
+        let b = line((5., 0.), (20., 0.), 1.);
+
+        let merged = a.merge_with(&b);
+
+        assert_close(&merged.start, (0., 0.));
+        assert_close(&merged.end, (20., 0.));
+        assert_eq!(merged.thickness, 1.);
+    }
+
+    #[test]
+    fn merging_touching_lines_joins_them() {
+        let a = line((0., 0.), (10., 0.), 1.);
+        let b = line((10., 0.), (25., 0.), 1.);
+
+        let merged = a.merge_with(&b);
+
+        assert_close(&merged.start, (0., 0.));
+        assert_close(&merged.end, (25., 0.));
+    }
+
+    #[test]
+    fn merging_contained_line_keeps_extent() {
+        let a = line((0., 0.), (20., 0.), 1.);
+        let b = line((5., 0.), (8., 0.), 1.);
+
+        let merged = a.merge_with(&b);
+
+        assert_close(&merged.start, (0., 0.));
+        assert_close(&merged.end, (20., 0.));
+    }
+
+    #[test]
+    fn merging_keeps_orientation_of_self() {
+        let a = line((10., 0.), (0., 0.), 1.);
+        let b = line((5., 0.), (20., 0.), 1.);
+
+        let merged = a.merge_with(&b);
+
+        assert_close(&merged.start, (20., 0.));
+        assert_close(&merged.end, (0., 0.));
+
+        let merged = b.merge_with(&a);
+
+        assert_close(&merged.start, (0., 0.));
+        assert_close(&merged.end, (20., 0.));
+    }
+
+    #[test]
+    fn merging_adjacent_rows_spans_both_strokes() {
+        let a = line((0., 0.), (10., 0.), 1.);
+        let b = line((0., 1.), (12., 1.), 1.);
+
+        let merged = a.merge_with(&b);
+
+        assert_close(&merged.start, (0., 0.5));
+        assert_close(&merged.end, (12., 0.5));
+        assert_eq!(merged.thickness, 2.);
+    }
+
+    #[test]
+    fn merging_diagonal_lines() {
+        let a = line((0., 0.), (10., 10.), 1.);
+        let b = line((15., 15.), (5., 5.), 1.);
+
+        let merged = a.merge_with(&b);
+
+        assert_close(&merged.start, (0., 0.));
+        assert_close(&merged.end, (15., 15.));
+    }
+
+    /// Rows of the pages the line styles are drawn on
+    const PAGE_HEIGHT: u32 = 200;
+
+    /// Horizontal lines found on a blank page with black ink on the given
+    /// runs of (row, columns)
+    fn lines_from_runs(runs: &[(u32, Range<u32>)]) -> Vec<Line> {
+        let mut mask = BinaryMask::new(600, PAGE_HEIGHT);
+        for (y, xs) in runs {
+            for x in xs.clone() {
+                mask.set(x, *y, true);
+            }
+        }
+
+        let img = RgbImage::from_fn(mask.width(), mask.height(), |x, y| {
+            match mask.is_ink(x, y) {
+                true => Rgb([0, 0, 0]),
+                false => Rgb([255, 255, 255]),
+            }
+        });
+        let layers = ColorLayers::new(&img, &mask, &LayersConfig::default());
+
+        horizzontal_lines_from_image(&mut mask, &layers, &DetectionConfig::default(), &mut vec![])
+            .into_iter()
+            .filter_map(|shape| match shape {
+                Shape::Line(line) => Some(line),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// `count` runs of `length` pixels, `gap` apart, starting at `x0` on row `y`
+    fn pattern(y: u32, x0: u32, length: u32, gap: u32, count: u32) -> Vec<(u32, Range<u32>)> {
+        (0..count)
+            .map(|i| {
+                let a = x0 + i * (length + gap);
+                (y, a..a + length)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dashed_line_is_one_dashed_line() {
+        let lines = lines_from_runs(&pattern(50, 100, 10, 5, 20));
+
+        assert_eq!(lines.len(), 1, "{:?}", lines);
+        assert_eq!(lines[0].style, LineStyle::Dashed);
+        let y = (PAGE_HEIGHT - 50) as f32;
+        assert_close(&lines[0].start, (100., y));
+        assert_close(&lines[0].end, (394., y));
+    }
+
+    #[test]
+    fn dotted_line_is_one_dotted_line() {
+        let lines = lines_from_runs(&pattern(100, 50, 2, 4, 60));
+
+        assert_eq!(lines.len(), 1, "{:?}", lines);
+        assert_eq!(lines[0].style, LineStyle::Dotted);
+        let y = (PAGE_HEIGHT - 100) as f32;
+        assert_close(&lines[0].start, (50., y));
+        assert_close(&lines[0].end, (405., y));
+    }
+
+    #[test]
+    fn irregular_short_runs_are_not_a_line() {
+        // Like a row through text, close enough to chain but uneven
+        let lengths = [3, 12, 2, 9, 20, 4, 30, 2, 7, 15];
+        let mut runs = vec![];
+        let mut x = 100;
+        for (i, length) in lengths.iter().cycle().take(30).enumerate() {
+            runs.push((20, x..x + length));
+            x += length + [2, 14, 5][i % 3];
+        }
+
+        assert!(lines_from_runs(&runs).is_empty());
+    }
+
+    #[test]
+    fn close_parallel_strokes_are_one_double_line() {
+        // Two strokes of two rows each, three rows apart, the lower one longer
+        let runs = [
+            (150, 100..500),
+            (151, 100..500),
+            (155, 90..520),
+            (156, 90..520),
+        ];
+
+        let lines = lines_from_runs(&runs);
+
+        assert_eq!(lines.len(), 1, "{:?}", lines);
+        assert_eq!(lines[0].style, LineStyle::Double);
+        let y = PAGE_HEIGHT as f32 - 153.;
+        assert_close(&lines[0].start, (90., y));
+        assert_close(&lines[0].end, (519., y));
+        assert_eq!(lines[0].thickness, 4.);
+    }
+
+    #[test]
+    fn collinear_segments_of_a_thick_line_are_not_double() {
+        // A track six rows thick, broken by a symbol between the segments
+        let runs: Vec<_> = (100..106)
+            .flat_map(|y| [(y, 20..250), (y, 320..580)])
+            .collect();
+
+        let lines = lines_from_runs(&runs);
+
+        assert_eq!(lines.len(), 2, "{:?}", lines);
+        for line in &lines {
+            assert_eq!(line.style, LineStyle::Solid);
+            assert_eq!(line.thickness, 6.);
+            assert_close(&Point(0., line.start.1), (0., PAGE_HEIGHT as f32 - 102.5));
+        }
+        let mut starts: Vec<_> = lines.iter().map(|l| l.start.0).collect();
+        starts.sort_by(f32::total_cmp);
+        assert_eq!(starts, [20., 320.]);
+    }
+
+    #[test]
+    fn rows_of_a_stroke_pool_their_fits() {
+        let runs: Vec<_> = (100..103).map(|y| (y, 50..450)).collect();
+
+        let lines = lines_from_runs(&runs);
+
+        assert_eq!(lines.len(), 1, "{:?}", lines);
+        let fit = lines[0].fit.as_ref().unwrap();
+        assert_eq!(fit.pixels, 1200);
+        // Rows one pixel above and below the centre line
+        assert!(
+            (fit.residual - (2f32 / 3.).sqrt()).abs() < 1e-4,
+            "{:?}",
+            fit
+        );
+    }
+
+    #[test]
+    fn extracted_shapes_carry_their_pixels_and_residual() {
+        let mut mask = BinaryMask::from_fn(200, 200, |x, y| {
+            let d = Point(x as f32, y as f32).distance(&Point(100., 100.));
+            (19. ..22.).contains(&d)
+        });
+        let ink = mask.ink_count();
+        let img = RgbImage::from_pixel(200, 200, Rgb([0, 0, 0]));
+        let layers = ColorLayers::new(&img, &mask, &LayersConfig::default());
+
+        let shapes = extract_shapes(&mut mask, &layers, &DetectionConfig::default());
+
+        assert_eq!(shapes.len(), 1);
+        let Shape::Circle(circle) = &shapes[0] else {
+            std::panic!("{:?}", shapes[0]);
+        };
+        let fit = circle.fit.as_ref().unwrap();
+        assert_eq!(fit.pixels, ink);
+        assert!(fit.residual < 1.5, "{:?}", fit);
+    }
+
+    #[test]
+    fn distant_parallel_strokes_stay_apart() {
+        let lines = lines_from_runs(&[(150, 100..500), (170, 100..500)]);
+
+        assert_eq!(lines.len(), 2, "{:?}", lines);
+        assert!(lines.iter().all(|l| l.style == LineStyle::Solid));
+    }
+
+    /// Mask with ink on the given rows and columns, and the line along the
+    /// middle of the rows from column 10 to 190, in shape coordinates
+    fn stroke(rows: Range<u32>) -> (BinaryMask, Line) {
+        let mask =
+            BinaryMask::from_fn(200, 100, |x, y| (10..191).contains(&x) && rows.contains(&y));
+        let y = 100. - (rows.start + rows.end - 1) as f32 / 2.;
+
+        (mask, line((10., y), (190., y), 1.))
+    }
+
+    #[test]
+    fn stroke_width_is_the_width_in_pixels() {
+        for (rows, width) in [(50..51, 1.), (49..52, 3.), (47..53, 6.)] {
+            let (mask, line) = stroke(rows);
+
+            assert_eq!(stroke_width(&line, &mask), Some(width));
+        }
+    }
+
+    #[test]
+    fn stroke_width_ignores_a_line_running_into_a_junction() {
+        let (mut mask, line) = stroke(49..52);
+        // A crossing track and a branch ending on the line
+        for y in 0..100 {
+            for x in 99..102 {
+                mask.set(x, y, true);
+            }
+        }
+        for y in 52..100 {
+            for x in 150..153 {
+                mask.set(x, y, true);
+            }
+        }
+
+        assert_eq!(stroke_width(&line, &mask), Some(3.));
+    }
+
+    #[test]
+    fn stroke_width_of_a_diagonal_is_measured_across_it() {
+        // Three pixels wide perpendicular to the stroke
+        let mask = BinaryMask::from_fn(200, 200, |x, y| {
+            let across = (x as f32 - y as f32).abs() / std::f32::consts::SQRT_2;
+            (20..180).contains(&x) && across <= 1.5
+        });
+        let line = line((20., 180.), (180., 20.), 1.);
+
+        let width = stroke_width(&line, &mask).unwrap();
+        assert!((width - 3.).abs() <= 0.5, "{}", width);
+    }
+
+    #[test]
+    fn stroke_width_of_a_line_without_ink_is_unknown() {
+        let (_, line) = stroke(50..51);
+
+        assert_eq!(stroke_width(&line, &BinaryMask::new(200, 100)), None);
+        assert_eq!(
+            stroke_width(
+                &self::line((5., 5.), (5., 5.), 1.),
+                &BinaryMask::new(200, 100)
+            ),
+            None
+        );
+    }
 }
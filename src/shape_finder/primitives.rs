@@ -0,0 +1,327 @@
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use image::Rgb;
+use nannou::glam::Vec2;
+
+use super::{Circle, Point, Rectangle};
+use crate::config::DetectionConfig;
+
+/// Angular sectors around its centre a hollow circle has ink in
+const CIRCLE_SECTORS: usize = 16;
+
+/// Largest width to height ratio of the bounding box of a circle
+const CIRCLE_MAX_ASPECT: f32 = 1.25;
+
+/// Orientations tried when looking for the tightest box around a rectangle
+const ANGLE_STEPS: usize = 90;
+
+/// Pieces of every side of a hollow rectangle that need ink
+const SIDE_BINS: usize = 8;
+
+/// Rectangles with a shorter side are strokes rather than boxes
+const RECTANGLE_MIN_SIDE: f32 = 3.;
+
+fn bounds(points: impl Iterator<Item = Vec2>) -> (Vec2, Vec2) {
+    points.fold(
+        (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+        |(min, max), p| (min.min(p), max.max(p)),
+    )
+}
+
+/// A ring or disc made of the pixels, `None` if they form something else
+pub(super) fn circle(
+    pixels: &[(usize, usize)],
+    color: Rgb<u8>,
+    config: &DetectionConfig,
+) -> Option<Circle> {
+    let points: Vec<_> = pixels
+        .iter()
+        .map(|(x, y)| Vec2::new(*x as f32, *y as f32))
+        .collect();
+
+    let (min, max) = bounds(points.iter().cloned());
+    // Pixels cover one unit beyond their coordinates
+    let size = max - min + Vec2::ONE;
+    let aspect = size.x.max(size.y) / size.x.min(size.y);
+    if aspect > CIRCLE_MAX_ASPECT {
+        return None;
+    }
+
+    let centre = (min + max) / 2.;
+    let outer = (size.x + size.y) / 4.;
+    let area = PI * outer * outer;
+    let count = points.len() as f32;
+    let tolerance = config.primitive_tolerance;
+
+    if count >= area * config.min_fill_ratio {
+        if count > area / config.min_fill_ratio {
+            return None;
+        }
+
+        return Some(Circle {
+            center: Point(centre.x, centre.y),
+            radius: outer,
+            filled: true,
+            color,
+            fit: None,
+        });
+    }
+
+    // Radius of the hole of a ring with as many pixels
+    let inner = (outer * outer - count / PI).max(0.).sqrt();
+
+    let mut sectors = [false; CIRCLE_SECTORS];
+    for p in &points {
+        let distance = p.distance(centre) + 0.5;
+        if distance < inner - tolerance || distance > outer + tolerance {
+            return None;
+        }
+
+        let angle = (p.y - centre.y).atan2(p.x - centre.x).rem_euclid(TAU);
+        sectors[((angle / TAU * CIRCLE_SECTORS as f32) as usize).min(CIRCLE_SECTORS - 1)] = true;
+    }
+
+    if !sectors.iter().all(|s| *s) {
+        return None;
+    }
+
+    Some(Circle {
+        center: Point(centre.x, centre.y),
+        radius: (inner + outer) / 2.,
+        filled: false,
+        color,
+        fit: None,
+    })
+}
+
+/// A possibly rotated box made of the pixels, filled or as an outline,
+/// `None` if they form something else
+pub(super) fn rectangle(
+    pixels: &[(usize, usize)],
+    color: Rgb<u8>,
+    config: &DetectionConfig,
+) -> Option<Rectangle> {
+    let points: Vec<_> = pixels
+        .iter()
+        .map(|(x, y)| Vec2::new(*x as f32, *y as f32))
+        .collect();
+
+    // The orientation with the smallest bounding box
+    let (angle, min, max) = (0..ANGLE_STEPS)
+        .map(|i| {
+            let angle = i as f32 / ANGLE_STEPS as f32 * FRAC_PI_2;
+            let axis = Vec2::new(angle.cos(), angle.sin());
+            let (min, max) = bounds(
+                points
+                    .iter()
+                    .map(|p| Vec2::new(p.dot(axis), p.dot(axis.perp()))),
+            );
+            (angle, min, max)
+        })
+        .min_by(|a, b| {
+            let area =
+                |(min, max): (Vec2, Vec2)| (max - min + Vec2::ONE).x * (max - min + Vec2::ONE).y;
+            area((a.1, a.2)).total_cmp(&area((b.1, b.2)))
+        })?;
+
+    let size = max - min + Vec2::ONE;
+    let (short, long) = (size.x.min(size.y), size.x.max(size.y));
+    if short < RECTANGLE_MIN_SIDE || long / short > config.rectangle_max_aspect {
+        return None;
+    }
+
+    let axis = Vec2::new(angle.cos(), angle.sin());
+    let local_centre = (min + max) / 2.;
+    let centre = axis * local_centre.x + axis.perp() * local_centre.y;
+
+    let rectangle = |filled| Rectangle {
+        center: Point(centre.x, centre.y),
+        width: size.x,
+        height: size.y,
+        angle,
+        filled,
+        color,
+        fit: None,
+    };
+
+    let area = size.x * size.y;
+    let count = points.len() as f32;
+
+    if count >= area * config.min_fill_ratio {
+        return match count <= area / config.min_fill_ratio {
+            true => Some(rectangle(true)),
+            false => None,
+        };
+    }
+
+    // Width of a border with as many pixels
+    let perimeter = size.x + size.y;
+    let border = (perimeter - (perimeter * perimeter - 4. * count).max(0.).sqrt()) / 4.;
+    let reach = border + config.primitive_tolerance;
+
+    // Pieces of the bottom, top, left and right side with ink close to them
+    let mut sides = [[false; SIDE_BINS]; 4];
+    for p in &points {
+        let local = Vec2::new(p.dot(axis), p.dot(axis.perp())) - min;
+        let offsets = [
+            local.y,
+            size.y - 1. - local.y,
+            local.x,
+            size.x - 1. - local.x,
+        ];
+
+        if offsets.iter().all(|o| *o > reach) {
+            return None;
+        }
+
+        for (side, offset) in offsets.iter().enumerate() {
+            if *offset <= reach {
+                let (along, length) = match side {
+                    0 | 1 => (local.x, size.x),
+                    _ => (local.y, size.y),
+                };
+                let bin = (along / length * SIDE_BINS as f32) as usize;
+                sides[side][bin.min(SIDE_BINS - 1)] = true;
+            }
+        }
+    }
+
+    match sides.iter().flatten().all(|s| *s) {
+        true => Some(rectangle(false)),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pixels of a 100 × 100 area the predicate holds for, at their centres
+    fn pixels(inside: impl Fn(Vec2) -> bool) -> Vec<(usize, usize)> {
+        (0..100)
+            .flat_map(|y| (0..100).map(move |x| (x, y)))
+            .filter(|(x, y)| inside(Vec2::new(*x as f32, *y as f32)))
+            .collect()
+    }
+
+    fn ring(centre: Vec2, inner: f32, outer: f32) -> Vec<(usize, usize)> {
+        pixels(|p| (inner..=outer).contains(&p.distance(centre)))
+    }
+
+    /// Box of `size` turned by `angle` around `centre`, with a border of
+    /// `border` pixels or filled if `None`
+    fn rotated_box(
+        centre: Vec2,
+        size: Vec2,
+        angle: f32,
+        border: Option<f32>,
+    ) -> Vec<(usize, usize)> {
+        let axis = Vec2::new(angle.cos(), angle.sin());
+        pixels(|p| {
+            let d = p - centre;
+            let local = Vec2::new(d.dot(axis), d.dot(axis.perp())).abs();
+            let half = size / 2.;
+            let inside = local.x <= half.x && local.y <= half.y;
+            match border {
+                Some(border) => inside && (local.x > half.x - border || local.y > half.y - border),
+                None => inside,
+            }
+        })
+    }
+
+    fn black() -> Rgb<u8> {
+        Rgb([0, 0, 0])
+    }
+
+    fn assert_near(value: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (value - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            value,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn disc_is_a_filled_circle() {
+        let config = DetectionConfig::default();
+        let disc = pixels(|p| p.distance(Vec2::new(50., 40.)) <= 12.);
+
+        let circle = circle(&disc, black(), &config).expect("a circle");
+
+        assert!(circle.filled);
+        assert_near(circle.center.0, 50., 0.5);
+        assert_near(circle.center.1, 40., 0.5);
+        assert_near(circle.radius, 12.5, 1.);
+    }
+
+    #[test]
+    fn ring_is_a_hollow_circle() {
+        let config = DetectionConfig::default();
+        let ring = ring(Vec2::new(45., 55.), 15., 18.);
+
+        let circle = circle(&ring, black(), &config).expect("a circle");
+
+        assert!(!circle.filled);
+        assert_near(circle.center.0, 45., 0.5);
+        assert_near(circle.center.1, 55., 0.5);
+        assert_near(circle.radius, 16.5, 1.);
+    }
+
+    #[test]
+    fn arc_of_a_ring_is_not_a_circle() {
+        let config = DetectionConfig::default();
+        let arc: Vec<_> = ring(Vec2::new(50., 50.), 15., 18.)
+            .into_iter()
+            .filter(|(_, y)| *y >= 45)
+            .collect();
+
+        assert_eq!(circle(&arc, black(), &config), None);
+    }
+
+    #[test]
+    fn axis_aligned_boxes_are_rectangles() {
+        let config = DetectionConfig::default();
+
+        for (border, filled) in [(None, true), (Some(2.), false)] {
+            let pixels = rotated_box(Vec2::new(50., 30.), Vec2::new(40., 16.), 0., border);
+
+            let rectangle = rectangle(&pixels, black(), &config).expect("a rectangle");
+
+            assert_eq!(rectangle.filled, filled);
+            assert_near(rectangle.angle, 0., 1e-6);
+            assert_near(rectangle.width, 41., 1.);
+            assert_near(rectangle.height, 17., 1.);
+            assert_near(rectangle.center.0, 50., 0.5);
+            assert_near(rectangle.center.1, 30., 0.5);
+        }
+    }
+
+    #[test]
+    fn rotated_boxes_are_rectangles() {
+        let config = DetectionConfig::default();
+        let angle = 30_f32.to_radians();
+
+        for (border, filled) in [(None, true), (Some(3.), false)] {
+            let pixels = rotated_box(Vec2::new(50., 50.), Vec2::new(40., 20.), angle, border);
+
+            let rectangle = rectangle(&pixels, black(), &config).expect("a rectangle");
+
+            assert_eq!(rectangle.filled, filled);
+            assert_near(rectangle.angle, angle, 1_f32.to_radians());
+            assert_near(rectangle.width, 41., 2.);
+            assert_near(rectangle.height, 21., 2.);
+            assert_near(rectangle.center.0, 50., 1.);
+            assert_near(rectangle.center.1, 50., 1.);
+        }
+    }
+
+    #[test]
+    fn disc_is_not_a_rectangle() {
+        let config = DetectionConfig::default();
+        let disc = pixels(|p| p.distance(Vec2::new(50., 50.)) <= 15.);
+
+        assert_eq!(rectangle(&disc, black(), &config), None);
+    }
+}
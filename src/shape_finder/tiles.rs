@@ -0,0 +1,380 @@
+use rayon::prelude::*;
+
+use crate::binarize::BinaryMask;
+
+/// Edge length of the square tiles the page is split into for parallel scanning
+pub const TILE_SIZE: u32 = 256;
+
+const NO_LABEL: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    /// Exclusive
+    pub x1: u32,
+    /// Exclusive
+    pub y1: u32,
+}
+
+impl Tile {
+    fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x0 && x < self.x1 && y >= self.y0 && y < self.y1
+    }
+}
+
+pub fn tiles(width: u32, height: u32) -> Vec<Tile> {
+    let mut res = vec![];
+    for y0 in (0..height).step_by(TILE_SIZE as _) {
+        for x0 in (0..width).step_by(TILE_SIZE as _) {
+            res.push(Tile {
+                x0,
+                y0,
+                x1: (x0 + TILE_SIZE).min(width),
+                y1: (y0 + TILE_SIZE).min(height),
+            });
+        }
+    }
+    res
+}
+
+/// Horizontal runs of ink as (y, x0, x1 exclusive), sorted by row and start.
+///
+/// Every tile is scanned on its own and runs ending on a tile edge are
+/// joined with the run starting on the other side of it.
+pub fn row_runs(mask: &BinaryMask) -> Vec<(u32, u32, u32)> {
+    let mut runs: Vec<_> = tiles(mask.width(), mask.height())
+        .par_iter()
+        .flat_map_iter(|tile| {
+            let mut runs = vec![];
+            for y in tile.y0..tile.y1 {
+                let mut prev: Option<u32> = None;
+                for x in tile.x0..tile.x1 {
+                    if mask.is_ink(x, y) {
+                        if prev.is_none() {
+                            prev = Some(x);
+                        }
+                    } else if let Some(a) = prev.take() {
+                        runs.push((y, a, x));
+                    }
+                }
+                if let Some(a) = prev {
+                    runs.push((y, a, tile.x1));
+                }
+            }
+            runs
+        })
+        .collect();
+
+    runs.sort();
+
+    let mut stitched: Vec<(u32, u32, u32)> = Vec::with_capacity(runs.len());
+    for run in runs {
+        match stitched.last_mut() {
+            Some(last) if last.0 == run.0 && last.2 == run.1 => last.2 = run.2,
+            _ => stitched.push(run),
+        }
+    }
+
+    stitched
+}
+
+struct TileLabels {
+    tile: Tile,
+    /// Local component per pixel of the tile, row major
+    labels: Vec<u32>,
+    components: Vec<Vec<(u32, u32)>>,
+}
+
+impl TileLabels {
+    fn label(&self, x: u32, y: u32) -> u32 {
+        self.labels[((y - self.tile.y0) * self.tile.width() + x - self.tile.x0) as usize]
+    }
+}
+
+fn label_tile(mask: &BinaryMask, tile: Tile) -> TileLabels {
+    let mut labels = vec![NO_LABEL; (tile.width() * (tile.y1 - tile.y0)) as usize];
+    let mut components = vec![];
+    let index = |x: u32, y: u32| ((y - tile.y0) * tile.width() + x - tile.x0) as usize;
+
+    for y in tile.y0..tile.y1 {
+        for x in tile.x0..tile.x1 {
+            if labels[index(x, y)] != NO_LABEL || !mask.is_ink(x, y) {
+                continue;
+            }
+
+            let label = components.len() as u32;
+            let mut component = vec![];
+            let mut stack = vec![(x, y)];
+            labels[index(x, y)] = label;
+
+            while let Some((cx, cy)) = stack.pop() {
+                component.push((cx, cy));
+
+                for (dx, dy) in NEIGHBOURS {
+                    let (nx, ny) = (cx as i64 + dx, cy as i64 + dy);
+                    if nx < 0 || ny < 0 || !tile.contains(nx as _, ny as _) {
+                        continue;
+                    }
+                    let (nx, ny) = (nx as u32, ny as u32);
+
+                    if labels[index(nx, ny)] == NO_LABEL && mask.is_ink(nx, ny) {
+                        labels[index(nx, ny)] = label;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+
+            components.push(component);
+        }
+    }
+
+    TileLabels {
+        tile,
+        labels,
+        components,
+    }
+}
+
+const NEIGHBOURS: [(i64, i64); 8] = [
+    (1, 0),
+    (-1, 0),
+    (1, 1),
+    (-1, 1),
+    (0, 1),
+    (1, -1),
+    (-1, -1),
+    (0, -1),
+];
+
+struct DisjointSet(Vec<usize>);
+
+impl DisjointSet {
+    fn find(&mut self, a: usize) -> usize {
+        let mut root = a;
+        while self.0[root] != root {
+            root = self.0[root];
+        }
+        let mut a = a;
+        while self.0[a] != root {
+            let next = self.0[a];
+            self.0[a] = root;
+            a = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.0[a.max(b)] = a.min(b);
+        }
+    }
+}
+
+/// 8-connected components of ink pixels in image coordinates.
+///
+/// Tiles are labelled in parallel, then components touching across a tile
+/// edge are joined. The result is ordered like a row by row scan would find
+/// the components.
+pub fn connected_components(mask: &BinaryMask) -> Vec<Vec<(u32, u32)>> {
+    let tiles = tiles(mask.width(), mask.height());
+    let columns = mask.width().div_ceil(TILE_SIZE);
+
+    let labelled: Vec<_> = tiles
+        .par_iter()
+        .map(|tile| label_tile(mask, *tile))
+        .collect();
+
+    let mut offsets = Vec::with_capacity(labelled.len());
+    let mut total = 0;
+    for tile in &labelled {
+        offsets.push(total);
+        total += tile.components.len();
+    }
+
+    let global_label = |x: u32, y: u32| {
+        let i = ((y / TILE_SIZE) * columns + x / TILE_SIZE) as usize;
+        match labelled[i].label(x, y) {
+            NO_LABEL => None,
+            l => Some(offsets[i] + l as usize),
+        }
+    };
+
+    let mut set = DisjointSet((0..total).collect());
+
+    for tile in &tiles {
+        let right = tile.x1 - 1;
+        let bottom = tile.y1 - 1;
+        let border = (tile.y0..tile.y1)
+            .map(|y| (right, y))
+            .chain((tile.x0..tile.x1).map(|x| (x, bottom)));
+
+        for (x, y) in border {
+            let Some(label) = global_label(x, y) else {
+                continue;
+            };
+
+            for (dx, dy) in NEIGHBOURS {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                if nx < 0 || ny < 0 || nx >= mask.width() as _ || ny >= mask.height() as _ {
+                    continue;
+                }
+                if tile.contains(nx as _, ny as _) {
+                    continue;
+                }
+                if let Some(other) = global_label(nx as _, ny as _) {
+                    set.union(label, other);
+                }
+            }
+        }
+    }
+
+    let mut grouped: Vec<Vec<(u32, u32)>> = vec![vec![]; total];
+    for (i, tile) in labelled.into_iter().enumerate() {
+        for (l, component) in tile.components.into_iter().enumerate() {
+            let root = set.find(offsets[i] + l);
+            grouped[root].extend(component);
+        }
+    }
+
+    let mut components: Vec<_> = grouped.into_iter().filter(|c| !c.is_empty()).collect();
+    components.par_sort_by_key(|c| c.iter().map(|(x, y)| (*y, *x)).min());
+
+    components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs found by scanning the whole mask as one tile
+    fn single_tile_runs(mask: &BinaryMask) -> Vec<(u32, u32, u32)> {
+        let mut runs = vec![];
+        for y in 0..mask.height() {
+            let mut start = None;
+            for x in 0..mask.width() {
+                match (mask.is_ink(x, y), start) {
+                    (true, None) => start = Some(x),
+                    (false, Some(a)) => {
+                        runs.push((y, a, x));
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(a) = start {
+                runs.push((y, a, mask.width()));
+            }
+        }
+        runs
+    }
+
+    /// Components found by labelling the whole mask as one tile, their
+    /// pixels sorted
+    fn single_tile_components(mask: &BinaryMask) -> Vec<Vec<(u32, u32)>> {
+        let whole = Tile {
+            x0: 0,
+            y0: 0,
+            x1: mask.width(),
+            y1: mask.height(),
+        };
+        label_tile(mask, whole)
+            .components
+            .into_iter()
+            .map(|mut c| {
+                c.sort_by_key(|(x, y)| (*y, *x));
+                c
+            })
+            .collect()
+    }
+
+    fn tiled_components(mask: &BinaryMask) -> Vec<Vec<(u32, u32)>> {
+        connected_components(mask)
+            .into_iter()
+            .map(|mut c| {
+                c.sort_by_key(|(x, y)| (*y, *x));
+                c
+            })
+            .collect()
+    }
+
+    fn draw(mask: &mut BinaryMask, xs: std::ops::Range<u32>, ys: std::ops::Range<u32>) {
+        for y in ys {
+            for x in xs.clone() {
+                mask.set(x, y, true);
+            }
+        }
+    }
+
+    /// Page spanning several tiles, not a multiple of the tile size
+    fn page() -> BinaryMask {
+        BinaryMask::new(3 * TILE_SIZE - 70, 3 * TILE_SIZE - 120)
+    }
+
+    fn assert_like_single_tile(mask: &BinaryMask) {
+        assert_eq!(row_runs(mask), single_tile_runs(mask));
+        assert_eq!(tiled_components(mask), single_tile_components(mask));
+    }
+
+    #[test]
+    fn horizontal_line_across_tiles_is_one_run() {
+        let mut mask = page();
+        draw(&mut mask, 200..600, 100..103);
+
+        assert_like_single_tile(&mask);
+        assert_eq!(row_runs(&mask)[0], (100, 200, 600));
+        assert_eq!(connected_components(&mask).len(), 1);
+    }
+
+    #[test]
+    fn line_ending_on_a_tile_edge_is_not_joined_with_the_next_tile() {
+        let mut mask = page();
+        draw(&mut mask, 100..TILE_SIZE, 10..11);
+        draw(&mut mask, TILE_SIZE + 1..TILE_SIZE + 50, 10..11);
+
+        assert_like_single_tile(&mask);
+        assert_eq!(connected_components(&mask).len(), 2);
+    }
+
+    #[test]
+    fn vertical_line_across_tiles_is_one_component() {
+        let mut mask = page();
+        draw(&mut mask, 300..302, 20..600);
+
+        assert_like_single_tile(&mask);
+        assert_eq!(connected_components(&mask).len(), 1);
+    }
+
+    #[test]
+    fn diagonal_through_a_tile_corner_is_one_component() {
+        let mut mask = page();
+        for i in 200..320 {
+            mask.set(i, i, true);
+        }
+
+        assert_like_single_tile(&mask);
+        assert_eq!(connected_components(&mask).len(), 1);
+    }
+
+    #[test]
+    fn u_shape_joined_in_a_later_tile_is_one_component() {
+        let mut mask = page();
+        // Arms in the first two tiles of the top row, the bar joining them
+        // only in the row of tiles below
+        draw(&mut mask, 100..103, 50..400);
+        draw(&mut mask, 400..403, 50..400);
+        draw(&mut mask, 100..403, 397..400);
+        // Separate stroke before the U in scan order
+        draw(&mut mask, 500..510, 10..12);
+
+        assert_like_single_tile(&mask);
+        let components = connected_components(&mask);
+        assert_eq!(components.len(), 2);
+        assert_eq!(components[1].len(), 2 * 3 * 350 + 3 * 297);
+    }
+}
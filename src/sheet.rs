@@ -0,0 +1,444 @@
+use rayon::prelude::*;
+
+use crate::{
+    binarize::BinaryMask, config::SheetConfig, shape_finder::Point, spatial_index::BoundingBox,
+};
+
+/// Piece of text on the page, in image pixels
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextSpan {
+    pub text: String,
+    pub bounds: BoundingBox,
+}
+
+/// Parts of the sheet that belong to its layout rather than to the plan,
+/// in image pixels
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SheetLayout {
+    /// Outer edge of the frame drawn around the plan
+    pub frame: Option<BoundingBox>,
+    /// Box in the bottom right corner of the frame
+    pub title_block: Option<BoundingBox>,
+    /// Box stacked on top of the title block, one exactly as wide is taken
+    /// as part of the title block
+    pub legend: Option<BoundingBox>,
+}
+
+/// What the title block says about the plan
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TitleBlock {
+    pub station_name: Option<String>,
+    pub plan_number: Option<String>,
+    pub revision: Option<String>,
+    pub date: Option<String>,
+    pub sheet: Option<String>,
+    /// As written, e.g. `1:500`
+    pub scale: Option<String>,
+}
+
+/// Runs of ink at least `min_length` long, as (row, first, last) in rows or columns
+fn long_runs(mask: &BinaryMask, vertical: bool, min_length: u32) -> Vec<(u32, u32, u32)> {
+    let (lines, length) = match vertical {
+        true => (mask.width(), mask.height()),
+        false => (mask.height(), mask.width()),
+    };
+    let is_ink = |line: u32, i: u32| match vertical {
+        true => mask.is_ink(line, i),
+        false => mask.is_ink(i, line),
+    };
+
+    (0..lines)
+        .into_par_iter()
+        .flat_map_iter(|line| {
+            let mut runs = vec![];
+            let mut start = None;
+            for i in 0..=length {
+                match (i < length && is_ink(line, i), start) {
+                    (true, None) => start = Some(i),
+                    (false, Some(s)) => {
+                        if i - s >= min_length {
+                            runs.push((line, s, i - 1));
+                        }
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+            runs
+        })
+        .collect()
+}
+
+/// Box whose bottom edge lies on `bottom` and whose right edge lies on the
+/// frame: a line ending at the frame with a border going down from its left end
+fn corner_box(
+    mask: &BinaryMask,
+    frame: &BoundingBox,
+    bottom: f32,
+    config: &SheetConfig,
+) -> Option<BoundingBox> {
+    let margin = config.frame_margin as f32;
+    let min_width = (frame.width() * config.box_min_width) as u32;
+    let max_height = frame.height() * config.box_max_height;
+
+    let mut tops: Vec<_> = long_runs(mask, false, min_width.max(1))
+        .into_iter()
+        .filter(|(y, _, x1)| {
+            let y = *y as f32;
+            *x1 as f32 >= frame.max.0 - margin && y < bottom - margin && y >= bottom - max_height
+        })
+        .collect();
+    tops.sort();
+
+    tops.into_iter()
+        .find(|(y, x0, _)| {
+            let rows = *y..bottom as u32;
+            let covered = rows
+                .clone()
+                .filter(|row| {
+                    (x0.saturating_sub(2)..=(x0 + 2).min(mask.width() - 1))
+                        .any(|x| mask.is_ink(x, *row))
+                })
+                .count();
+
+            covered as f32 >= rows.len() as f32 * 0.9
+        })
+        .map(|(y, x0, _)| {
+            BoundingBox::new(&Point(x0 as f32, y as f32), &Point(frame.max.0, bottom))
+        })
+}
+
+/// Finds the frame around the plan and the boxes in its bottom right corner
+pub fn detect_layout(mask: &BinaryMask, config: &SheetConfig) -> SheetLayout {
+    let (width, height) = (mask.width() as f32, mask.height() as f32);
+
+    let rows = long_runs(mask, false, (width * config.frame_min_length) as u32);
+    let columns = long_runs(mask, true, (height * config.frame_min_length) as u32);
+
+    let (Some(top), Some(bottom)) = (
+        rows.iter().map(|r| r.0).min(),
+        rows.iter().map(|r| r.0).max(),
+    ) else {
+        return SheetLayout::default();
+    };
+    let (Some(left), Some(right)) = (
+        columns.iter().map(|c| c.0).min(),
+        columns.iter().map(|c| c.0).max(),
+    ) else {
+        return SheetLayout::default();
+    };
+
+    if ((bottom - top) as f32) < height * config.frame_min_length
+        || ((right - left) as f32) < width * config.frame_min_length
+    {
+        return SheetLayout::default();
+    }
+
+    let frame = BoundingBox::new(
+        &Point(left as f32, top as f32),
+        &Point(right as f32, bottom as f32),
+    );
+
+    // The inner line of a double frame lies within the margin
+    let inner_bottom = rows
+        .iter()
+        .map(|r| r.0)
+        .filter(|y| *y + config.frame_margin >= bottom)
+        .min()
+        .unwrap_or(bottom) as f32;
+
+    let title_block = corner_box(mask, &frame, inner_bottom, config);
+    let legend = title_block
+        .as_ref()
+        .and_then(|t| corner_box(mask, &frame, t.min.1, config));
+
+    SheetLayout {
+        frame: Some(frame),
+        title_block,
+        legend,
+    }
+}
+
+impl SheetLayout {
+    /// Clears the frame, everything outside of it and the boxes from the mask,
+    /// so none of it is taken for tracks
+    pub fn exclude(&self, mask: &mut BinaryMask, config: &SheetConfig) {
+        let Some(frame) = &self.frame else {
+            return;
+        };
+
+        let inner = frame.expanded(-(config.frame_margin as f32));
+        let boxes: Vec<_> = [&self.title_block, &self.legend]
+            .into_iter()
+            .flatten()
+            .collect();
+
+        let old = mask.clone();
+        *mask = BinaryMask::from_fn(old.width(), old.height(), |x, y| {
+            let p = Point(x as f32, y as f32);
+            old.is_ink(x, y) && inner.contains(&p) && !boxes.iter().any(|b| b.contains(&p))
+        });
+    }
+}
+
+/// End of `label` at the start of `text` in bytes of `text`, matched ignoring
+/// case. The label has to end a word, "Gare" doesn't start "Garage".
+fn label_end(text: &str, label: &str) -> Option<usize> {
+    let mut chars = text.char_indices();
+    for l in label.chars() {
+        let (_, c) = chars.next()?;
+        if !c.to_lowercase().eq(l.to_lowercase()) {
+            return None;
+        }
+    }
+
+    match chars.next() {
+        None => Some(text.len()),
+        Some((_, c)) if c.is_alphanumeric() => None,
+        Some((end, _)) => Some(end),
+    }
+}
+
+/// Trimmed text after the longest label, `None` if the text doesn't start
+/// with any of them
+fn after_label<'a>(text: &'a str, labels: &[String]) -> Option<&'a str> {
+    labels
+        .iter()
+        .filter_map(|l| label_end(text, l))
+        .max()
+        .map(|end| text[end..].trim_start_matches(['.', ':', ' ']).trim())
+}
+
+/// Day, month and year separated by dots or slashes
+fn looks_like_date(word: &str) -> bool {
+    let parts: Vec<_> = word.split(['.', '/']).collect();
+
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|p| !p.is_empty() && p.len() <= 4 && p.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Ratio like `1:500`
+fn looks_like_scale(word: &str) -> bool {
+    word.split_once(':').is_some_and(|(a, b)| {
+        [a, b]
+            .iter()
+            .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit() || c == '\''))
+    })
+}
+
+/// Reads the labelled fields of the title block. A field's value follows its
+/// label in the same span, or is the next span in reading order.
+pub fn read_title_block(
+    spans: &[TextSpan],
+    region: &BoundingBox,
+    config: &SheetConfig,
+) -> TitleBlock {
+    let mut spans: Vec<_> = spans
+        .iter()
+        .filter(|s| {
+            region.contains(&Point(
+                (s.bounds.min.0 + s.bounds.max.0) / 2.,
+                (s.bounds.min.1 + s.bounds.max.1) / 2.,
+            ))
+        })
+        .filter(|s| !s.text.trim().is_empty())
+        .collect();
+
+    // Reading order, spans overlapping vertically are on the same line
+    spans.sort_by(|a, b| a.bounds.min.1.total_cmp(&b.bounds.min.1));
+    let mut lines: Vec<Vec<&TextSpan>> = vec![];
+    for span in spans {
+        match lines.last_mut() {
+            Some(line) if line.iter().any(|s| span.bounds.min.1 <= s.bounds.max.1) => {
+                line.push(span)
+            }
+            _ => lines.push(vec![span]),
+        }
+    }
+    let spans: Vec<_> = lines
+        .into_iter()
+        .flat_map(|mut line| {
+            line.sort_by(|a, b| a.bounds.min.0.total_cmp(&b.bounds.min.0));
+            line
+        })
+        .collect();
+
+    let field = |labels: &[String]| {
+        spans.iter().enumerate().find_map(|(i, span)| {
+            let value = after_label(span.text.trim(), labels)?;
+            match value.is_empty() {
+                false => Some(value.to_string()),
+                true => spans.get(i + 1).map(|next| next.text.trim().to_string()),
+            }
+        })
+    };
+
+    TitleBlock {
+        station_name: field(&config.station_labels),
+        plan_number: field(&config.plan_number_labels),
+        revision: field(&config.revision_labels),
+        date: field(&config.date_labels).or_else(|| {
+            spans
+                .iter()
+                .flat_map(|s| s.text.split_whitespace())
+                .find(|w| looks_like_date(w))
+                .map(|w| w.to_string())
+        }),
+        sheet: field(&config.sheet_labels),
+        scale: field(&config.scale_labels).or_else(|| {
+            spans
+                .iter()
+                .flat_map(|s| s.text.split_whitespace())
+                .find(|w| looks_like_scale(w))
+                .map(|w| w.to_string())
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeInclusive;
+
+    use super::*;
+
+    fn hline(mask: &mut BinaryMask, y: u32, xs: RangeInclusive<u32>) {
+        for x in xs {
+            mask.set(x, y, true);
+        }
+    }
+
+    fn vline(mask: &mut BinaryMask, x: u32, ys: RangeInclusive<u32>) {
+        for y in ys {
+            mask.set(x, y, true);
+        }
+    }
+
+    /// Page with a frame from (10, 10) to (389, 289) and a title block in
+    /// its bottom right corner from (250, 230)
+    fn framed_page() -> BinaryMask {
+        let mut mask = BinaryMask::new(400, 300);
+        hline(&mut mask, 10, 10..=389);
+        hline(&mut mask, 289, 10..=389);
+        vline(&mut mask, 10, 10..=289);
+        vline(&mut mask, 389, 10..=289);
+
+        hline(&mut mask, 230, 250..=389);
+        vline(&mut mask, 250, 230..=289);
+        // A track crossing the plan
+        hline(&mut mask, 120, 40..=360);
+        mask
+    }
+
+    fn bounds(min: (f32, f32), max: (f32, f32)) -> BoundingBox {
+        BoundingBox::new(&Point(min.0, min.1), &Point(max.0, max.1))
+    }
+
+    #[test]
+    fn frame_title_block_and_legend_are_found() {
+        let mut mask = framed_page();
+        hline(&mut mask, 180, 300..=389);
+        vline(&mut mask, 300, 180..=230);
+
+        let layout = detect_layout(&mask, &SheetConfig::default());
+
+        assert_eq!(layout.frame, Some(bounds((10., 10.), (389., 289.))));
+        assert_eq!(layout.title_block, Some(bounds((250., 230.), (389., 289.))));
+        assert_eq!(layout.legend, Some(bounds((300., 180.), (389., 230.))));
+    }
+
+    #[test]
+    fn legend_away_from_the_frame_edge_is_not_part_of_the_sheet() {
+        let mut mask = framed_page();
+        hline(&mut mask, 180, 300..=360);
+        vline(&mut mask, 300, 180..=230);
+        vline(&mut mask, 360, 180..=230);
+
+        let config = SheetConfig::default();
+        let layout = detect_layout(&mask, &config);
+
+        assert!(layout.title_block.is_some());
+        assert_eq!(layout.legend, None);
+
+        layout.exclude(&mut mask, &config);
+        assert!(mask.is_ink(330, 180));
+        assert!(mask.is_ink(100, 120));
+        assert!(!mask.is_ink(300, 250));
+        assert!(!mask.is_ink(10, 100));
+    }
+
+    #[test]
+    fn page_without_frame_has_no_layout() {
+        let mut mask = BinaryMask::new(400, 300);
+        hline(&mut mask, 120, 40..=360);
+
+        assert_eq!(
+            detect_layout(&mask, &SheetConfig::default()),
+            SheetLayout::default()
+        );
+    }
+
+    #[test]
+    fn labels_have_to_end_a_word() {
+        let labels: Vec<_> = ["Gare", "Rev", "Revision", "Plan-Nr", "Maßstab"]
+            .map(String::from)
+            .to_vec();
+
+        let cases = [
+            ("Gare: Lausanne", Some("Lausanne")),
+            ("GARE Lausanne", Some("Lausanne")),
+            ("Gare", Some("")),
+            ("Garage nord", None),
+            ("Revision: B", Some("B")),
+            ("Rev. C", Some("C")),
+            ("Revisionsdatum: 12.03.2020", None),
+            ("Plan-Nr.: 123-45", Some("123-45")),
+            ("Plan-Nr123", None),
+            // Lowercasing "ẞ" changes its length in bytes
+            ("MAẞSTAB: 1:500", Some("1:500")),
+            ("Massstab 1:500", None),
+        ];
+
+        for (text, value) in cases {
+            assert_eq!(after_label(text, &labels), value, "{}", text);
+        }
+    }
+
+    #[test]
+    fn title_block_fields_follow_their_labels() {
+        let span = |text: &str, x: f32, y: f32| TextSpan {
+            text: text.to_string(),
+            bounds: bounds((x, y), (x + 40., y + 8.)),
+        };
+        let spans = [
+            span("Bahnhof: Olten", 260., 240.),
+            span("Plan-Nr", 260., 252.),
+            span("4711-02", 320., 253.),
+            span("Revisionsdatum: 12.03.2020", 260., 264.),
+            span("Blatt 2", 260., 276.),
+            span("1:500", 320., 276.),
+            // Outside of the title block
+            span("Garage", 100., 100.),
+            span("Revision: Z", 100., 110.),
+        ];
+
+        let title_block = read_title_block(
+            &spans,
+            &bounds((250., 230.), (389., 289.)),
+            &SheetConfig::default(),
+        );
+
+        assert_eq!(
+            title_block,
+            TitleBlock {
+                station_name: Some("Olten".to_string()),
+                plan_number: Some("4711-02".to_string()),
+                revision: None,
+                date: Some("12.03.2020".to_string()),
+                sheet: Some("2".to_string()),
+                scale: Some("1:500".to_string()),
+            }
+        );
+    }
+}
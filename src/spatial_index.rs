@@ -0,0 +1,408 @@
+use std::{cell::RefCell, rc::Rc};
+
+use rstar::{PointDistance, RTree, RTreeObject, AABB};
+
+use crate::{
+    data_structures::Node,
+    shape_finder::{Circle, Curve, Line, Point, Rectangle, Shape},
+};
+
+/// Axis aligned box in the same coordinate frame as the indexed items
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl BoundingBox {
+    pub fn new(a: &Point, b: &Point) -> Self {
+        BoundingBox {
+            min: Point(a.0.min(b.0), a.1.min(b.1)),
+            max: Point(a.0.max(b.0), a.1.max(b.1)),
+        }
+    }
+
+    pub fn around(center: &Point, radius: f32) -> Self {
+        BoundingBox {
+            min: Point(center.0 - radius, center.1 - radius),
+            max: Point(center.0 + radius, center.1 + radius),
+        }
+    }
+
+    pub fn expanded(&self, margin: f32) -> Self {
+        BoundingBox {
+            min: Point(self.min.0 - margin, self.min.1 - margin),
+            max: Point(self.max.0 + margin, self.max.1 + margin),
+        }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.max.0 - self.min.0
+    }
+
+    pub fn height(&self) -> f32 {
+        self.max.1 - self.min.1
+    }
+
+    pub fn contains(&self, point: &Point) -> bool {
+        point.0 >= self.min.0
+            && point.0 <= self.max.0
+            && point.1 >= self.min.1
+            && point.1 <= self.max.1
+    }
+
+    fn to_aabb(&self) -> AABB<[f32; 2]> {
+        AABB::from_corners([self.min.0, self.min.1], [self.max.0, self.max.1])
+    }
+
+    fn distance_2(&self, point: &Point) -> f32 {
+        self.to_aabb().distance_2(&[point.0, point.1])
+    }
+}
+
+/// Anything that can be stored in a [`SpatialIndex`]
+pub trait Bounded {
+    fn bounding_box(&self) -> BoundingBox;
+
+    /// Squared distance from the item to the given point
+    fn distance_2(&self, point: &Point) -> f32 {
+        self.bounding_box().distance_2(point)
+    }
+}
+
+impl Bounded for Point {
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::new(self, self)
+    }
+
+    fn distance_2(&self, point: &Point) -> f32 {
+        (self.0 - point.0).powi(2) + (self.1 - point.1).powi(2)
+    }
+}
+
+impl Bounded for Line {
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::new(&self.start, &self.end)
+    }
+
+    fn distance_2(&self, point: &Point) -> f32 {
+        self.distance_to_point(point).powi(2)
+    }
+}
+
+impl Bounded for Curve {
+    fn bounding_box(&self) -> BoundingBox {
+        let points = self.points();
+        let min_x = points.iter().map(|p| p.0).fold(f32::MAX, f32::min);
+        let max_x = points.iter().map(|p| p.0).fold(f32::MIN, f32::max);
+        let min_y = points.iter().map(|p| p.1).fold(f32::MAX, f32::min);
+        let max_y = points.iter().map(|p| p.1).fold(f32::MIN, f32::max);
+
+        BoundingBox::new(&Point(min_x, min_y), &Point(max_x, max_y))
+    }
+
+    fn distance_2(&self, point: &Point) -> f32 {
+        let points = self.points();
+        let closest = points
+            .windows(2)
+            .map(|w| Line {
+                start: w[0].clone(),
+                end: w[1].clone(),
+                ..self.chord()
+            })
+            .map(|l| l.distance_to_point(point))
+            .fold(f32::MAX, f32::min);
+
+        closest.powi(2)
+    }
+}
+
+impl Bounded for Circle {
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::around(&self.center, self.radius)
+    }
+}
+
+impl Bounded for Rectangle {
+    fn bounding_box(&self) -> BoundingBox {
+        let corners = self.corners();
+        let min_x = corners.iter().map(|p| p.0).fold(f32::MAX, f32::min);
+        let max_x = corners.iter().map(|p| p.0).fold(f32::MIN, f32::max);
+        let min_y = corners.iter().map(|p| p.1).fold(f32::MAX, f32::min);
+        let max_y = corners.iter().map(|p| p.1).fold(f32::MIN, f32::max);
+
+        BoundingBox::new(&Point(min_x, min_y), &Point(max_x, max_y))
+    }
+}
+
+impl Bounded for Shape {
+    fn bounding_box(&self) -> BoundingBox {
+        match self {
+            Shape::Line(line) => line.bounding_box(),
+            Shape::Curve(curve) => curve.bounding_box(),
+            Shape::Circle(circle) => circle.bounding_box(),
+            Shape::Rectangle(rectangle) => rectangle.bounding_box(),
+            Shape::Point(point, _, _) => point.bounding_box(),
+            Shape::Custom(pixels, _) => {
+                let min_x = pixels.iter().map(|(x, _)| *x).min().unwrap_or_default();
+                let max_x = pixels.iter().map(|(x, _)| *x).max().unwrap_or_default();
+                let min_y = pixels.iter().map(|(_, y)| *y).min().unwrap_or_default();
+                let max_y = pixels.iter().map(|(_, y)| *y).max().unwrap_or_default();
+
+                BoundingBox::new(
+                    &Point(min_x as _, min_y as _),
+                    &Point(max_x as _, max_y as _),
+                )
+            }
+        }
+    }
+
+    fn distance_2(&self, point: &Point) -> f32 {
+        match self {
+            Shape::Line(line) => line.distance_2(point),
+            Shape::Curve(curve) => curve.distance_2(point),
+            Shape::Circle(circle) => circle.distance_2(point),
+            Shape::Rectangle(rectangle) => rectangle.distance_2(point),
+            Shape::Point(p, _, _) => p.distance_2(point),
+            Shape::Custom(..) => self.bounding_box().distance_2(point),
+        }
+    }
+}
+
+impl Bounded for Rc<RefCell<Node>> {
+    fn bounding_box(&self) -> BoundingBox {
+        let (x, y) = self.borrow().coordinates;
+        Point(x as _, y as _).bounding_box()
+    }
+
+    fn distance_2(&self, point: &Point) -> f32 {
+        let (x, y) = self.borrow().coordinates;
+        Point(x as _, y as _).distance_2(point)
+    }
+}
+
+struct Entry<T> {
+    id: usize,
+    envelope: AABB<[f32; 2]>,
+    item: T,
+}
+
+impl<T: Bounded> RTreeObject for Entry<T> {
+    type Envelope = AABB<[f32; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        self.envelope
+    }
+}
+
+impl<T: Bounded> PointDistance for Entry<T> {
+    fn distance_2(&self, point: &[f32; 2]) -> f32 {
+        self.item.distance_2(&Point(point[0], point[1]))
+    }
+}
+
+/// R-tree over shapes or graph nodes, answering nearest neighbour and
+/// bounding box queries without scanning every item.
+///
+/// Query results carry the position the item had in the input, so callers
+/// can keep using their own vectors for everything else.
+pub struct SpatialIndex<T: Bounded> {
+    tree: RTree<Entry<T>>,
+}
+
+impl<T: Bounded> SpatialIndex<T> {
+    pub fn new(items: impl IntoIterator<Item = T>) -> Self {
+        let entries = items
+            .into_iter()
+            .enumerate()
+            .map(|(id, item)| Entry {
+                id,
+                envelope: item.bounding_box().to_aabb(),
+                item,
+            })
+            .collect();
+
+        SpatialIndex {
+            tree: RTree::bulk_load(entries),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert(&mut self, item: T) -> usize {
+        let id = self.len();
+        self.tree.insert(Entry {
+            id,
+            envelope: item.bounding_box().to_aabb(),
+            item,
+        });
+        id
+    }
+
+    /// Closest item to the point
+    pub fn nearest(&self, point: &Point) -> Option<(usize, &T)> {
+        self.tree
+            .nearest_neighbor(&[point.0, point.1])
+            .map(|e| (e.id, &e.item))
+    }
+
+    /// All items whose distance to the point is at most `distance`
+    pub fn within_distance<'a>(
+        &'a self,
+        point: &Point,
+        distance: f32,
+    ) -> impl Iterator<Item = (usize, &'a T)> + 'a {
+        self.tree
+            .locate_within_distance([point.0, point.1], distance * distance)
+            .map(|e| (e.id, &e.item))
+    }
+
+    /// All items whose bounding box intersects the given box
+    pub fn intersecting<'a>(
+        &'a self,
+        bounding_box: &BoundingBox,
+    ) -> impl Iterator<Item = (usize, &'a T)> + 'a {
+        self.tree
+            .locate_in_envelope_intersecting(&bounding_box.to_aabb())
+            .map(|e| (e.id, &e.item))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.tree.iter().map(|e| (e.id, &e.item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use super::*;
+    use crate::shape_finder::LineStyle;
+
+    /// Points spread over a 1000 × 1000 square, the same for every run
+    fn points(count: usize) -> Vec<Point> {
+        let mut state = 12345_u32;
+        let mut next = move || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 8) as f32 / (1 << 24) as f32 * 1000.
+        };
+
+        (0..count).map(|_| Point(next(), next())).collect()
+    }
+
+    fn sorted(ids: impl Iterator<Item = usize>) -> Vec<usize> {
+        let mut ids: Vec<_> = ids.collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn nearest_matches_a_linear_scan() {
+        let items = points(500);
+        let index = SpatialIndex::new(items.clone());
+
+        for query in points(50).iter().map(|p| Point(p.1, p.0)) {
+            let expected = items
+                .iter()
+                .map(|p| p.distance_2(&query))
+                .enumerate()
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap();
+            let (id, found) = index.nearest(&query).unwrap();
+
+            assert_eq!(found.distance_2(&query), expected.1);
+            assert_eq!(&items[id], found);
+        }
+    }
+
+    #[test]
+    fn within_distance_matches_a_linear_scan() {
+        let items = points(500);
+        let index = SpatialIndex::new(items.clone());
+
+        for query in points(20) {
+            for distance in [0., 10., 75.] {
+                let expected = items
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, p)| p.distance_2(&query) <= distance * distance)
+                    .map(|(id, _)| id);
+
+                assert_eq!(
+                    sorted(index.within_distance(&query, distance).map(|(id, _)| id)),
+                    sorted(expected)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn within_distance_measures_from_lines_not_their_boxes() {
+        let line = |start: Point, end: Point| Line {
+            start,
+            end,
+            thickness: 1.,
+            stroke_width: 0.,
+            color: Rgb([0, 0, 0]),
+            style: LineStyle::Solid,
+            fit: None,
+        };
+        let index = SpatialIndex::new([
+            line(Point(0., 0.), Point(100., 100.)),
+            line(Point(0., 50.), Point(100., 50.)),
+        ]);
+
+        // Inside the box of the diagonal, but 35 px away from it
+        let query = Point(100., 50.);
+        assert_eq!(
+            sorted(index.within_distance(&query, 10.).map(|(id, _)| id)),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn intersecting_matches_a_linear_scan() {
+        let items = points(500);
+        let index = SpatialIndex::new(items.clone());
+
+        for (a, b) in points(20).iter().zip(points(40).iter().skip(20)) {
+            let query = BoundingBox::new(a, b);
+            let expected = items
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| query.contains(p))
+                .map(|(id, _)| id);
+
+            assert_eq!(
+                sorted(index.intersecting(&query).map(|(id, _)| id)),
+                sorted(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn inserted_items_are_found_with_the_next_id() {
+        let items = points(100);
+        let mut index = SpatialIndex::new(items.clone());
+
+        let id = index.insert(Point(2000., 2000.));
+
+        assert_eq!(id, items.len());
+        assert_eq!(index.len(), items.len() + 1);
+        assert_eq!(index.nearest(&Point(1990., 1990.)).unwrap().0, id);
+        assert_eq!(
+            sorted(
+                index
+                    .intersecting(&BoundingBox::around(&Point(2000., 2000.), 1.))
+                    .map(|(id, _)| id)
+            ),
+            vec![id]
+        );
+    }
+}
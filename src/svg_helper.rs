@@ -1,13 +1,23 @@
-use draw::WINDOW_WIDTH;
-use draw::WINDOW_HEIGHT;
 use svg::node::element::Circle;
 use svg::node::element::Element;
+use svg::node::element::Group;
 use svg::node::element::Line;
+use svg::node::element::Path as SvgPath;
+use svg::node::element::Rectangle;
 use svg::node::element::Text;
-use svg::node::element::Group;
+use svg::node::element::Title;
 use svg::Document;
+use svg::Node;
+
+use anyhow::Context;
+use image::RgbImage;
+use std::path::Path;
 
-use crate::data_structures::{Connectable, Label, Node, PinPoint, SingleLiner};
+use crate::data_structures::{Connectable, SingleLiner};
+use crate::image_helper;
+use crate::metadata::PlanMetadata;
+use crate::shape_finder::{CurveGeometry, Point};
+use crate::transform::Transform;
 
 pub fn insert_svg(svg_schema: &mut Vec<Element>, new_connectable: Connectable) {
     let coordinates = new_connectable.get_coordinates();
@@ -76,18 +86,105 @@ pub fn insert_svg(svg_schema: &mut Vec<Element>, new_connectable: Connectable) {
     // }
 }
 
-pub fn save_and_draw_svg(svg_schema: &mut Vec<Element>) {
-    let mut group = Group::new()
-        .set("transform", format!("scale(1, -1) translate(0, -{})", WINDOW_HEIGHT));
+/// Adds a curved track from `start` to `end` as a `<path>`, arcs as elliptical arc commands
+pub fn insert_curve(
+    svg_schema: &mut Vec<Element>,
+    start: &Point,
+    end: &Point,
+    geometry: &CurveGeometry,
+) {
+    let mut d = format!("M {} {}", start.0, start.1);
+
+    match geometry {
+        CurveGeometry::Arc { radius, sweep, .. } => {
+            let large_arc = (sweep.abs() > std::f32::consts::PI) as u8;
+            let sweep_flag = (*sweep > 0.) as u8;
+            d += &format!(
+                " A {} {} 0 {} {} {} {}",
+                radius, radius, large_arc, sweep_flag, end.0, end.1
+            );
+        }
+        CurveGeometry::Polyline(corners) => {
+            for p in corners.iter().chain([end]) {
+                d += &format!(" L {} {}", p.0, p.1);
+            }
+        }
+    }
+
+    let path = SvgPath::new()
+        .set("d", d)
+        .set("stroke", "black")
+        .set("fill", "none")
+        .into();
+    svg_schema.push(path);
+}
+
+/// Namespace of the plan metadata in the `<metadata>` element
+const METADATA_NAMESPACE: &str = "urn:railify:plan";
+
+/// `<metadata>` element with a `<railify:plan>` carrying the fields as attributes
+fn metadata_element(metadata: &PlanMetadata) -> Element {
+    let mut plan = Element::new("railify:plan");
+    plan.assign("xmlns:railify", METADATA_NAMESPACE);
+    for (key, value) in metadata.fields() {
+        plan.assign(key, value);
+    }
+
+    let mut element = Element::new("metadata");
+    element.append(plan);
+    element
+}
 
+/// Saves the elements, drawn in the coordinates of the shapes, on a sheet of
+/// `size` millimetres. `to_svg` takes the shapes onto the sheet, y down.
+pub fn save_and_draw_svg(
+    svg_schema: &mut Vec<Element>,
+    metadata: &PlanMetadata,
+    to_svg: &Transform,
+    size: (f32, f32),
+    path: impl AsRef<Path>,
+) -> anyhow::Result<()> {
+    let mut group = Group::new();
+    if let Some(matrix) = to_svg.to_svg() {
+        group = group.set("transform", matrix);
+    }
 
     for svg_item in svg_schema {
         group = group.add(svg_item.clone());
     }
 
     let document = Document::new()
-        .set("viewBox", (0, 0, WINDOW_HEIGHT, WINDOW_WIDTH))
+        .set("viewBox", (0, 0, size.0, size.1))
+        .set("width", format!("{}mm", size.0))
+        .set("height", format!("{}mm", size.1))
+        .add(Title::new(metadata.title()))
+        .add(metadata_element(metadata))
         .add(group);
 
-    svg::save("plan.svg", &document).unwrap();
+    let path = path.as_ref();
+    svg::save(path, &document).with_context(|| format!("failed to write {}", path.display()))
+}
+
+pub fn convert_image_to_svg(image: &RgbImage) -> Document {
+    let mut svg = Document::new();
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let pixel = image.get_pixel(x, y);
+            let (r, g, b) = (pixel[0], pixel[1], pixel[2]);
+
+            if image_helper::is_white_pixel(r, g, b) {
+                continue;
+            }
+
+            let rect = Rectangle::new()
+                .set("x", x as i32)
+                .set("y", y as i32)
+                .set("width", 1)
+                .set("height", 1)
+                .set("fill", format!("rgb({},{},{})", r, g, b));
+
+            svg = svg.add(rect);
+        }
+    }
+    svg
 }
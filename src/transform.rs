@@ -0,0 +1,234 @@
+use nalgebra::{Matrix3, Vector3};
+
+use crate::{deskew::Deskew, shape_finder::Point};
+
+/// PDF points in a millimetre
+pub const MM_PER_POINT: f32 = 25.4 / 72.;
+
+/// Plane transform in homogeneous coordinates, affine unless it comes from a
+/// projective fit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform(pub Matrix3<f64>);
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform(Matrix3::identity())
+    }
+
+    pub fn translation(dx: f32, dy: f32) -> Self {
+        Transform(Matrix3::new_translation(&[dx as f64, dy as f64].into()))
+    }
+
+    pub fn scale(sx: f32, sy: f32) -> Self {
+        Transform(Matrix3::new_nonuniform_scaling(
+            &[sx as f64, sy as f64].into(),
+        ))
+    }
+
+    /// Counter clockwise rotation by `angle` radians around `center`, clockwise
+    /// on screen when y points down
+    pub fn rotation_about(angle: f32, center: &Point) -> Self {
+        Transform::translation(-center.0, -center.1)
+            .then(&Transform(Matrix3::new_rotation(angle as f64)))
+            .then(&Transform::translation(center.0, center.1))
+    }
+
+    /// Mirrors y within `0..height`, between frames where y points up and down
+    pub fn flip_y(height: f32) -> Self {
+        Transform::scale(1., -1.).then(&Transform::translation(0., height))
+    }
+
+    /// This transform followed by `next`
+    pub fn then(&self, next: &Transform) -> Self {
+        Transform(next.0 * self.0)
+    }
+
+    pub fn inverse(&self) -> Option<Self> {
+        self.0.try_inverse().map(Transform)
+    }
+
+    pub fn apply(&self, point: &Point) -> Point {
+        let (x, y) = self.apply_f64(point.0 as f64, point.1 as f64);
+        Point(x as f32, y as f32)
+    }
+
+    /// [`Transform::apply`] in full precision, for world coordinates
+    pub fn apply_f64(&self, x: f64, y: f64) -> (f64, f64) {
+        let p = self.0 * Vector3::new(x, y, 1.);
+        (p.x / p.z, p.y / p.z)
+    }
+
+    pub fn is_affine(&self) -> bool {
+        self.0[(2, 0)] == 0. && self.0[(2, 1)] == 0. && self.0[(2, 2)] == 1.
+    }
+
+    /// How much lengths grow, the mean over all directions for affine transforms
+    pub fn scale_factor(&self) -> f32 {
+        let m = &self.0;
+        (m[(0, 0)] * m[(1, 1)] - m[(0, 1)] * m[(1, 0)]).abs().sqrt() as f32
+    }
+
+    /// The transform as SVG `matrix(a b c d e f)`, `None` if it isn't affine
+    pub fn to_svg(&self) -> Option<String> {
+        let m = &self.0;
+        self.is_affine().then(|| {
+            format!(
+                "matrix({} {} {} {} {} {})",
+                m[(0, 0)],
+                m[(1, 0)],
+                m[(0, 1)],
+                m[(1, 1)],
+                m[(0, 2)],
+                m[(1, 2)]
+            )
+        })
+    }
+}
+
+/// Transforms between the coordinate frames of a page.
+///
+/// - shapes: page pixels as detected, y up from the bottom left corner of
+///   the straightened page
+/// - pixels: the rendered page or scan as it came in, y down
+/// - points: PDF points, y up from the bottom left corner of the page
+/// - sheet: sheet millimetres, y up like the points
+/// - world: georeferenced coordinates, when control points are known
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PageFrames {
+    pub shapes_to_pixels: Transform,
+    pub pixels_to_points: Transform,
+    pub points_to_sheet: Transform,
+    pub sheet_to_world: Option<Transform>,
+    /// Width and height of the sheet in millimetres
+    pub sheet_size: (f32, f32),
+}
+
+impl PageFrames {
+    /// Frames of a page rendered at `image_size` pixels from `page_size` PDF
+    /// points, straightened by `deskew` before the detection
+    pub fn new(image_size: (u32, u32), page_size: (f32, f32), deskew: &Deskew) -> Self {
+        let (width, height) = (image_size.0 as f32, image_size.1 as f32);
+
+        let shapes_to_pixels = image_to_shapes(deskew.straight_size(image_size).1)
+            .inverse()
+            .unwrap_or_default()
+            .then(&deskew.straight_to_original());
+
+        PageFrames {
+            shapes_to_pixels,
+            pixels_to_points: Transform::scale(page_size.0 / width, page_size.1 / height)
+                .then(&Transform::flip_y(page_size.1)),
+            points_to_sheet: Transform::scale(MM_PER_POINT, MM_PER_POINT),
+            sheet_to_world: None,
+            sheet_size: (page_size.0 * MM_PER_POINT, page_size.1 * MM_PER_POINT),
+        }
+    }
+
+    pub fn shapes_to_points(&self) -> Transform {
+        self.shapes_to_pixels.then(&self.pixels_to_points)
+    }
+
+    pub fn shapes_to_sheet(&self) -> Transform {
+        self.shapes_to_points().then(&self.points_to_sheet)
+    }
+
+    /// `None` unless the page is georeferenced
+    pub fn shapes_to_world(&self) -> Option<Transform> {
+        self.sheet_to_world
+            .map(|world| self.shapes_to_sheet().then(&world))
+    }
+
+    /// Sheet millimetres to SVG user units, the same millimetres with y down
+    pub fn sheet_to_svg(&self) -> Transform {
+        Transform::flip_y(self.sheet_size.1)
+    }
+}
+
+/// Image pixels, y down, to the frame shapes are detected in, y up
+pub fn image_to_shapes(height: u32) -> Transform {
+    Transform::flip_y(height as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Point, b: &Point) {
+        assert!(
+            (a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn shapes_are_pixels_with_y_up() {
+        let frames = PageFrames::new((800, 600), (842., 595.), &Deskew::default());
+
+        assert_close(
+            &frames.shapes_to_pixels.apply(&Point(10., 0.)),
+            &Point(10., 600.),
+        );
+        assert_close(
+            &frames.shapes_to_pixels.apply(&Point(10., 600.)),
+            &Point(10., 0.),
+        );
+    }
+
+    #[test]
+    fn shapes_round_trip_through_pixels() {
+        for deskew in [
+            Deskew::default(),
+            Deskew::new(3_f32.to_radians(), (800, 600)),
+        ] {
+            let frames = PageFrames::new((800, 600), (842., 595.), &deskew);
+            let back = frames.shapes_to_pixels.inverse().unwrap();
+
+            for p in [Point(0., 0.), Point(412.5, 77.25), Point(800., 600.)] {
+                assert_close(&back.apply(&frames.shapes_to_pixels.apply(&p)), &p);
+            }
+        }
+    }
+
+    #[test]
+    fn shapes_of_a_straightened_page_map_onto_the_scan() {
+        let deskew = Deskew::new(3_f32.to_radians(), (800, 600));
+        let frames = PageFrames::new((800, 600), (842., 595.), &deskew);
+        let height = deskew.straight_size((800, 600)).1 as f32;
+
+        for (x, y) in [(0., 0.), (400., 300.), (800., 600.)] {
+            let (sx, sy) = deskew.to_straight(x, y);
+
+            assert_close(
+                &frames.shapes_to_pixels.apply(&Point(sx, height - sy)),
+                &Point(x, y),
+            );
+        }
+    }
+
+    #[test]
+    fn sheet_is_the_page_in_millimetres() {
+        // A4 landscape
+        let frames = PageFrames::new((842, 595), (842., 595.), &Deskew::default());
+
+        assert_close(
+            &frames.shapes_to_sheet().apply(&Point(0., 0.)),
+            &Point(0., 0.),
+        );
+        assert_close(
+            &frames.shapes_to_sheet().apply(&Point(842., 595.)),
+            &Point(297.039, 209.903),
+        );
+        assert_close(
+            &frames.sheet_to_svg().apply(&Point(0., 0.)),
+            &Point(0., frames.sheet_size.1),
+        );
+    }
+}